@@ -0,0 +1,723 @@
+//! Authentication with API tokens or an SSO proxy header, and role-based authorization.
+//!
+//! [`authenticate`] identifies every request and stores its [`Identity`] in the request extensions,
+//! routers then declare the role their routes need with `.route_layer(auth::require(Role::Member))`.
+//! Tokens are managed with the `tokens` subcommand, see [`TokensCommand`].
+
+use std::{
+    fmt,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context as _, Result};
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tower::{Layer, Service};
+
+use crate::Error;
+
+/// What a client may do, each role includes the ones before it.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Play sounds and look around
+    Guest,
+    /// Everything the frontend needs day to day, like managing boards or switching inputs
+    Member,
+    /// Destructive and operational things, like deleting, changing the config or powering off
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Guest => "guest",
+            Role::Member => "member",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "guest" => Ok(Role::Guest),
+            "member" => Ok(Role::Member),
+            "admin" => Ok(Role::Admin),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// JSON file holding the hashed API tokens, managed with the `tokens` subcommand
+    pub tokens_file: PathBuf,
+    /// Role of requests without credentials, unset to reject them
+    pub anonymous_role: Option<Role>,
+    /// Trust the identity an SSO proxy in front of us puts into a header.
+    ///
+    /// Only enable this if the proxy strips the header from client requests,
+    /// and the backend can't be reached without going through it.
+    pub sso: Option<SsoConfig>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            tokens_file: PathBuf::from("tokens.json"),
+            anonymous_role: Some(Role::Guest),
+            sso: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SsoConfig {
+    /// Header containing the user name, e.g. `X-Forwarded-User`
+    pub user_header: String,
+    /// Header containing the role, `default_role` is used if it's missing
+    pub role_header: Option<String>,
+    pub default_role: Role,
+}
+
+/// Who made a request, available as an [`axum::Extension`] behind [`authenticate`].
+#[derive(Debug, Clone)]
+pub struct Identity {
+    /// Token name or SSO user name, `None` for anonymous requests
+    pub name: Option<String>,
+    pub role: Role,
+}
+
+/// Identifies requests, see [`authenticate`].
+#[derive(Debug, Clone)]
+pub struct Auth {
+    config: Arc<AuthConfig>,
+    /// The tokens file as last read, `None` before the first request with a token
+    tokens: Arc<Mutex<Option<CachedTokens>>>,
+}
+
+#[derive(Debug)]
+struct CachedTokens {
+    /// `None` if the file was missing
+    version: Option<FileVersion>,
+    store: Arc<TokenStore>,
+}
+
+/// Modification time and length of the tokens file.
+type FileVersion = (SystemTime, u64);
+
+impl Auth {
+    pub fn new(config: AuthConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            tokens: Arc::default(),
+        }
+    }
+
+    /// Returns the tokens, reading the file again only if it changed,
+    /// e.g. because the `tokens` subcommand added or revoked one.
+    async fn tokens(&self) -> Result<Arc<TokenStore>> {
+        let path = &self.config.tokens_file;
+        let version = match tokio::fs::metadata(path).await {
+            Ok(metadata) => Some((metadata.modified()?, metadata.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        let mut cache = self.tokens.lock().await;
+        match &*cache {
+            Some(cached) if cached.version == version => Ok(cached.store.clone()),
+            _ => {
+                let store = Arc::new(TokenStore::load(path).await?);
+                *cache = Some(CachedTokens {
+                    version,
+                    store: store.clone(),
+                });
+                Ok(store)
+            }
+        }
+    }
+
+    async fn identify(&self, headers: &HeaderMap) -> Result<Option<Identity>, Error> {
+        if let Some(authorization) = headers.get(header::AUTHORIZATION) {
+            let token = authorization
+                .to_str()
+                .ok()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .ok_or_else(|| unauthorized_error("Expected a bearer token"))?;
+
+            let tokens = self.tokens().await.map_err(|e| {
+                tracing::error!("Failed to load tokens: {e:#}");
+                Error::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal_error",
+                    "Internal error",
+                )
+            })?;
+            let token = tokens
+                .find(token)
+                .ok_or_else(|| unauthorized_error("Invalid token"))?;
+
+            return Ok(Some(Identity {
+                name: Some(token.name.clone()),
+                role: token.role,
+            }));
+        }
+
+        if let Some(sso) = &self.config.sso {
+            if let Some(user) = header_str(headers, &sso.user_header) {
+                let role = match sso
+                    .role_header
+                    .as_ref()
+                    .and_then(|h| header_str(headers, h))
+                {
+                    Some(role) => role
+                        .parse::<Role>()
+                        .map_err(|_| unauthorized_error("Unknown role"))?,
+                    None => sso.default_role,
+                };
+                return Ok(Some(Identity {
+                    name: Some(user.to_string()),
+                    role,
+                }));
+            }
+        }
+
+        Ok(self
+            .config
+            .anonymous_role
+            .map(|role| Identity { name: None, role }))
+    }
+}
+
+/// Middleware identifying every request, which has to wrap all routes using [`require`]:
+/// `.layer(middleware::from_fn_with_state(auth, auth::authenticate))`.
+///
+/// Requests with an invalid token are rejected right away instead of being treated as anonymous.
+pub async fn authenticate<B>(
+    State(auth): State<Auth>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    match auth.identify(request.headers()).await {
+        Ok(Some(identity)) => {
+            if let Some(name) = &identity.name {
+                tracing::Span::current().record("user", name.as_str());
+            }
+            request.extensions_mut().insert(identity);
+        }
+        // Routes without a policy, like the health checks, stay reachable
+        Ok(None) => {}
+        Err(e) => return e.into_response(),
+    }
+
+    next.run(request).await
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+}
+
+fn unauthorized_error(message: &str) -> Error {
+    Error::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+}
+
+fn unauthorized(message: &str) -> Response {
+    let mut response = unauthorized_error(message).into_response();
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+/// Checks that `identity` has at least `role`, for handlers deciding by what a request asks for.
+pub fn authorize(identity: Option<&Identity>, role: Role) -> Result<(), Denied> {
+    match identity {
+        Some(identity) if identity.role >= role => Ok(()),
+        // Anonymous clients might get in with credentials
+        None | Some(Identity { name: None, .. }) => Err(Denied::Unauthorized(role)),
+        Some(_) => Err(Denied::Forbidden(role)),
+    }
+}
+
+/// Why [`authorize`] turned a request away, answered like [`require`] does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denied {
+    /// `401`, the client has to authenticate to get `role`
+    Unauthorized(Role),
+    /// `403`, the client's role is below `role`
+    Forbidden(Role),
+}
+
+impl IntoResponse for Denied {
+    fn into_response(self) -> Response {
+        match self {
+            Denied::Unauthorized(role) => unauthorized(&format!("This requires the {role} role")),
+            Denied::Forbidden(role) => Error::new(
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                format!("This requires the {role} role"),
+            )
+            .into_response(),
+        }
+    }
+}
+
+/// Policy layer letting only requests with at least `role` through,
+/// to be added to routers with `route_layer`.
+pub fn require(role: Role) -> RequireRole {
+    RequireRole(role)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RequireRole(Role);
+
+impl<S> Layer<S> for RequireRole {
+    type Service = RequireRoleService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireRoleService {
+            inner,
+            role: self.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequireRoleService<S> {
+    inner: S,
+    role: Role,
+}
+
+impl<S, B> Service<Request<B>> for RequireRoleService<S>
+where
+    S: Service<Request<B>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        match authorize(request.extensions().get::<Identity>(), self.role) {
+            Ok(()) => Box::pin(self.inner.call(request)),
+            Err(denied) => Box::pin(async move { Ok(denied.into_response()) }),
+        }
+    }
+}
+
+/// An API token as stored in the tokens file, which only knows the hash of the token itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredToken {
+    name: String,
+    role: Role,
+    /// Hex SHA-256 of the token
+    hash: String,
+    created: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TokenStore {
+    tokens: Vec<StoredToken>,
+}
+
+impl TokenStore {
+    /// Loads the tokens file, a missing file holds no tokens.
+    async fn load(path: &Path) -> Result<Self> {
+        match tokio::fs::read(path).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("Invalid tokens file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    async fn save(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_vec_pretty(self)?;
+        tokio::fs::write(path, contents)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    fn find(&self, token: &str) -> Option<&StoredToken> {
+        let hash = hash_token(token);
+        self.tokens.iter().find(|stored| stored.hash == hash)
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Manages the API tokens in [`AuthConfig::tokens_file`].
+#[derive(Debug, clap::Subcommand)]
+pub enum TokensCommand {
+    /// Create a token and print it, it can't be shown again later
+    Add {
+        name: String,
+        #[arg(long, value_enum, default_value_t = Role::Member)]
+        role: Role,
+    },
+    /// List the names and roles of all tokens
+    List,
+    /// Delete a token
+    Revoke { name: String },
+}
+
+impl TokensCommand {
+    pub async fn run(self, config: &AuthConfig) -> Result<()> {
+        let path = &config.tokens_file;
+        let mut store = TokenStore::load(path).await?;
+
+        match self {
+            TokensCommand::Add { name, role } => {
+                if store.tokens.iter().any(|stored| stored.name == name) {
+                    bail!("A token named {name} already exists");
+                }
+                let token = generate_token();
+                store.tokens.push(StoredToken {
+                    name,
+                    role,
+                    hash: hash_token(&token),
+                    created: Utc::now(),
+                });
+                store.save(path).await?;
+                println!("{token}");
+            }
+            TokensCommand::List => {
+                for stored in &store.tokens {
+                    println!("{}\t{}\t{}", stored.name, stored.role, stored.created);
+                }
+            }
+            TokensCommand::Revoke { name } => {
+                let count = store.tokens.len();
+                store.tokens.retain(|stored| stored.name != name);
+                if store.tokens.len() == count {
+                    bail!("No token named {name}");
+                }
+                store.save(path).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware, routing::get, Router};
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// An [`Auth`] with the tokens `"member-secret"` and `"admin-secret"` in a temporary file.
+    async fn auth(sso: Option<SsoConfig>) -> (TempDir, Auth) {
+        let dir = TempDir::new().unwrap();
+        let config = AuthConfig {
+            tokens_file: dir.path().join("tokens.json"),
+            anonymous_role: Some(Role::Guest),
+            sso,
+        };
+        let store = TokenStore {
+            tokens: vec![
+                stored("door-display", Role::Member, "member-secret"),
+                stored("ops", Role::Admin, "admin-secret"),
+            ],
+        };
+        store.save(&config.tokens_file).await.unwrap();
+        (dir, Auth::new(config))
+    }
+
+    fn stored(name: &str, role: Role, token: &str) -> StoredToken {
+        StoredToken {
+            name: name.to_string(),
+            role,
+            hash: hash_token(token),
+            created: Utc::now(),
+        }
+    }
+
+    fn sso() -> SsoConfig {
+        SsoConfig {
+            user_header: "x-forwarded-user".to_string(),
+            role_header: Some("x-forwarded-role".to_string()),
+            default_role: Role::Member,
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    header::HeaderName::from_static(name),
+                    value.parse().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn identity(name: Option<&str>, role: Role) -> Identity {
+        Identity {
+            name: name.map(str::to_string),
+            role,
+        }
+    }
+
+    #[test]
+    fn hashes_tokens() {
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let token = generate_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_token());
+    }
+
+    #[tokio::test]
+    async fn identifies_tokens() {
+        let (_dir, auth) = auth(None).await;
+
+        let identity = auth
+            .identify(&headers(&[("authorization", "Bearer member-secret")]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(identity.name.as_deref(), Some("door-display"));
+        assert_eq!(identity.role, Role::Member);
+
+        for authorization in [
+            "Bearer wrong",
+            "Basic bWVtYmVyLXNlY3JldA==",
+            "member-secret",
+        ] {
+            let error = auth
+                .identify(&headers(&[("authorization", authorization)]))
+                .await
+                .unwrap_err();
+            assert_eq!(error.status, StatusCode::UNAUTHORIZED, "{authorization}");
+        }
+    }
+
+    #[tokio::test]
+    async fn identifies_sso_users() {
+        let (_dir, auth) = auth(Some(sso())).await;
+
+        let identity = auth
+            .identify(&headers(&[("x-forwarded-user", "tanja")]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(identity.name.as_deref(), Some("tanja"));
+        assert_eq!(identity.role, Role::Member);
+
+        let identity = auth
+            .identify(&headers(&[
+                ("x-forwarded-user", "tanja"),
+                ("x-forwarded-role", "admin"),
+            ]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(identity.role, Role::Admin);
+
+        let error = auth
+            .identify(&headers(&[
+                ("x-forwarded-user", "tanja"),
+                ("x-forwarded-role", "root"),
+            ]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status, StatusCode::UNAUTHORIZED);
+
+        // Tokens win over the proxy's header
+        let identity = auth
+            .identify(&headers(&[
+                ("authorization", "Bearer admin-secret"),
+                ("x-forwarded-user", "tanja"),
+            ]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(identity.name.as_deref(), Some("ops"));
+    }
+
+    #[tokio::test]
+    async fn identifies_anonymous_requests() {
+        let (_dir, auth) = auth(None).await;
+        let identity = auth.identify(&HeaderMap::new()).await.unwrap().unwrap();
+        assert_eq!(identity.name, None);
+        assert_eq!(identity.role, Role::Guest);
+
+        // The SSO header is ignored unless it's enabled
+        let identity = auth
+            .identify(&headers(&[("x-forwarded-user", "tanja")]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(identity.name, None);
+
+        let auth = Auth::new(AuthConfig {
+            anonymous_role: None,
+            ..(*auth.config).clone()
+        });
+        assert!(auth.identify(&HeaderMap::new()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn picks_up_token_changes() {
+        let (_dir, auth) = auth(None).await;
+        let path = auth.config.tokens_file.clone();
+        let bearer = |token: &str| headers(&[("authorization", &format!("Bearer {token}"))]);
+        assert!(auth.identify(&bearer("member-secret")).await.is_ok());
+
+        let store = TokenStore {
+            tokens: vec![stored("new", Role::Guest, "new-secret")],
+        };
+        store.save(&path).await.unwrap();
+        assert!(auth.identify(&bearer("member-secret")).await.is_err());
+        assert!(auth.identify(&bearer("new-secret")).await.is_ok());
+
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert!(auth.identify(&bearer("new-secret")).await.is_err());
+    }
+
+    #[test]
+    fn authorizes_roles_in_order() {
+        let roles = [Role::Guest, Role::Member, Role::Admin];
+        for (i, &has) in roles.iter().enumerate() {
+            for (j, &needs) in roles.iter().enumerate() {
+                let result = authorize(Some(&identity(Some("user"), has)), needs);
+                if i >= j {
+                    assert_eq!(result, Ok(()), "{has} for {needs}");
+                } else {
+                    assert_eq!(result, Err(Denied::Forbidden(needs)), "{has} for {needs}");
+                }
+            }
+        }
+
+        // Anonymous clients could still log in
+        assert_eq!(
+            authorize(Some(&identity(None, Role::Guest)), Role::Member),
+            Err(Denied::Unauthorized(Role::Member))
+        );
+        assert_eq!(
+            authorize(None, Role::Guest),
+            Err(Denied::Unauthorized(Role::Guest))
+        );
+    }
+
+    #[tokio::test]
+    async fn requires_roles() {
+        let (_dir, auth) = auth(None).await;
+        let app = Router::new()
+            .route("/member", get(|| async {}))
+            .route_layer(require(Role::Member))
+            .layer(middleware::from_fn_with_state(auth, authenticate));
+        let status = |authorization: Option<&'static str>| {
+            let mut request = Request::get("/member");
+            if let Some(authorization) = authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+            let app = app.clone();
+            async move {
+                let request = request.body(hyper::Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap().status()
+            }
+        };
+
+        assert_eq!(status(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Bearer wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Bearer member-secret")).await, StatusCode::OK);
+        assert_eq!(status(Some("Bearer admin-secret")).await, StatusCode::OK);
+
+        let response = Denied::Unauthorized(Role::Member).into_response();
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        let response = Denied::Forbidden(Role::Admin).into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn manages_tokens_in_the_file() {
+        let (_dir, auth) = auth(None).await;
+        let config = &*auth.config;
+        let load = || TokenStore::load(&config.tokens_file);
+
+        TokensCommand::Add {
+            name: "tablet".to_string(),
+            role: Role::Guest,
+        }
+        .run(config)
+        .await
+        .unwrap();
+        let store = load().await.unwrap();
+        let names: Vec<_> = store.tokens.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["door-display", "ops", "tablet"]);
+        assert_eq!(store.tokens[2].role, Role::Guest);
+        assert_eq!(store.tokens[2].hash.len(), 64);
+
+        let duplicate = TokensCommand::Add {
+            name: "tablet".to_string(),
+            role: Role::Admin,
+        };
+        assert!(duplicate.run(config).await.is_err());
+        TokensCommand::List.run(config).await.unwrap();
+
+        TokensCommand::Revoke {
+            name: "door-display".to_string(),
+        }
+        .run(config)
+        .await
+        .unwrap();
+        let store = load().await.unwrap();
+        let names: Vec<_> = store.tokens.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["ops", "tablet"]);
+        let unknown = TokensCommand::Revoke {
+            name: "door-display".to_string(),
+        };
+        assert!(unknown.run(config).await.is_err());
+
+        // A missing file holds no tokens yet
+        tokio::fs::remove_file(&config.tokens_file).await.unwrap();
+        assert!(load().await.unwrap().tokens.is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+//! Layered configuration shared by all backends.
+//!
+//! Later layers override earlier ones:
+//!
+//! 1. the backend's [`Default`] config,
+//! 2. a TOML file, [`Config::DEFAULT_PATH`] unless `--config` is given,
+//! 3. env vars starting with [`Config::ENV_PREFIX`], with `__` separating nested keys,
+//!    e.g. `R3_SOUNDS_PLAYER__COMMAND=mpv`,
+//! 4. command line flags.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use figment::{
+    providers::{Env, Format, Serialized, Toml},
+    Figment,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The configuration of a backend, loaded with [`load`].
+pub trait Config: Serialize + DeserializeOwned + Default {
+    /// Prefix of the env vars overriding the config, e.g. `R3_SOUNDS_`
+    const ENV_PREFIX: &'static str;
+    /// The config file read if no `--config` flag is given, it may be missing
+    const DEFAULT_PATH: &'static str;
+
+    /// Checks the loaded config for values the backend can't run with.
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Command line flags selecting and dumping the config, to be flattened into a backend's CLI.
+#[derive(Debug, Default, clap::Args)]
+pub struct ConfigArgs {
+    /// Path of the TOML config file
+    #[arg(long, short, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Print the effective config as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+/// Loads and validates the config from all layers.
+///
+/// `overrides` are the backend's command line flags,
+/// unset flags have to be skipped when serializing so they don't override anything.
+pub fn load<C: Config>(args: &ConfigArgs, overrides: impl Serialize) -> Result<C> {
+    let path = match &args.config {
+        Some(path) if !path.is_file() => bail!("Config file {} doesn't exist", path.display()),
+        Some(path) => path.clone(),
+        None => PathBuf::from(C::DEFAULT_PATH),
+    };
+
+    let config: C = Figment::from(Serialized::defaults(C::default()))
+        .merge(Toml::file(&path))
+        .merge(Env::prefixed(C::ENV_PREFIX).split("__"))
+        .merge(Serialized::defaults(overrides))
+        .extract()
+        .with_context(|| format!("Invalid config (file {})", path.display()))?;
+
+    config.validate().context("Invalid config")?;
+    Ok(config)
+}
+
+/// Prints the config as TOML, for `--print-config`.
+pub fn print<C: Config>(config: &C) -> Result<()> {
+    print!("{}", toml::to_string_pretty(config)?);
+    Ok(())
+}
+
+#[cfg(test)]
+// The closures `Jail` runs return figment's error, which is large
+#[allow(clippy::result_large_err)]
+mod tests {
+    use figment::Jail;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(default)]
+    struct TestConfig {
+        name: String,
+        port: u16,
+        nested: Nested,
+    }
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    #[serde(default)]
+    struct Nested {
+        level: String,
+        count: u32,
+    }
+
+    impl Default for TestConfig {
+        fn default() -> Self {
+            Self {
+                name: "default".to_string(),
+                port: 8080,
+                nested: Nested::default(),
+            }
+        }
+    }
+
+    impl Config for TestConfig {
+        const ENV_PREFIX: &'static str = "R3_TEST_";
+        const DEFAULT_PATH: &'static str = "test.toml";
+
+        fn validate(&self) -> Result<()> {
+            if self.port == 0 {
+                bail!("port must not be 0");
+            }
+            Ok(())
+        }
+    }
+
+    /// Command line flags the way backends declare them.
+    #[derive(Default, Serialize)]
+    struct Overrides {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        port: Option<u16>,
+    }
+
+    #[test]
+    fn layers_override_each_other() {
+        Jail::expect_with(|jail| {
+            let config: TestConfig = load(&ConfigArgs::default(), Overrides::default()).unwrap();
+            assert_eq!(config.name, "default");
+            assert_eq!(config.port, 8080);
+
+            jail.create_file(
+                "test.toml",
+                r#"
+                name = "file"
+                port = 1
+
+                [nested]
+                level = "file"
+                count = 1
+                "#,
+            )?;
+            let config: TestConfig = load(&ConfigArgs::default(), Overrides::default()).unwrap();
+            assert_eq!(config.name, "file");
+            assert_eq!(config.port, 1);
+            assert_eq!(config.nested.level, "file");
+
+            jail.set_env("R3_TEST_PORT", 2);
+            jail.set_env("R3_TEST_NESTED__LEVEL", "env");
+            let config: TestConfig = load(&ConfigArgs::default(), Overrides::default()).unwrap();
+            assert_eq!(config.name, "file");
+            assert_eq!(config.port, 2);
+            assert_eq!(config.nested.level, "env");
+            assert_eq!(config.nested.count, 1);
+
+            let config: TestConfig =
+                load(&ConfigArgs::default(), Overrides { port: Some(3) }).unwrap();
+            assert_eq!(config.port, 3);
+            assert_eq!(config.nested.level, "env");
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reads_the_given_config_file() {
+        Jail::expect_with(|jail| {
+            jail.create_file("test.toml", "name = \"default path\"")?;
+            jail.create_file("other.toml", "name = \"other\"")?;
+            let args = ConfigArgs {
+                config: Some("other.toml".into()),
+                ..Default::default()
+            };
+            let config: TestConfig = load(&args, Overrides::default()).unwrap();
+            assert_eq!(config.name, "other");
+
+            // Unlike the default path, a missing file given on the command line is a mistake
+            let args = ConfigArgs {
+                config: Some("missing.toml".into()),
+                ..Default::default()
+            };
+            assert!(load::<TestConfig>(&args, Overrides::default()).is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        Jail::expect_with(|jail| {
+            jail.set_env("R3_TEST_PORT", 0);
+            let error =
+                load::<TestConfig>(&ConfigArgs::default(), Overrides::default()).unwrap_err();
+            assert!(
+                format!("{error:#}").contains("port must not be 0"),
+                "{error:#}"
+            );
+
+            jail.set_env("R3_TEST_PORT", "not a port");
+            assert!(load::<TestConfig>(&ConfigArgs::default(), Overrides::default()).is_err());
+
+            jail.set_env("R3_TEST_PORT", 1);
+            jail.create_file("test.toml", "port = [")?;
+            assert!(load::<TestConfig>(&ConfigArgs::default(), Overrides::default()).is_err());
+            Ok(())
+        });
+    }
+}
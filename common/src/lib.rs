@@ -0,0 +1,19 @@
+//! Server infrastructure shared by the Realraum backends.
+//!
+//! A backend loads its [`config`], calls [`logging::init`], builds its router around
+//! [`routes::api`], [`routes::health`] and [`routes::static_files`], wraps it with
+//! [`logging::trace_requests`], and runs it with [`server::serve`].
+//! Metrics recorded anywhere are exposed through [`metrics::routes`],
+//! API documentation through [`openapi::routes`].
+
+pub mod auth;
+pub mod config;
+pub mod error;
+pub mod logging;
+pub mod metrics;
+pub mod openapi;
+pub mod response;
+pub mod routes;
+pub mod server;
+
+pub use error::Error;
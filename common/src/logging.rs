@@ -0,0 +1,167 @@
+//! Structured logging with [`tracing`], a span per request and a log level adjustable at runtime.
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{HeaderName, Request},
+    routing::get,
+    Json, Router,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::Level;
+use tracing_subscriber::{
+    layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Layer, Registry,
+};
+
+use crate::{response, Error};
+
+const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Filter directives like `info` or `info,realraum_backend_sounds=debug`,
+    /// overridden by the `RUST_LOG` env var
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Plain,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    Plain,
+    /// One JSON object per line, for journald and log shippers
+    Json,
+}
+
+/// Changes the log level of the running server, see [`routes`].
+#[derive(Clone)]
+pub struct LogHandle(reload::Handle<EnvFilter, Registry>);
+
+impl LogHandle {
+    pub fn level(&self) -> Result<String> {
+        Ok(self.0.with_current(|filter| filter.to_string())?)
+    }
+
+    pub fn set_level(&self, directives: &str) -> Result<()> {
+        let filter = EnvFilter::try_new(directives)?;
+        self.0.reload(filter)?;
+        Ok(())
+    }
+}
+
+/// Sets up logging to stderr as configured, has to be called once before anything is logged.
+pub fn init(config: &LogConfig) -> Result<LogHandle> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(&config.level).context("Invalid log.level")?,
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let format = match config.format {
+        LogFormat::Plain => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(format)
+        .try_init()?;
+
+    Ok(LogHandle(handle))
+}
+
+/// Wraps `router` so every request gets an `x-request-id`, taken from the client or generated,
+/// which is sent back in the response and recorded in a span covering everything logged
+/// while handling the request.
+pub fn trace_requests<S>(router: Router<S>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let trace = TraceLayer::new_for_http()
+        .make_span_with(|request: &Request<_>| {
+            let request_id = request
+                .extensions()
+                .get::<RequestId>()
+                .and_then(|id| id.header_value().to_str().ok())
+                .unwrap_or_default();
+            tracing::info_span!(
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                request_id,
+                user = tracing::field::Empty,
+            )
+        })
+        .on_response(DefaultOnResponse::new().level(Level::INFO));
+
+    // Layers wrap everything added before, so the request id has to be set last
+    router
+        .layer(PropagateRequestIdLayer::new(REQUEST_ID_HEADER))
+        .layer(trace)
+        .layer(SetRequestIdLayer::new(REQUEST_ID_HEADER, MakeRequestUuid))
+}
+
+#[derive(Debug, Deserialize)]
+struct SetLevel {
+    level: String,
+}
+
+/// Router reading and changing the log level on `/log-level`.
+///
+/// `PUT` takes `{ "level": "debug" }` with the same directives as [`LogConfig::level`].
+/// The change lasts until the server restarts.
+pub fn routes<S>(handle: LogHandle) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/log-level", get(get_level).put(set_level))
+        .with_state(handle)
+}
+
+async fn get_level(State(handle): State<LogHandle>) -> Result<Json<Value>, Error> {
+    let level = handle.level().map_err(internal)?;
+    Ok(response::ok(json!({ "level": level })))
+}
+
+async fn set_level(
+    State(handle): State<LogHandle>,
+    Json(body): Json<SetLevel>,
+) -> Result<Json<Value>, Error> {
+    handle
+        .set_level(&body.level)
+        .map_err(|e| Error::new(StatusCode::BAD_REQUEST, "invalid_log_level", e.to_string()))?;
+    tracing::warn!(level = %body.level, "Log level changed");
+
+    Ok(response::ok(json!({ "level": body.level })))
+}
+
+fn internal(e: anyhow::Error) -> Error {
+    tracing::error!("{e:#}");
+    Error::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "internal_error",
+        "Internal error",
+    )
+}
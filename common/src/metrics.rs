@@ -0,0 +1,60 @@
+//! Prometheus metrics on `/metrics`.
+//!
+//! Backends record their own metrics with the [`::metrics`] macros,
+//! request counts and latencies are recorded by [`track_requests`].
+
+use std::time::Instant;
+
+use anyhow::Result;
+use axum::{
+    extract::MatchedPath, http::Request, middleware::Next, response::Response, routing::get, Router,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+/// Buckets of all `*_seconds` histograms, from a fast local request to a slow network timeout.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Installs the global metrics recorder, has to be called once before serving.
+pub fn install() -> Result<PrometheusHandle> {
+    Ok(PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), LATENCY_BUCKETS)?
+        .install_recorder()?)
+}
+
+/// Router serving the metrics recorded through `handle` on `/metrics`.
+pub fn routes<S>(handle: PrometheusHandle) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new().route(
+        "/metrics",
+        get(move || {
+            let handle = handle.clone();
+            async move { handle.render() }
+        }),
+    )
+}
+
+/// Middleware counting requests and their latency per route, to be added with `route_layer`
+/// so the route template instead of the full path ends up in the labels.
+pub async fn track_requests<B>(request: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    let labels = [("method", method), ("route", route), ("status", status)];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels)
+        .record(start.elapsed().as_secs_f64());
+
+    response
+}
@@ -0,0 +1,228 @@
+//! OpenAPI documents of the typed API versions, with an interactive API reference.
+//!
+//! Backends describe their API with `#[derive(utoipa::OpenApi)]`, adding [`BearerAuth`]
+//! as modifier, and merge [`routes`] into the API version it documents.
+//! They build that version as [`Routes`], so tests can check that the spec has every route.
+
+use std::{collections::BTreeSet, convert::Infallible};
+
+use axum::{
+    body::Body,
+    http::{header, Request},
+    response::{Html, IntoResponse},
+    routing::{get, MethodRouter, Route},
+    Json, Router,
+};
+use tower::{Layer, Service};
+use utoipa::{
+    openapi::{
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        OpenApi,
+    },
+    Modify,
+};
+
+/// Name of the security scheme added by [`BearerAuth`].
+pub const SECURITY_SCHEME: &str = "token";
+
+/// [Swagger UI](https://github.com/swagger-api/swagger-ui) rendering the `openapi.json` next to it,
+/// with its release bundle vendored so the reference works without internet access.
+const REFERENCE_PAGE: &str = r##"<!doctype html>
+<html>
+  <head>
+    <title>API Reference</title>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <link rel="stylesheet" href="docs/swagger-ui.css">
+  </head>
+  <body>
+    <div id="api-reference"></div>
+    <script src="docs/swagger-ui-bundle.js"></script>
+    <script>
+      SwaggerUIBundle({ url: "openapi.json", dom_id: "#api-reference" });
+    </script>
+  </body>
+</html>
+"##;
+
+const SWAGGER_UI_JS: &str = include_str!("../assets/swagger-ui/swagger-ui-bundle.js");
+const SWAGGER_UI_CSS: &str = include_str!("../assets/swagger-ui/swagger-ui.css");
+
+/// The paths [`routes`] serves, which aren't part of the spec itself.
+pub const REFERENCE_PATHS: [&str; 4] = [
+    "/openapi.json",
+    "/docs",
+    "/docs/swagger-ui-bundle.js",
+    "/docs/swagger-ui.css",
+];
+
+/// Serves `spec` on `/openapi.json` and an API reference for it on `/docs`.
+pub fn routes<S>(spec: OpenApi) -> Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let asset = |content_type: &'static str, content: &'static str| {
+        get(move || async move { ([(header::CONTENT_TYPE, content_type)], content) })
+    };
+
+    Routes::new()
+        .route("/openapi.json", get(move || async move { Json(spec) }))
+        .route("/docs", get(|| async { Html(REFERENCE_PAGE) }))
+        .route(
+            "/docs/swagger-ui-bundle.js",
+            asset("text/javascript; charset=utf-8", SWAGGER_UI_JS),
+        )
+        .route(
+            "/docs/swagger-ui.css",
+            asset("text/css; charset=utf-8", SWAGGER_UI_CSS),
+        )
+}
+
+/// Documents the `Authorization: Bearer <token>` header of [`crate::auth`],
+/// as security scheme named [`SECURITY_SCHEME`].
+pub struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            SECURITY_SCHEME,
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// A [`Router`] remembering the templates of its routes in OpenAPI syntax, like `/boards/{name}`,
+/// for tests asserting that a spec documents every route. axum doesn't expose its routes.
+///
+/// Build it like a [`Router`] and convert it with `.into()` when done.
+pub struct Routes<S = ()> {
+    router: Router<S>,
+    paths: BTreeSet<String>,
+}
+
+impl<S> Default for Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self {
+            router: Router::new(),
+            paths: BTreeSet::new(),
+        }
+    }
+
+    /// See [`Router::route`].
+    pub fn route(mut self, path: &str, method_router: MethodRouter<S>) -> Self {
+        self.paths.insert(openapi_path(path));
+        self.router = self.router.route(path, method_router);
+        self
+    }
+
+    /// See [`Router::merge`].
+    pub fn merge(mut self, other: Routes<S>) -> Self {
+        self.paths.extend(other.paths);
+        self.router = self.router.merge(other.router);
+        self
+    }
+
+    /// See [`Router::nest`].
+    pub fn nest(mut self, path: &str, other: Routes<S>) -> Self {
+        let prefix = openapi_path(path);
+        self.paths
+            .extend(other.paths.iter().map(|nested| format!("{prefix}{nested}")));
+        self.router = self.router.nest(path, other.router);
+        self
+    }
+
+    /// See [`Router::route_layer`].
+    pub fn route_layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request<Body>> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        self.router = self.router.route_layer(layer);
+        self
+    }
+
+    /// The templates of all routes.
+    pub fn paths(&self) -> &BTreeSet<String> {
+        &self.paths
+    }
+}
+
+impl<S> From<Routes<S>> for Router<S> {
+    fn from(routes: Routes<S>) -> Self {
+        routes.router
+    }
+}
+
+/// Turns axum's `:param` and `*param` segments into OpenAPI's `{param}`.
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix([':', '*']) {
+            Some(param) => format!("{{{param}}}"),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::post};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_the_reference_without_a_cdn() {
+        let router: Router = Router::new().nest("/v2", routes(OpenApi::default()).into());
+        for (path, content_type) in [
+            ("/v2/docs", "text/html; charset=utf-8"),
+            (
+                "/v2/docs/swagger-ui-bundle.js",
+                "text/javascript; charset=utf-8",
+            ),
+            ("/v2/docs/swagger-ui.css", "text/css; charset=utf-8"),
+            ("/v2/openapi.json", "application/json"),
+        ] {
+            let request = Request::get(path).body(Body::empty()).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+            assert_eq!(response.headers()[header::CONTENT_TYPE], content_type);
+        }
+        assert!(!REFERENCE_PAGE.contains("https://"));
+    }
+
+    #[test]
+    fn lists_route_templates() {
+        let routes: Routes = Routes::new().route("/sounds", get(|| async {})).nest(
+            "/v2",
+            Routes::new()
+                .route("/sounds/:id/play", post(|| async {}))
+                .merge(Routes::new().route("/play/*path", post(|| async {}))),
+        );
+
+        assert_eq!(
+            routes.paths(),
+            &BTreeSet::from([
+                "/sounds".to_string(),
+                "/v2/play/{path}".to_string(),
+                "/v2/sounds/{id}/play".to_string(),
+            ])
+        );
+    }
+}
@@ -0,0 +1,33 @@
+//! The JSON envelope all API responses share.
+//!
+//! Successful responses look like `{ "status": "ok", ... }`,
+//! errors like `{ "status": "error", "code": ..., "message": ... }`, see [`crate::Error`].
+
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+/// The `status` field of every response, for the typed response structs of newer API versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Error,
+}
+
+/// Wraps the fields of a successful response into the `{ "status": "ok", ... }` envelope.
+///
+/// `fields` should be a JSON object, anything else ends up in a `data` field.
+pub fn ok(fields: Value) -> Json<Value> {
+    let mut body = serde_json::Map::new();
+    body.insert("status".to_string(), json!("ok"));
+    match fields {
+        Value::Object(fields) => body.extend(fields),
+        Value::Null => {}
+        data => {
+            body.insert("data".to_string(), data);
+        }
+    }
+    Json(Value::Object(body))
+}
@@ -0,0 +1,135 @@
+use std::{fmt::Display, future::Future, path::Path};
+
+use axum::{
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{any, get},
+    Json, Router,
+};
+use hyper::{StatusCode, Uri};
+use serde_json::{json, Value};
+use tower_http::services::{ServeDir, ServeFile};
+
+use crate::{response, Error};
+
+/// The `/api` router a backend nests its API versions into,
+/// greeting clients on `/` and answering unknown routes with a JSON 404.
+pub fn api<S>(greeting: &'static str, version: &'static str) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route(
+            "/",
+            get(move || async move {
+                response::ok(json!({
+                    "message": greeting,
+                    "server_version": version,
+                }))
+            }),
+        )
+        .route("/*any", any(fallback))
+}
+
+pub async fn fallback(_: Uri) -> Error {
+    Error::no_such_route()
+}
+
+/// Middleware for routes kept only for old clients, e.g. the `GET` actions of `/api/v1`,
+/// marking their responses with a `Deprecation` header.
+pub async fn deprecated<B>(request: Request<B>, next: Next<B>) -> Response {
+    tracing::info!(uri = %request.uri(), "Deprecated route used");
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_static("true"),
+    );
+    response
+}
+
+/// The outcome of one readiness check, e.g. whether the database is accessible.
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub result: Result<(), String>,
+}
+
+impl Check {
+    pub fn new<E: Display>(name: impl Into<String>, result: Result<(), E>) -> Self {
+        Self {
+            name: name.into(),
+            result: result.map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Liveness probe on `/healthz`, answering as long as the server runs at all,
+/// and readiness probe on `/readyz`, answering `503` unless all checks returned by `ready` pass.
+pub fn health<S, F, Fut>(ready: F) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    F: Fn() -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Vec<Check>> + Send,
+{
+    Router::new().route("/healthz", get(healthz)).route(
+        "/readyz",
+        get(move || {
+            let ready = ready.clone();
+            async move { readyz(ready().await) }
+        }),
+    )
+}
+
+async fn healthz() -> Json<Value> {
+    response::ok(json!({ "message": "Alive" }))
+}
+
+fn readyz(checks: Vec<Check>) -> Response {
+    let ready = checks.iter().all(|check| check.result.is_ok());
+    let results: serde_json::Map<_, _> = checks
+        .into_iter()
+        .map(|check| {
+            let result = check.result.err().unwrap_or_else(|| "ok".to_string());
+            (check.name, json!(result))
+        })
+        .collect();
+
+    if ready {
+        response::ok(json!({ "message": "Ready", "checks": results })).into_response()
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "status": "error",
+                "code": "not_ready",
+                "message": "Not ready",
+                "checks": results,
+            })),
+        )
+            .into_response()
+    }
+}
+
+/// Serves the frontend from `dir`, falling back to its `index.html` for client-side routes.
+pub fn static_files(dir: &Path) -> ServeDir<tower_http::set_status::SetStatus<ServeFile>> {
+    ServeDir::new(dir).not_found_service(ServeFile::new(dir.join("index.html")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_unknown_routes() {
+        let response = fallback(Uri::from_static("/api/v1/nope"))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "error");
+        assert_eq!(body["code"], "no_such_route");
+    }
+}
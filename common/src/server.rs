@@ -0,0 +1,38 @@
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use axum::Router;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Runs `app` on `addr` until the process receives `SIGINT` or `SIGTERM`,
+/// letting in-flight requests finish before returning.
+///
+/// Handlers can extract the client's address with `ConnectInfo<SocketAddr>`.
+pub async fn serve(app: Router, addr: SocketAddr) -> Result<()> {
+    tracing::info!("Starting server on http://{addr}");
+
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    tracing::info!("Server stopped");
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            tracing::warn!("Failed to listen for SIGTERM: {e}");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+    tracing::info!("Shutting down");
+}
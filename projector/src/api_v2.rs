@@ -0,0 +1,304 @@
+//! The v2 API on `/api/v2`, with typed responses and an OpenAPI document on `/api/v2/openapi.json`.
+//!
+//! Commands are sent with `POST /api/v2/{group}/{name}`, one operation per command,
+//! so generated clients get a method for each of them. `GET /api/v2/status` tells
+//! what we know about the projector.
+//!
+//! These routes control the default projector, `/api/v2/projectors/{projector}/...`
+//! the one of the given name; `GET /api/v2/projectors` lists them.
+
+use axum::{
+    extract::State,
+    routing::{get, post, MethodRouter},
+    Json,
+};
+use realraum_backend_common::{
+    error::ErrorBody,
+    openapi::{self, BearerAuth, Routes, SECURITY_SCHEME},
+    response::Status,
+    Error,
+};
+use serde::Serialize;
+use utoipa::{
+    openapi::{
+        path::{HttpMethod, OperationBuilder, ParameterBuilder, ParameterIn},
+        security::SecurityRequirement,
+        ContentBuilder, ObjectBuilder, OpenApi as OpenApiDoc, Ref, Required, ResponseBuilder, Type,
+    },
+    OpenApi, ToSchema,
+};
+
+use crate::{
+    driver::Delivery,
+    projectors::{ProjectorInfo, Projectors, SelectedProjector},
+    state::ProjectorState,
+    AppState, ProjectorCommand,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Realraum Projector-Remote API"),
+    paths(list_projectors, status),
+    components(schemas(CommandSent, ErrorBody)),
+    modifiers(&BearerAuth),
+)]
+struct ApiDoc;
+
+/// A command the projector received.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct CommandSent {
+    status: Status,
+    /// `{group}/{name}` of the command, e.g. `power/on`
+    command: String,
+    /// The bytes sent to the projector
+    bytes: Vec<u8>,
+    /// Whether the projector reported the command back,
+    /// `false` doesn't mean it failed as not all commands are reported
+    confirmed: bool,
+}
+
+/// The state of the projector.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct StatusResponse {
+    status: Status,
+    #[serde(flatten)]
+    state: ProjectorState,
+}
+
+/// All projectors.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct ProjectorList {
+    status: Status,
+    projectors: Vec<ProjectorInfo>,
+}
+
+/// Routes of the v2 API, including its OpenAPI document.
+pub(crate) fn routes() -> Routes<AppState> {
+    Routes::new()
+        .route("/projectors", get(list_projectors))
+        .merge(projector_routes())
+        .nest("/projectors/:projector", projector_routes())
+        .merge(openapi::routes(spec()))
+}
+
+/// The routes controlling one projector.
+fn projector_routes() -> Routes<AppState> {
+    Routes::new()
+        .route("/status", get(status))
+        .merge(crate::commands(post_command))
+}
+
+/// List the projectors
+#[utoipa::path(
+    get,
+    path = "/api/v2/projectors",
+    tag = "projectors",
+    responses(
+        (status = 200, body = ProjectorList),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security((), ("token" = [])),
+)]
+async fn list_projectors(State(projectors): State<Projectors>) -> Json<ProjectorList> {
+    Json(ProjectorList {
+        status: Status::Ok,
+        projectors: projectors.list(),
+    })
+}
+
+/// Get what we know about the projector, each value says where it came from
+#[utoipa::path(
+    get,
+    path = "/api/v2/status",
+    tag = "status",
+    responses(
+        (status = 200, body = StatusResponse),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security((), ("token" = [])),
+)]
+async fn status(SelectedProjector(projector): SelectedProjector) -> Json<StatusResponse> {
+    Json(StatusResponse {
+        status: Status::Ok,
+        state: projector.state(),
+    })
+}
+
+/// Route sending `command` to the projector on `POST`.
+fn post_command(command: ProjectorCommand) -> MethodRouter<AppState> {
+    post(move |SelectedProjector(projector)| async move {
+        let sent = crate::send(&projector, command.into()).await?;
+        Ok::<_, Error>(Json(CommandSent {
+            status: Status::Ok,
+            command: command.to_string(),
+            bytes: sent.bytes,
+            confirmed: sent.delivery == Delivery::Confirmed,
+        }))
+    })
+}
+
+/// The OpenAPI document, with an operation for each of [`ProjectorCommand::ALL`],
+/// and all operations again for each projector.
+fn spec() -> OpenApiDoc {
+    let mut spec = ApiDoc::openapi();
+    let json = |schema: &str| {
+        ContentBuilder::new()
+            .schema(Some(Ref::from_schema_name(schema)))
+            .build()
+    };
+
+    for command in ProjectorCommand::ALL {
+        let (group, name) = (command.group().name(), command.name());
+        let role = crate::required_role(command);
+        let operation = OperationBuilder::new()
+            .operation_id(Some(format!("{group}_{name}")))
+            .tag(group)
+            .summary(Some(format!("Send {group}/{name} to the projector")))
+            .description(Some(format!("Requires the {role} role.")))
+            .response(
+                "200",
+                ResponseBuilder::new()
+                    .description("The projector received the command")
+                    .content("application/json", json("CommandSent")),
+            )
+            .response(
+                "502",
+                ResponseBuilder::new()
+                    .description("Projector unreachable")
+                    .content("application/json", json("ErrorBody")),
+            )
+            .response(
+                "501",
+                ResponseBuilder::new()
+                    .description("The projector's protocol has no such command")
+                    .content("application/json", json("ErrorBody")),
+            )
+            .response(
+                "default",
+                ResponseBuilder::new()
+                    .description("Error")
+                    .content("application/json", json("ErrorBody")),
+            )
+            .security(SecurityRequirement::new::<_, _, String>(
+                SECURITY_SCHEME,
+                [],
+            ));
+
+        spec.paths.add_path_operation(
+            format!("/api/v2/{group}/{name}"),
+            vec![HttpMethod::Post],
+            operation.build(),
+        );
+    }
+
+    let projector = ParameterBuilder::new()
+        .name("projector")
+        .parameter_in(ParameterIn::Path)
+        .required(Required::True)
+        .description(Some("Name of the projector"))
+        .schema(Some(ObjectBuilder::new().schema_type(Type::String)))
+        .build();
+    let aliases: Vec<_> = spec
+        .paths
+        .paths
+        .iter()
+        .filter(|(path, _)| *path != "/api/v2/projectors")
+        .map(|(path, item)| (path.clone(), item.clone()))
+        .collect();
+    for (path, mut item) in aliases {
+        for operation in [&mut item.get, &mut item.post].into_iter().flatten() {
+            operation.operation_id = operation
+                .operation_id
+                .as_ref()
+                .map(|id| format!("projector_{id}"));
+            operation
+                .parameters
+                .get_or_insert_with(Vec::new)
+                .push(projector.clone());
+        }
+        let path = path.replacen("/api/v2", "/api/v2/projectors/{projector}", 1);
+        spec.paths.paths.insert(path, item);
+    }
+
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, sync::Arc};
+
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+        Router,
+    };
+    use realraum_backend_common::auth::{Identity, Role};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::config::Config;
+
+    fn app() -> Router {
+        let mut config = Config::default();
+        // Nothing listens there, so commands fail right away instead of timing out
+        config.projectors[0].set_addr("127.0.0.1:1".parse().unwrap());
+        Router::new()
+            .nest("/api/v2", routes().into())
+            .with_state(AppState {
+                projectors: Projectors::spawn(&config.projectors),
+                config: Arc::new(config),
+                runs: Default::default(),
+            })
+    }
+
+    #[test]
+    fn documents_all_routes() {
+        let routes = routes().paths().clone();
+        let documented: BTreeSet<_> = spec()
+            .paths
+            .paths
+            .into_keys()
+            .map(|path| path.trim_start_matches("/api/v2").to_string())
+            .chain(openapi::REFERENCE_PATHS.map(str::to_string))
+            .collect();
+
+        assert_eq!(routes, documented);
+    }
+
+    #[tokio::test]
+    async fn spec_matches_route_methods() {
+        let app = app();
+
+        for (path, item) in &spec().paths.paths {
+            let path = path.replace("{projector}", "beamer");
+            for method in [Method::GET, Method::POST, Method::PUT, Method::DELETE] {
+                let mut request = Request::builder()
+                    .method(method.clone())
+                    .uri(&path)
+                    .body(Body::empty())
+                    .unwrap();
+                request.extensions_mut().insert(Identity {
+                    name: Some("test".to_string()),
+                    role: Role::Admin,
+                });
+                let status = app.clone().oneshot(request).await.unwrap().status();
+
+                let expected = match (&method, &item.get, &item.post) {
+                    (&Method::GET, Some(_), _) => StatusCode::OK,
+                    (&Method::POST, _, Some(_)) => StatusCode::BAD_GATEWAY,
+                    _ => StatusCode::METHOD_NOT_ALLOWED,
+                };
+                assert_eq!(status, expected, "{method} {path}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_projectors() {
+        let request = Request::get("/api/v2/projectors/nope/status")
+            .body(Body::empty())
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,47 @@
+//! Simulates our projector for working on the backend away from the space,
+//! point the backend at it with `--projector-addr`.
+
+use std::{net::SocketAddr, time::Duration};
+
+use anyhow::Result;
+use clap::Parser;
+use realraum_backend_common::logging::{self, LogConfig};
+use realraum_backend_projector::simulator::{Faults, Simulator};
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Address to listen on, the real projector's control port is 41794
+    #[arg(long, default_value = "127.0.0.1:41794")]
+    addr: SocketAddr,
+    /// IP ID clients must register with, any if unset
+    #[arg(long)]
+    ip_id: Option<u8>,
+    /// Hang up after this many packets from a client
+    #[arg(long)]
+    drop_after: Option<u32>,
+    /// Wait this long before every packet we send
+    #[arg(long, default_value_t = 0)]
+    delay_ms: u64,
+    /// Garble every nth packet we send
+    #[arg(long)]
+    garble_every: Option<u32>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let _log_handle = logging::init(&LogConfig::default())?;
+
+    let simulator = Simulator::bind(cli.addr, cli.ip_id).await?;
+    simulator.set_faults(Faults {
+        drop_after: cli.drop_after,
+        delay: Duration::from_millis(cli.delay_ms),
+        garble_every: cli.garble_every,
+    });
+    tracing::info!("Simulating the projector on {}", simulator.local_addr());
+
+    tokio::signal::ctrl_c().await?;
+    tracing::info!("Final state: {:?}", simulator.state());
+    Ok(())
+}
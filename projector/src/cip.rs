@@ -0,0 +1,351 @@
+//! The Crestron IP (CIP) protocol the projector speaks on its control port.
+//!
+//! Every packet starts with a type byte and the payload length as big endian `u16`.
+//! After connecting, the projector asks us to register with an IP ID, and both sides exchange
+//! heartbeats from then on. Buttons are digital joins, which we press by sending them high and
+//! release by sending them low; the projector reports changed joins with the same packets.
+//!
+//! Joins are numbered from 1 like in Crestron's tools, the wire format counts from 0.
+
+use std::fmt::{self, Display};
+
+const REGISTER: u8 = 0x01;
+const REGISTRATION_RESULT: u8 = 0x02;
+const DISCONNECT: u8 = 0x03;
+const DATA: u8 = 0x05;
+const HEARTBEAT: u8 = 0x0d;
+const HEARTBEAT_RESPONSE: u8 = 0x0e;
+const REGISTRATION_REQUEST: u8 = 0x0f;
+const SERIAL: u8 = 0x12;
+
+// Types of the payload of DATA packets
+const DIGITAL: u8 = 0x00;
+const UPDATE: u8 = 0x03;
+const ANALOG: u8 = 0x14;
+const DIGITAL_REPEAT: u8 = 0x27;
+
+// Kinds of UPDATE payloads
+const UPDATE_REQUEST: u8 = 0x00;
+const END_OF_QUERY: u8 = 0x16;
+
+/// Marks digital joins as low in the high byte of the join number.
+const LOW: u16 = 0x8000;
+
+/// A packet of either side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    /// The projector asks us to register
+    RegistrationRequest,
+    /// We register with our IP ID
+    Register {
+        ip_id: u8,
+    },
+    /// The projector accepted our IP ID
+    RegistrationAccepted,
+    /// The projector doesn't know our IP ID
+    RegistrationRejected,
+    /// Either side ends the session
+    Disconnect,
+    Heartbeat,
+    HeartbeatResponse,
+    Data(Data),
+    /// Text on a serial join
+    Serial {
+        join: u16,
+        text: String,
+    },
+    /// Packets we don't know, kept so they can be logged
+    Unknown {
+        kind: u8,
+        payload: Vec<u8>,
+    },
+}
+
+/// Payload of a data packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Data {
+    /// A button, `high` while pressed
+    Digital {
+        join: u16,
+        high: bool,
+    },
+    /// A level like the volume
+    Analog {
+        join: u16,
+        value: u16,
+    },
+    /// Asks the projector to report all its joins
+    UpdateRequest,
+    /// The projector reported all its joins
+    EndOfQuery,
+    Unknown {
+        kind: u8,
+        payload: Vec<u8>,
+    },
+}
+
+impl Packet {
+    /// Encodes the packet for the wire.
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, payload) = match self {
+            Packet::RegistrationRequest => (REGISTRATION_REQUEST, vec![0x02]),
+            Packet::Register { ip_id } => (
+                REGISTER,
+                vec![
+                    0x00, 0x00, 0x00, 0x00, 0x00, *ip_id, 0x40, 0xff, 0xff, 0xf1, 0x01,
+                ],
+            ),
+            Packet::RegistrationAccepted => (REGISTRATION_RESULT, vec![0x00, 0x00, 0x00, 0x03]),
+            Packet::RegistrationRejected => (REGISTRATION_RESULT, vec![0xff, 0xff, 0x02]),
+            Packet::Disconnect => (DISCONNECT, vec![]),
+            Packet::Heartbeat => (HEARTBEAT, vec![0x00, 0x00]),
+            Packet::HeartbeatResponse => (HEARTBEAT_RESPONSE, vec![0x00, 0x00]),
+            Packet::Data(data) => (DATA, data.encode()),
+            Packet::Serial { join, text } => {
+                let mut payload = vec![0x00, 0x00, 0x00, 0x00, 0x34];
+                payload.extend_from_slice(&join.wrapping_sub(1).to_be_bytes());
+                payload.push(0x03);
+                payload.extend_from_slice(text.as_bytes());
+                payload[3] = (payload.len() - 4) as u8;
+                (SERIAL, payload)
+            }
+            Packet::Unknown { kind, payload } => (*kind, payload.clone()),
+        };
+
+        let mut bytes = Vec::with_capacity(3 + payload.len());
+        bytes.push(kind);
+        bytes.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    /// Decodes the first packet of `bytes`, returning it and its length,
+    /// or `None` if `bytes` doesn't hold a whole packet yet.
+    pub fn decode(bytes: &[u8]) -> Option<(Packet, usize)> {
+        let [kind, len_high, len_low, ..] = *bytes else {
+            return None;
+        };
+        let len = 3 + u16::from_be_bytes([len_high, len_low]) as usize;
+        let payload = bytes.get(3..len)?;
+
+        let packet = match (kind, payload) {
+            (REGISTRATION_REQUEST, _) => Packet::RegistrationRequest,
+            (REGISTER, [.., ip_id, 0x40, 0xff, 0xff, 0xf1, 0x01]) => {
+                Packet::Register { ip_id: *ip_id }
+            }
+            (REGISTRATION_RESULT, [0xff, 0xff, 0x02]) => Packet::RegistrationRejected,
+            (REGISTRATION_RESULT, [_, _, _, _]) => Packet::RegistrationAccepted,
+            (DISCONNECT, _) => Packet::Disconnect,
+            (HEARTBEAT, _) => Packet::Heartbeat,
+            (HEARTBEAT_RESPONSE, _) => Packet::HeartbeatResponse,
+            (DATA, _) => Packet::Data(Data::decode(payload)),
+            (SERIAL, [_, _, _, _, 0x34, join_high, join_low, 0x03, text @ ..]) => Packet::Serial {
+                join: u16::from_be_bytes([*join_high, *join_low]).wrapping_add(1),
+                text: String::from_utf8_lossy(text).into_owned(),
+            },
+            _ => Packet::Unknown {
+                kind,
+                payload: payload.to_vec(),
+            },
+        };
+        Some((packet, len))
+    }
+}
+
+impl Data {
+    fn encode(&self) -> Vec<u8> {
+        let (kind, data) = match self {
+            Data::Digital { join, high } => {
+                let wire = join.wrapping_sub(1) | if *high { 0 } else { LOW };
+                (DIGITAL, wire.to_le_bytes().to_vec())
+            }
+            Data::Analog { join, value } => {
+                let mut data = join.wrapping_sub(1).to_be_bytes().to_vec();
+                data.extend_from_slice(&value.to_be_bytes());
+                (ANALOG, data)
+            }
+            Data::UpdateRequest => (UPDATE, vec![UPDATE_REQUEST]),
+            Data::EndOfQuery => (UPDATE, vec![END_OF_QUERY]),
+            Data::Unknown { kind, payload } => (*kind, payload.clone()),
+        };
+
+        let mut payload = vec![0x00, 0x00, 1 + data.len() as u8, kind];
+        payload.extend_from_slice(&data);
+        payload
+    }
+
+    fn decode(payload: &[u8]) -> Data {
+        let [_, _, _, kind, data @ ..] = payload else {
+            return Data::Unknown {
+                kind: 0,
+                payload: payload.to_vec(),
+            };
+        };
+
+        match (*kind, data) {
+            (DIGITAL | DIGITAL_REPEAT, [low, high, ..]) => {
+                let wire = u16::from_le_bytes([*low, *high]);
+                Data::Digital {
+                    join: (wire & !LOW) + 1,
+                    high: wire & LOW == 0,
+                }
+            }
+            (ANALOG, [join_high, join_low, value_high, value_low, ..]) => Data::Analog {
+                join: u16::from_be_bytes([*join_high, *join_low]).wrapping_add(1),
+                value: u16::from_be_bytes([*value_high, *value_low]),
+            },
+            (UPDATE, [UPDATE_REQUEST, ..]) => Data::UpdateRequest,
+            (UPDATE, [END_OF_QUERY, ..]) => Data::EndOfQuery,
+            (kind, data) => Data::Unknown {
+                kind,
+                payload: data.to_vec(),
+            },
+        }
+    }
+}
+
+impl Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Packet::Data(Data::Digital { join, high }) => {
+                write!(
+                    f,
+                    "digital join {join} {}",
+                    if *high { "high" } else { "low" }
+                )
+            }
+            Packet::Data(Data::Analog { join, value }) => write!(f, "analog join {join} = {value}"),
+            Packet::Serial { join, text } => write!(f, "serial join {join} = {text:?}"),
+            packet => write!(f, "{packet:?}"),
+        }
+    }
+}
+
+/// Splits a byte stream into packets.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    /// Adds received bytes.
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Takes the next complete packet, if there is one.
+    pub fn next_packet(&mut self) -> Option<Packet> {
+        let (packet, len) = Packet::decode(&self.buffer)?;
+        self.buffer.drain(..len);
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packets in their wire format, following the CIP framing.
+    /// The HDMI press and release are the bytes the hardcoded command table used to send.
+    const WIRE_FORMAT: &[(&[u8], Packet)] = &[
+        (&[0x0f, 0x00, 0x01, 0x02], Packet::RegistrationRequest),
+        (
+            &[
+                0x01, 0x00, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x40, 0xff, 0xff, 0xf1, 0x01,
+            ],
+            Packet::Register { ip_id: 0x03 },
+        ),
+        (
+            &[0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x03],
+            Packet::RegistrationAccepted,
+        ),
+        (
+            &[0x02, 0x00, 0x03, 0xff, 0xff, 0x02],
+            Packet::RegistrationRejected,
+        ),
+        (&[0x03, 0x00, 0x00], Packet::Disconnect),
+        (&[0x0d, 0x00, 0x02, 0x00, 0x00], Packet::Heartbeat),
+        (&[0x0e, 0x00, 0x02, 0x00, 0x00], Packet::HeartbeatResponse),
+        (
+            &[0x05, 0x00, 0x05, 0x00, 0x00, 0x02, 0x03, 0x00],
+            Packet::Data(Data::UpdateRequest),
+        ),
+        (
+            &[0x05, 0x00, 0x05, 0x00, 0x00, 0x02, 0x03, 0x16],
+            Packet::Data(Data::EndOfQuery),
+        ),
+        // HDMI pressed and released, as sent by the old command table
+        (
+            &[0x05, 0x00, 0x06, 0x00, 0x00, 0x03, 0x00, 0xcd, 0x13],
+            Packet::Data(Data::Digital {
+                join: 0x13ce,
+                high: true,
+            }),
+        ),
+        (
+            &[0x05, 0x00, 0x06, 0x00, 0x00, 0x03, 0x00, 0xcd, 0x93],
+            Packet::Data(Data::Digital {
+                join: 0x13ce,
+                high: false,
+            }),
+        ),
+        (
+            &[
+                0x05, 0x00, 0x08, 0x00, 0x00, 0x05, 0x14, 0x00, 0x04, 0x7f, 0xff,
+            ],
+            Packet::Data(Data::Analog {
+                join: 5,
+                value: 0x7fff,
+            }),
+        ),
+    ];
+
+    #[test]
+    fn decodes_wire_format() {
+        for (bytes, packet) in WIRE_FORMAT {
+            assert_eq!(
+                Packet::decode(bytes),
+                Some((packet.clone(), bytes.len())),
+                "{bytes:02x?}"
+            );
+        }
+    }
+
+    #[test]
+    fn encodes_wire_format() {
+        for (bytes, packet) in WIRE_FORMAT {
+            assert_eq!(&packet.encode(), bytes, "{packet:?}");
+        }
+    }
+
+    #[test]
+    fn round_trips_serial_joins() {
+        let packet = Packet::Serial {
+            join: 3,
+            text: "Dell 1510X".to_string(),
+        };
+        assert_eq!(
+            Packet::decode(&packet.encode()),
+            Some((packet.clone(), packet.encode().len()))
+        );
+    }
+
+    #[test]
+    fn splits_streams_into_packets() {
+        let mut decoder = Decoder::default();
+        decoder.extend(&[0x0f, 0x00, 0x01, 0x02, 0x0e, 0x00]);
+        assert_eq!(decoder.next_packet(), Some(Packet::RegistrationRequest));
+        assert_eq!(decoder.next_packet(), None);
+
+        decoder.extend(&[0x02, 0x00, 0x00, 0x07, 0x00, 0x01, 0x02]);
+        assert_eq!(decoder.next_packet(), Some(Packet::HeartbeatResponse));
+        assert_eq!(
+            decoder.next_packet(),
+            Some(Packet::Unknown {
+                kind: 0x07,
+                payload: vec![0x02]
+            })
+        );
+        assert_eq!(decoder.next_packet(), None);
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::SocketAddr,
+    path::PathBuf,
+};
+
+use anyhow::{bail, Result};
+use realraum_backend_common::{auth::AuthConfig, logging::LogConfig};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{protocol::ProjectorCommand, serial::Frame, state::Condition};
+
+/// Configuration of the projector backend, see [`realraum_backend_common::config`] for how it's loaded.
+///
+/// The pre-config env var `R3_PROJECTOR_ADDR` keeps working as an override.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Address the HTTP server listens on
+    pub addr: SocketAddr,
+    /// Directory of the frontend's static assets
+    pub static_dir: PathBuf,
+    pub log: LogConfig,
+    pub auth: AuthConfig,
+    /// The projectors we control, the first one is the default
+    /// which the routes without `/projectors/{name}` control
+    pub projectors: Vec<ProjectorConfig>,
+    pub policies: Policies,
+    pub batch: BatchConfig,
+    /// Macros like "presentation" on `POST /api/v1/scenes/{name}/run`
+    pub scenes: Vec<SceneConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:4201".parse().unwrap(),
+            static_dir: PathBuf::from("dist"),
+            log: LogConfig::default(),
+            auth: AuthConfig::default(),
+            projectors: vec![ProjectorConfig::default()],
+            policies: Policies::default(),
+            batch: BatchConfig::default(),
+            scenes: Vec::new(),
+        }
+    }
+}
+
+impl realraum_backend_common::config::Config for Config {
+    const ENV_PREFIX: &'static str = "R3_PROJECTOR_";
+    const DEFAULT_PATH: &'static str = "projector.toml";
+
+    fn validate(&self) -> Result<()> {
+        if self.projectors.is_empty() {
+            bail!("No projectors configured");
+        }
+        let mut names = HashSet::new();
+        for projector in &self.projectors {
+            let name = &projector.name;
+            if !is_valid_name(name) {
+                bail!("Projector name {name:?} must be letters, digits, '-' and '_' only");
+            }
+            if !names.insert(name) {
+                bail!("Projector name {name:?} is used twice");
+            }
+            if projector.driver == Driver::Serial {
+                let serial = &projector.serial;
+                if serial.device.is_empty() || serial.baud_rate == 0 {
+                    bail!("Projector {name:?} has no usable serial device");
+                }
+                if serial.commands.is_empty() {
+                    bail!(
+                        "Projector {name:?} has no serial.commands, \
+                         copy them from the RS-232 table of its manual"
+                    );
+                }
+                if !(5..=8).contains(&serial.data_bits) || !(1..=2).contains(&serial.stop_bits) {
+                    bail!(
+                        "Projector {name:?} needs 5 to 8 data bits and 1 or 2 stop bits, not {} and {}",
+                        serial.data_bits,
+                        serial.stop_bits
+                    );
+                }
+            } else if projector.host.is_empty() || projector.port() == 0 {
+                bail!(
+                    "Projector {name:?} has no usable address {}:{}",
+                    projector.host,
+                    projector.port()
+                );
+            }
+        }
+        let mut scenes = HashSet::new();
+        for scene in &self.scenes {
+            let name = &scene.name;
+            if !is_valid_name(name) {
+                bail!("Scene name {name:?} must be letters, digits, '-' and '_' only");
+            }
+            if !scenes.insert(name) {
+                bail!("Scene name {name:?} is used twice");
+            }
+            if scene.steps.is_empty() {
+                bail!("Scene {name:?} has no steps");
+            }
+        }
+        if self.batch.max_commands == 0 {
+            bail!("batch.max_commands must allow at least one command");
+        }
+        Ok(())
+    }
+}
+
+/// Names of projectors and scenes end up in paths.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProjectorConfig {
+    /// Name of the projector in the `/projectors/{name}/...` routes
+    pub name: String,
+    /// Hostname or IP address of the projector.
+    ///
+    /// Use a hostname, or a fixed IP address in the projector (like we did) or a DHCP reservation.
+    pub host: String,
+    /// Control port of the projector, the driver's default if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    pub driver: Driver,
+    /// IP ID we register with, as set in the projector's Crestron settings
+    pub ip_id: u8,
+    /// Password for PJLink's authentication, if the projector has one set.
+    /// Never serialized, so `--print-config` doesn't show it.
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    /// How often to send heartbeats (Crestron) or ask for the projector's state (PJLink)
+    pub heartbeat_secs: u64,
+    /// How long to wait for the projector to accept a connection
+    pub connect_timeout_secs: u64,
+    /// How long to wait for a command to be written before dropping the connection
+    pub write_timeout_secs: u64,
+    /// Upper bound of the delay between reconnect attempts
+    pub max_backoff_secs: u64,
+    /// The serial line of the `serial` driver
+    pub serial: SerialConfig,
+}
+
+impl Default for ProjectorConfig {
+    fn default() -> Self {
+        Self {
+            name: "beamer".to_string(),
+            host: "192.168.33.41".to_string(),
+            port: None,
+            driver: Driver::default(),
+            ip_id: 0x03,
+            password: None,
+            heartbeat_secs: 15,
+            connect_timeout_secs: 3,
+            write_timeout_secs: 2,
+            max_backoff_secs: 30,
+            serial: SerialConfig::default(),
+        }
+    }
+}
+
+impl ProjectorConfig {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(self.driver.default_port())
+    }
+
+    /// Points the projector at `addr`, for the `--projector-addr` flag.
+    pub fn set_addr(&mut self, addr: SocketAddr) {
+        self.host = addr.ip().to_string();
+        self.port = Some(addr.port());
+    }
+}
+
+/// The protocol a projector speaks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Driver {
+    /// Crestron CIP, like our Dell projector's web UI
+    #[default]
+    Crestron,
+    /// PJLink class 1 or 2
+    #[serde(rename = "pjlink")]
+    PjLink,
+    /// The commands of the projector's RS-232 manual on a serial line, see [`SerialConfig`]
+    Serial,
+}
+
+impl Driver {
+    /// The control port, serial lines have none.
+    pub fn default_port(self) -> u16 {
+        match self {
+            Driver::Crestron => 41794,
+            Driver::PjLink => 4352,
+            Driver::Serial => 0,
+        }
+    }
+}
+
+/// A serial device, its framing as set in the projector's RS-232 settings,
+/// and the projector's RS-232 commands.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SerialConfig {
+    /// Path of the device, e.g. `/dev/ttyUSB0` for a USB-serial cable
+    pub device: String,
+    pub baud_rate: u32,
+    pub parity: Parity,
+    /// 5 to 8
+    pub data_bits: u8,
+    /// 1 or 2
+    pub stop_bits: u8,
+    /// The bytes of each command, from the RS-232 table of the projector's manual,
+    /// e.g. `"power/on" = "50 57 52 20 4F 4E 0D"`
+    pub commands: BTreeMap<ProjectorCommand, Frame>,
+    /// What the projector answers to a command it accepted, if it does,
+    /// to confirm commands with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ack: Option<Frame>,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            device: "/dev/ttyUSB0".to_string(),
+            baud_rate: 19200,
+            parity: Parity::None,
+            data_bits: 8,
+            stop_bits: 1,
+            commands: BTreeMap::new(),
+            ack: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+/// What clients are allowed to do.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Policies {
+    /// Keep serving the deprecated `GET` command routes of `/api/v1`,
+    /// which link prefetchers and crawlers can trigger by accident
+    pub legacy_get_routes: bool,
+}
+
+impl Default for Policies {
+    fn default() -> Self {
+        Self {
+            legacy_get_routes: true,
+        }
+    }
+}
+
+/// Batches of commands on `POST /api/v1/command`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    /// Delay between two commands of a batch unless the request sets one,
+    /// the projector drops presses that follow each other too closely
+    pub delay_ms: u64,
+    /// Longest delay a request may ask for
+    pub max_delay_ms: u64,
+    /// Longest a whole batch may wait between its commands, the request stays open meanwhile
+    pub max_total_delay_ms: u64,
+    /// Most commands in one batch
+    pub max_commands: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            delay_ms: 300,
+            max_delay_ms: 60_000,
+            max_total_delay_ms: 120_000,
+            max_commands: 20,
+        }
+    }
+}
+
+/// A named macro of steps, run in order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneConfig {
+    pub name: String,
+    /// Shown by remotes instead of the name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub steps: Vec<SceneStep>,
+}
+
+/// A step of a scene, told apart by its key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum SceneStep {
+    /// `{ command = "power/on" }`, consecutive commands are `batch.delay_ms` apart
+    Command { command: ProjectorCommand },
+    /// `{ wait_ms = 500 }`
+    Wait { wait_ms: u64 },
+    /// `{ wait_for = { power = "on" }, timeout_secs = 60 }`, the scene fails on the timeout.
+    ///
+    /// Only values the projector reported count, so on `serial` projectors, which report nothing,
+    /// this always runs into the timeout. Use a `wait_ms` step for them instead.
+    WaitFor {
+        wait_for: Condition,
+        #[serde(default = "default_wait_timeout")]
+        timeout_secs: u64,
+    },
+}
+
+fn default_wait_timeout() -> u64 {
+    60
+}
@@ -0,0 +1,572 @@
+//! The [`ProjectorDriver`] for projectors speaking Crestron's [CIP](crate::cip) protocol,
+//! keeping one long-lived connection to the projector.
+//!
+//! A supervisor task owns the connection, reconnects with exponential backoff when it drops,
+//! and sends the commands of all handlers one after another through a channel,
+//! so presses from several clients never interleave on the wire.
+//!
+//! It also runs the [CIP](crate::cip) session: it registers when the projector asks for it,
+//! exchanges heartbeats, and waits for the projector to report a pressed join as feedback.
+
+use std::{io, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{mpsc, oneshot, watch},
+    time::{self, Instant},
+};
+use tracing::Instrument;
+
+use crate::{
+    cip::{Data, Decoder, Packet},
+    config::ProjectorConfig,
+    driver::{Delivery, Error, Operation, ProjectorDriver, Sent},
+    protocol::{Command, ProjectorCommand},
+    state::{ProjectorState, Source},
+};
+
+/// How many commands may wait for the connection before handlers get [`Error::Busy`].
+const QUEUE_SIZE: usize = 16;
+
+/// Delay before the first reconnect, doubled after every failed attempt up to the configured maximum.
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+
+/// How long we wait for feedback on a pressed join.
+const FEEDBACK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How many heartbeats may go unanswered before we consider the connection dead.
+const MISSED_HEARTBEATS: u32 = 3;
+
+struct Request {
+    command: Command,
+    reply: oneshot::Sender<Result<Delivery, Error>>,
+}
+
+/// Handle to the connection, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct CrestronDriver {
+    requests: mpsc::Sender<Request>,
+    state: watch::Receiver<ProjectorState>,
+}
+
+impl CrestronDriver {
+    /// Spawns the supervisor task, which connects right away.
+    pub fn spawn(config: ProjectorConfig) -> Self {
+        let (requests, receiver) = mpsc::channel(QUEUE_SIZE);
+        let (state, state_receiver) = watch::channel(ProjectorState::default());
+        tokio::spawn(
+            Supervisor {
+                host: config.host.clone(),
+                port: config.port(),
+                ip_id: config.ip_id,
+                connect_timeout: Duration::from_secs(config.connect_timeout_secs),
+                write_timeout: Duration::from_secs(config.write_timeout_secs),
+                heartbeat: Duration::from_secs(config.heartbeat_secs.max(1)),
+                backoff: Backoff::new(Duration::from_secs(config.max_backoff_secs)),
+                session: None,
+                pending: Vec::new(),
+                state,
+            }
+            .run(receiver)
+            .instrument(tracing::info_span!("projector", name = %config.name)),
+        );
+
+        Self {
+            requests,
+            state: state_receiver,
+        }
+    }
+
+    /// Presses and releases `command`, waiting for feedback once the session is registered.
+    async fn send(&self, command: Command) -> Result<Delivery, Error> {
+        let (reply, result) = oneshot::channel();
+        self.requests
+            .try_send(Request { command, reply })
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => Error::Busy,
+                mpsc::error::TrySendError::Closed(_) => Error::Closed,
+            })?;
+        result.await.map_err(|_| Error::Closed)?
+    }
+}
+
+#[async_trait]
+impl ProjectorDriver for CrestronDriver {
+    async fn execute(&self, operation: Operation) -> Result<Sent, Error> {
+        let command = operation.command().encode();
+        let delivery = self.send(command).await?;
+        Ok(Sent {
+            bytes: command.to_vec(),
+            delivery,
+        })
+    }
+
+    fn subscribe(&self) -> watch::Receiver<ProjectorState> {
+        self.state.clone()
+    }
+}
+
+/// The digital join of a button press.
+pub fn join(command: &Command) -> Option<u16> {
+    match Packet::decode(command) {
+        Some((Packet::Data(Data::Digital { join, .. }), _)) => Some(join),
+        _ => None,
+    }
+}
+
+/// The operation whose button is on `join`.
+fn operation(join: u16) -> Option<Operation> {
+    let press = Packet::Data(Data::Digital { join, high: true }).encode();
+    ProjectorCommand::decode(&press).map(Operation::from)
+}
+
+/// Exponential backoff between connection attempts.
+struct Backoff {
+    delay: Duration,
+    max: Duration,
+    next_attempt: Instant,
+}
+
+impl Backoff {
+    fn new(max: Duration) -> Self {
+        Self {
+            delay: MIN_BACKOFF,
+            max,
+            next_attempt: Instant::now(),
+        }
+    }
+
+    fn succeeded(&mut self) {
+        self.delay = MIN_BACKOFF;
+    }
+
+    fn failed(&mut self) {
+        self.next_attempt = Instant::now() + self.delay;
+        self.delay = (self.delay * 2).min(self.max.max(MIN_BACKOFF));
+    }
+
+    fn is_due(&self) -> bool {
+        Instant::now() >= self.next_attempt
+    }
+}
+
+/// An open connection.
+struct Session {
+    stream: TcpStream,
+    decoder: Decoder,
+    /// Whether the projector accepted our IP ID, projectors that never ask stay unregistered
+    registered: bool,
+    last_received: Instant,
+}
+
+/// A command waiting for feedback.
+struct Pending {
+    join: u16,
+    deadline: Instant,
+    reply: oneshot::Sender<Result<Delivery, Error>>,
+}
+
+struct Supervisor {
+    host: String,
+    port: u16,
+    ip_id: u8,
+    connect_timeout: Duration,
+    write_timeout: Duration,
+    heartbeat: Duration,
+    backoff: Backoff,
+    session: Option<Session>,
+    pending: Vec<Pending>,
+    state: watch::Sender<ProjectorState>,
+}
+
+impl Supervisor {
+    async fn run(mut self, mut requests: mpsc::Receiver<Request>) {
+        self.connect().await;
+        let mut buffer = [0; 256];
+        let mut heartbeat = time::interval_at(Instant::now() + self.heartbeat, self.heartbeat);
+        heartbeat.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        loop {
+            let disconnected = self.session.is_none();
+            let feedback_deadline = self.pending.iter().map(|pending| pending.deadline).min();
+            tokio::select! {
+                request = requests.recv() => {
+                    let Some(request) = request else {
+                        break;
+                    };
+                    self.send(request).await;
+                }
+                _ = time::sleep_until(self.backoff.next_attempt), if disconnected => {
+                    self.connect().await;
+                }
+                read = read(&mut self.session, &mut buffer), if !disconnected => {
+                    match read {
+                        Ok(0) => self.disconnect("Projector closed the connection"),
+                        Ok(n) => self.receive(&buffer[..n]).await,
+                        Err(e) => self.disconnect(&format!("Projector connection broke: {e}")),
+                    }
+                }
+                _ = heartbeat.tick(), if !disconnected => {
+                    self.send_heartbeat().await;
+                }
+                _ = time::sleep_until(feedback_deadline.unwrap_or_else(Instant::now)),
+                    if feedback_deadline.is_some() =>
+                {
+                    self.expire_pending();
+                }
+            }
+        }
+    }
+
+    async fn send(&mut self, Request { command, reply }: Request) {
+        // Don't let every press wait for the connect timeout while the projector is gone
+        if self.session.is_none() && self.backoff.is_due() {
+            self.connect().await;
+        }
+        if self.session.is_none() {
+            let _ = reply.send(Err(Error::Unreachable(io::ErrorKind::NotConnected.into())));
+            return;
+        }
+
+        // Release the button right away, the projector acts on the press
+        let mut bytes = command.to_vec();
+        let join = join(&command);
+        if let Some(join) = join {
+            bytes.extend(Packet::Data(Data::Digital { join, high: false }).encode());
+        }
+        if let Err(e) = self.write(&bytes).await {
+            let _ = reply.send(Err(Error::Unreachable(e)));
+            return;
+        }
+        if let Some(operation) = join.and_then(operation) {
+            // Until the projector tells us otherwise
+            self.state
+                .send_modify(|state| state.apply(operation, Source::Command));
+        }
+
+        // The client may have given up waiting, that's fine
+        match join {
+            Some(join)
+                if self
+                    .session
+                    .as_ref()
+                    .is_some_and(|session| session.registered) =>
+            {
+                self.pending.push(Pending {
+                    join,
+                    deadline: Instant::now() + FEEDBACK_TIMEOUT,
+                    reply,
+                });
+            }
+            _ => {
+                let _ = reply.send(Ok(Delivery::Unconfirmed));
+            }
+        }
+    }
+
+    /// Handles bytes from the projector.
+    async fn receive(&mut self, bytes: &[u8]) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        session.decoder.extend(bytes);
+        session.last_received = Instant::now();
+
+        while let Some(packet) = self.session.as_mut().and_then(|s| s.decoder.next_packet()) {
+            tracing::trace!("Received {packet}");
+            let answer = match packet {
+                Packet::RegistrationRequest => Some(Packet::Register { ip_id: self.ip_id }),
+                Packet::RegistrationAccepted => {
+                    tracing::info!("Registered with IP ID {:#04x}", self.ip_id);
+                    if let Some(session) = &mut self.session {
+                        session.registered = true;
+                    }
+                    Some(Packet::Data(Data::UpdateRequest))
+                }
+                Packet::RegistrationRejected => {
+                    self.disconnect(&format!(
+                        "Projector rejected IP ID {:#04x}, check projector.ip_id",
+                        self.ip_id
+                    ));
+                    None
+                }
+                Packet::Disconnect => {
+                    self.disconnect("Projector ended the session");
+                    None
+                }
+                Packet::Heartbeat => Some(Packet::HeartbeatResponse),
+                Packet::Data(Data::Digital { join, high }) => {
+                    tracing::debug!("Feedback: digital join {join} {high}");
+                    // Low joins are released buttons or deselected options, the high ones tell more
+                    if let Some(operation) = operation(join).filter(|_| high) {
+                        self.state
+                            .send_modify(|state| state.apply(operation, Source::Feedback));
+                    }
+                    self.confirm(join);
+                    None
+                }
+                Packet::Unknown { kind, payload } => {
+                    tracing::debug!("Ignoring unknown packet {kind:#04x}: {payload:02x?}");
+                    None
+                }
+                _ => None,
+            };
+
+            if let Some(answer) = answer {
+                if self.write(&answer.encode()).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Sends a heartbeat, or drops the connection if the projector stopped answering them.
+    async fn send_heartbeat(&mut self) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        // Projectors that never registered aren't expected to speak heartbeats
+        if !session.registered {
+            return;
+        }
+        if session.last_received.elapsed() > self.heartbeat * MISSED_HEARTBEATS {
+            self.disconnect("Projector stopped answering heartbeats");
+            return;
+        }
+        let _ = self.write(&Packet::Heartbeat.encode()).await;
+    }
+
+    /// Writes `bytes`, dropping the connection if that fails.
+    async fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let Some(session) = &mut self.session else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        let result = match time::timeout(self.write_timeout, session.stream.write_all(bytes)).await
+        {
+            Ok(result) => result,
+            Err(_) => Err(io::ErrorKind::TimedOut.into()),
+        };
+        result.inspect_err(|e| self.disconnect(&format!("Failed to send to the projector: {e}")))
+    }
+
+    fn confirm(&mut self, join: u16) {
+        for pending in extract(&mut self.pending, |pending| pending.join == join) {
+            let _ = pending.reply.send(Ok(Delivery::Confirmed));
+        }
+    }
+
+    fn expire_pending(&mut self) {
+        let now = Instant::now();
+        for pending in extract(&mut self.pending, |pending| pending.deadline <= now) {
+            let _ = pending.reply.send(Ok(Delivery::Unconfirmed));
+        }
+    }
+
+    async fn connect(&mut self) {
+        let start = std::time::Instant::now();
+        match time::timeout(
+            self.connect_timeout,
+            TcpStream::connect((self.host.as_str(), self.port)),
+        )
+        .await
+        {
+            Ok(Ok(stream)) => {
+                metrics::histogram!("projector_connection_seconds")
+                    .record(start.elapsed().as_secs_f64());
+                tracing::info!("Connected to the projector at {}:{}", self.host, self.port);
+                self.session = Some(Session {
+                    stream,
+                    decoder: Decoder::default(),
+                    registered: false,
+                    last_received: Instant::now(),
+                });
+                self.backoff.succeeded();
+                self.state.send_modify(|state| state.set_connected(true));
+            }
+            Ok(Err(e)) => self.connect_failed(e),
+            Err(_) => self.connect_failed(io::ErrorKind::TimedOut.into()),
+        }
+    }
+
+    fn connect_failed(&mut self, e: io::Error) {
+        tracing::warn!(
+            "Failed to connect to the projector at {}:{}, retrying in {:?}: {e}",
+            self.host,
+            self.port,
+            self.backoff.delay
+        );
+        self.backoff.failed();
+    }
+
+    fn disconnect(&mut self, reason: &str) {
+        tracing::warn!("{reason}, reconnecting");
+        metrics::counter!("projector_disconnects_total").increment(1);
+        self.session = None;
+        self.state.send_modify(|state| state.set_connected(false));
+        // Don't hammer a projector that hangs up right after accepting
+        self.backoff.failed();
+        // Those commands were sent, we just won't hear back about them
+        for pending in self.pending.drain(..) {
+            let _ = pending.reply.send(Ok(Delivery::Unconfirmed));
+        }
+    }
+}
+
+/// Removes and returns the elements of `items` matching `predicate`.
+fn extract<T>(items: &mut Vec<T>, predicate: impl Fn(&T) -> bool) -> Vec<T> {
+    let (matching, rest) = items.drain(..).partition(predicate);
+    *items = rest;
+    matching
+}
+
+/// Reads from `session`, which must be set.
+async fn read(session: &mut Option<Session>, buffer: &mut [u8]) -> io::Result<usize> {
+    match session {
+        Some(session) => session.stream.read(buffer).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        protocol::commands,
+        state::{Confidence, Input},
+    };
+
+    fn config(addr: SocketAddr) -> ProjectorConfig {
+        let mut config = ProjectorConfig::default();
+        config.set_addr(addr);
+        config
+    }
+
+    async fn expect(connection: &mut TcpStream, packet: Packet) {
+        let mut received = vec![0; packet.encode().len()];
+        connection.read_exact(&mut received).await.unwrap();
+        assert_eq!(Packet::decode(&received), Some((packet, received.len())));
+    }
+
+    async fn press_and_release(connection: &mut TcpStream, command: Command) {
+        let Some((Packet::Data(Data::Digital { join, .. }), _)) = Packet::decode(&command) else {
+            panic!("not a press");
+        };
+        let mut received = [0; 18];
+        connection.read_exact(&mut received).await.unwrap();
+        assert_eq!(received[..9], command);
+        assert_eq!(
+            received[9..],
+            Packet::Data(Data::Digital { join, high: false }).encode()
+        );
+    }
+
+    #[test]
+    fn commands_are_distinct_presses() {
+        for command in ProjectorCommand::ALL {
+            let bytes = command.encode();
+            assert!(
+                matches!(
+                    Packet::decode(&bytes),
+                    Some((Packet::Data(Data::Digital { high: true, .. }), 9))
+                ),
+                "{command} isn't a press"
+            );
+            let operation = Operation::from(command);
+            assert_eq!(operation.command(), command);
+            assert_eq!(join(&bytes).and_then(super::operation), Some(operation));
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_commands_over_one_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let projector = CrestronDriver::spawn(config(listener.local_addr().unwrap()));
+        let (mut connection, _) = listener.accept().await.unwrap();
+
+        let sent = projector
+            .execute(Operation::Input(Input::Hdmi))
+            .await
+            .unwrap();
+        assert_eq!(sent.bytes, commands::input::HDMI);
+        assert_eq!(sent.delivery, Delivery::Unconfirmed);
+        projector.send(commands::power::ON).await.unwrap();
+
+        press_and_release(&mut connection, commands::input::HDMI).await;
+        press_and_release(&mut connection, commands::power::ON).await;
+        assert!(projector.is_connected());
+    }
+
+    #[tokio::test]
+    async fn registers_and_confirms_commands() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let projector = CrestronDriver::spawn(config(listener.local_addr().unwrap()));
+        let (mut connection, _) = listener.accept().await.unwrap();
+
+        connection
+            .write_all(&Packet::RegistrationRequest.encode())
+            .await
+            .unwrap();
+        expect(&mut connection, Packet::Register { ip_id: 0x03 }).await;
+        connection
+            .write_all(&Packet::RegistrationAccepted.encode())
+            .await
+            .unwrap();
+        expect(&mut connection, Packet::Data(Data::UpdateRequest)).await;
+
+        connection
+            .write_all(&Packet::Heartbeat.encode())
+            .await
+            .unwrap();
+        expect(&mut connection, Packet::HeartbeatResponse).await;
+
+        let (sent, ()) = tokio::join!(projector.send(commands::input::HDMI), async {
+            press_and_release(&mut connection, commands::input::HDMI).await;
+            // The projector reports the selected input
+            let feedback = Packet::Data(Data::Digital {
+                join: 0x13ce,
+                high: true,
+            });
+            connection.write_all(&feedback.encode()).await.unwrap();
+        });
+        assert_eq!(sent.unwrap(), Delivery::Confirmed);
+        let state = projector.state();
+        assert_eq!(state.input.value, Some(Input::Hdmi));
+        assert_eq!(state.input.confidence, Confidence::Confirmed);
+
+        let (sent, ()) = tokio::join!(projector.send(commands::menu::UP), async {
+            press_and_release(&mut connection, commands::menu::UP).await;
+        });
+        assert_eq!(sent.unwrap(), Delivery::Unconfirmed);
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_the_projector_hangs_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let projector = CrestronDriver::spawn(config(listener.local_addr().unwrap()));
+        drop(listener.accept().await.unwrap());
+
+        let (mut connection, _) = listener.accept().await.unwrap();
+        projector.send(commands::volume::MUTE).await.unwrap();
+        press_and_release(&mut connection, commands::volume::MUTE).await;
+    }
+
+    #[tokio::test]
+    async fn fails_while_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let projector = CrestronDriver::spawn(config(addr));
+        assert!(matches!(
+            projector.send(commands::power::ON).await,
+            Err(Error::Unreachable(_))
+        ));
+        assert!(!projector.is_connected());
+    }
+}
@@ -0,0 +1,298 @@
+//! Sending commands by name on `POST /api/v1/command`, one at a time or in batches.
+//!
+//! Unlike the routes of [`crate::commands`], which have one route per command, this resolves
+//! `{group}/{name}` with [`ProjectorCommand::from_name`], so clients can send whatever
+//! `/api/v1/commands` lists.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    routing::post,
+    Extension, Json, Router,
+};
+use hyper::StatusCode;
+use realraum_backend_common::{
+    auth::{self, Identity, Role},
+    response, Error,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    config::{BatchConfig, Config},
+    driver::Delivery,
+    projectors::{Projector, SelectedProjector},
+    protocol::ProjectorCommand,
+    required_role, AppState,
+};
+
+/// `POST /command` and `POST /command/{group}/{name}`, for members.
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/command", post(handle_command))
+        .route("/command/:group/:name", post(handle_named_command))
+        .route_layer(auth::require(Role::Member))
+}
+
+/// A command by its group and name, as listed by `/api/v1/commands`.
+#[derive(Debug, Deserialize)]
+struct Step {
+    group: String,
+    name: String,
+    /// Wait before the next command instead of the batch's delay
+    delay_ms: Option<u64>,
+}
+
+/// The body of `POST /command`, a single [`Step`] or a batch of them.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Payload {
+    Batch {
+        commands: Vec<Step>,
+        /// Wait between commands instead of `batch.delay_ms`
+        delay_ms: Option<u64>,
+    },
+    Single(Step),
+}
+
+async fn handle_command(
+    State(config): State<Arc<Config>>,
+    SelectedProjector(projector): SelectedProjector,
+    identity: Option<Extension<Identity>>,
+    Json(payload): Json<Payload>,
+) -> Result<Json<Value>, Response> {
+    let (steps, delay_ms) = match payload {
+        Payload::Batch { commands, delay_ms } => (commands, delay_ms),
+        Payload::Single(step) => (vec![step], None),
+    };
+    let batch = resolve(&config.batch, steps, delay_ms).map_err(IntoResponse::into_response)?;
+    for (command, _) in &batch {
+        auth::authorize(identity.as_deref(), required_role(*command))
+            .map_err(IntoResponse::into_response)?;
+    }
+
+    run(&projector, &batch)
+        .await
+        .map_err(IntoResponse::into_response)
+}
+
+async fn handle_named_command(
+    SelectedProjector(projector): SelectedProjector,
+    identity: Option<Extension<Identity>>,
+    // All params, as the route may be nested below `/projectors/:projector`
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Json<Value>, Response> {
+    let command = lookup(&params["group"], &params["name"]).map_err(IntoResponse::into_response)?;
+    auth::authorize(identity.as_deref(), required_role(command))
+        .map_err(IntoResponse::into_response)?;
+
+    run(&projector, &[(command, Duration::ZERO)])
+        .await
+        .map_err(IntoResponse::into_response)
+}
+
+/// Resolves all commands of a batch with the delay after each, before anything is sent.
+fn resolve(
+    config: &BatchConfig,
+    steps: Vec<Step>,
+    delay_ms: Option<u64>,
+) -> Result<Vec<(ProjectorCommand, Duration)>, Error> {
+    if steps.is_empty() {
+        return Err(Error::new(
+            StatusCode::BAD_REQUEST,
+            "empty_batch",
+            "The batch has no commands",
+        ));
+    }
+    if steps.len() > config.max_commands {
+        return Err(Error::new(
+            StatusCode::BAD_REQUEST,
+            "batch_too_large",
+            format!("A batch may have at most {} commands", config.max_commands),
+        ));
+    }
+
+    let default_delay = delay_ms.unwrap_or(config.delay_ms);
+    let batch = steps
+        .into_iter()
+        .map(|step| {
+            let delay = step.delay_ms.unwrap_or(default_delay);
+            if delay > config.max_delay_ms {
+                return Err(Error::new(
+                    StatusCode::BAD_REQUEST,
+                    "delay_too_long",
+                    format!("Delays may be at most {}ms", config.max_delay_ms),
+                ));
+            }
+            Ok((
+                lookup(&step.group, &step.name)?,
+                Duration::from_millis(delay),
+            ))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // There's no waiting after the last command
+    let total: Duration = batch[..batch.len() - 1]
+        .iter()
+        .map(|(_, delay)| *delay)
+        .sum();
+    if total > Duration::from_millis(config.max_total_delay_ms) {
+        return Err(Error::new(
+            StatusCode::BAD_REQUEST,
+            "batch_too_long",
+            format!(
+                "The delays of a batch may add up to at most {}ms",
+                config.max_total_delay_ms
+            ),
+        ));
+    }
+    Ok(batch)
+}
+
+fn lookup(group: &str, name: &str) -> Result<ProjectorCommand, Error> {
+    ProjectorCommand::from_name(group, name).ok_or_else(|| {
+        Error::new(
+            StatusCode::NOT_FOUND,
+            "unknown_command",
+            format!("No command {group}/{name}, see /api/v1/commands"),
+        )
+    })
+}
+
+/// Sends the commands in order, waiting their delay between them, and stops at the first failure.
+async fn run(
+    projector: &Projector,
+    batch: &[(ProjectorCommand, Duration)],
+) -> Result<Json<Value>, Error> {
+    let mut results = Vec::with_capacity(batch.len());
+    for (i, (command, delay)) in batch.iter().enumerate() {
+        let sent = crate::send(projector, (*command).into())
+            .await
+            .map_err(|mut e| {
+                if batch.len() > 1 {
+                    e.message = format!(
+                        "{command} failed after {i} of {} commands: {}",
+                        batch.len(),
+                        e.message
+                    );
+                }
+                e
+            })?;
+        results.push(json!({
+            "command": command.to_string(),
+            "bytes": sent.bytes,
+            "confirmed": sent.delivery == Delivery::Confirmed,
+        }));
+        if i + 1 < batch.len() {
+            tokio::time::sleep(*delay).await;
+        }
+    }
+
+    Ok(response::ok(json!({
+        "message": "Commands sent successfully",
+        "results": results,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(group: &str, name: &str, delay_ms: Option<u64>) -> Step {
+        Step {
+            group: group.into(),
+            name: name.into(),
+            delay_ms,
+        }
+    }
+
+    #[test]
+    fn parses_single_commands_and_batches() {
+        let single: Payload =
+            serde_json::from_value(json!({"group": "power", "name": "on"})).unwrap();
+        assert!(matches!(single, Payload::Single(Step { ref name, .. }) if name == "on"));
+
+        let batch: Payload = serde_json::from_value(json!({
+            "commands": [{"group": "power", "name": "on"}, {"group": "input", "name": "hdmi", "delay_ms": 0}],
+            "delay_ms": 5000,
+        }))
+        .unwrap();
+        assert!(matches!(
+            batch,
+            Payload::Batch { ref commands, delay_ms: Some(5000) } if commands.len() == 2
+        ));
+    }
+
+    #[test]
+    fn resolves_batches_with_their_delays() {
+        let config = BatchConfig::default();
+        let batch = resolve(
+            &config,
+            vec![
+                step("power", "on", Some(5000)),
+                step("input", "hdmi", None),
+                step("volume", "mute", None),
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            batch,
+            [
+                (ProjectorCommand::PowerOn, Duration::from_secs(5)),
+                (ProjectorCommand::InputHdmi, Duration::from_millis(300)),
+                (ProjectorCommand::VolumeMute, Duration::from_millis(300)),
+            ]
+        );
+
+        let batch = resolve(&config, vec![step("menu", "ok", None)], Some(0)).unwrap();
+        assert_eq!(batch, [(ProjectorCommand::MenuOk, Duration::ZERO)]);
+    }
+
+    #[test]
+    fn rejects_bad_batches() {
+        let config = BatchConfig::default();
+        let code = |steps, delay_ms| resolve(&config, steps, delay_ms).unwrap_err().code;
+
+        assert_eq!(code(vec![], None), "empty_batch");
+        assert_eq!(
+            code((0..21).map(|_| step("menu", "up", None)).collect(), None),
+            "batch_too_large"
+        );
+        assert_eq!(
+            code(vec![step("power", "on", None)], Some(120_000)),
+            "delay_too_long"
+        );
+        assert_eq!(
+            code(
+                vec![step("power", "on", None), step("power", "toggle", None)],
+                None
+            ),
+            "unknown_command"
+        );
+        assert_eq!(
+            code(
+                vec![
+                    step("power", "on", Some(60_000)),
+                    step("input", "hdmi", Some(60_000)),
+                    step("volume", "mute", None),
+                    step("volume", "up", None),
+                ],
+                None
+            ),
+            "batch_too_long"
+        );
+        // The last delay isn't waited
+        resolve(
+            &config,
+            vec![
+                step("power", "on", Some(60_000)),
+                step("input", "hdmi", Some(60_000)),
+            ],
+            None,
+        )
+        .unwrap();
+    }
+}
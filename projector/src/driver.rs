@@ -0,0 +1,186 @@
+//! Projectors are controlled through a [`ProjectorDriver`], one for each protocol:
+//!
+//! - [`crestron`](crate::crestron), the CIP protocol of our Dell projector's web UI,
+//! - [`pjlink`](crate::pjlink), which most newer projectors speak,
+//! - [`serial`](crate::serial), the commands of a projector's RS-232 manual on a serial line.
+//!
+//! The API offers the same [`Operation`]s for all of them, one for each [`ProjectorCommand`],
+//! drivers answer with [`Error::Unsupported`] for those their protocol lacks.
+
+use std::{
+    fmt::{self, Debug, Display},
+    io,
+};
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use crate::{
+    protocol::ProjectorCommand,
+    state::{Input, Power, ProjectorState},
+};
+
+/// Something a projector can be told to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Power(Power),
+    Input(Input),
+    /// Cycles through the inputs like the remote's source button
+    NextInput,
+    Mute(bool),
+    Volume(Direction),
+    Blank(bool),
+    Freeze(bool),
+    Adjust(Picture, Direction),
+    Menu(MenuButton),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// Picture settings that can be turned up or down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Picture {
+    Contrast,
+    Brightness,
+    Color,
+    Sharpness,
+}
+
+/// Buttons for navigating the projector's on-screen menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuButton {
+    Menu,
+    Up,
+    Down,
+    Left,
+    Right,
+    Ok,
+    Auto,
+}
+
+impl From<ProjectorCommand> for Operation {
+    fn from(command: ProjectorCommand) -> Self {
+        use ProjectorCommand::*;
+        match command {
+            InputVgaA => Operation::Input(Input::VgaA),
+            InputVgaB => Operation::Input(Input::VgaB),
+            InputComposite1 => Operation::Input(Input::Composite1),
+            InputComposite2 => Operation::Input(Input::Composite2),
+            InputSVideo => Operation::Input(Input::SVideo),
+            InputHdmi => Operation::Input(Input::Hdmi),
+            InputSourceButton => Operation::NextInput,
+            VolumeUp => Operation::Volume(Direction::Up),
+            VolumeDown => Operation::Volume(Direction::Down),
+            VolumeMute => Operation::Mute(true),
+            VolumeUnMute => Operation::Mute(false),
+            PowerOn => Operation::Power(Power::On),
+            PowerOff => Operation::Power(Power::Off),
+            MenuButton => Operation::Menu(self::MenuButton::Menu),
+            MenuUp => Operation::Menu(self::MenuButton::Up),
+            MenuDown => Operation::Menu(self::MenuButton::Down),
+            MenuLeft => Operation::Menu(self::MenuButton::Left),
+            MenuRight => Operation::Menu(self::MenuButton::Right),
+            MenuOk => Operation::Menu(self::MenuButton::Ok),
+            MenuAutoButton => Operation::Menu(self::MenuButton::Auto),
+            PictureBlank => Operation::Blank(true),
+            PictureUnBlank => Operation::Blank(false),
+            PictureFreeze => Operation::Freeze(true),
+            PictureUnFreeze => Operation::Freeze(false),
+            PictureContrastUp => Operation::Adjust(Picture::Contrast, Direction::Up),
+            PictureContrastDown => Operation::Adjust(Picture::Contrast, Direction::Down),
+            PictureBrightnessUp => Operation::Adjust(Picture::Brightness, Direction::Up),
+            PictureBrightnessDown => Operation::Adjust(Picture::Brightness, Direction::Down),
+            PictureColorUp => Operation::Adjust(Picture::Color, Direction::Up),
+            PictureColorDown => Operation::Adjust(Picture::Color, Direction::Down),
+            PictureSharpnessUp => Operation::Adjust(Picture::Sharpness, Direction::Up),
+            PictureSharpnessDown => Operation::Adjust(Picture::Sharpness, Direction::Down),
+        }
+    }
+}
+
+impl Operation {
+    /// The command performing the operation, every operation has exactly one.
+    pub fn command(self) -> ProjectorCommand {
+        ProjectorCommand::ALL
+            .into_iter()
+            .find(|&command| Operation::from(command) == self)
+            .expect("every operation has a command")
+    }
+}
+
+/// What the projector made of an operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sent {
+    /// The bytes sent to the projector
+    pub bytes: Vec<u8>,
+    pub delivery: Delivery,
+}
+
+/// Whether the projector confirmed an operation it received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// The projector acknowledged it or reported the change
+    Confirmed,
+    /// We sent it, but got no feedback.
+    /// Some projectors never send any, and some commands don't change anything they report
+    Unconfirmed,
+}
+
+/// Why an operation didn't reach the projector.
+#[derive(Debug)]
+pub enum Error {
+    /// We couldn't connect or the connection broke while sending
+    Unreachable(io::Error),
+    /// Too many operations are already waiting
+    Busy,
+    /// The driver's task is gone, which only happens on shutdown
+    Closed,
+    /// The protocol has no command for the operation
+    Unsupported(Operation),
+    /// The projector refused the operation, e.g. while warming up
+    Refused(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unreachable(e) => write!(f, "Projector unreachable: {e}"),
+            Error::Busy => write!(f, "Too many commands waiting for the projector"),
+            Error::Closed => write!(f, "Projector connection closed"),
+            Error::Unsupported(operation) => {
+                write!(f, "The projector's protocol doesn't support {operation:?}")
+            }
+            Error::Refused(reason) => write!(f, "Projector refused the command: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A connection to a projector speaking some protocol.
+///
+/// Drivers keep their connection and [`ProjectorState`] up to date in the background,
+/// and send operations one after another.
+#[async_trait]
+pub trait ProjectorDriver: Debug + Send + Sync {
+    /// Powers on or off, selects an input, mutes, adjusts the picture…
+    /// whatever `operation` says, as far as the protocol supports it.
+    async fn execute(&self, operation: Operation) -> Result<Sent, Error>;
+
+    /// Watches what we know about the projector, to wait for it to change.
+    fn subscribe(&self) -> watch::Receiver<ProjectorState>;
+
+    /// What we know about the projector.
+    fn state(&self) -> ProjectorState {
+        self.subscribe().borrow().clone()
+    }
+
+    /// Whether the connection is currently up.
+    fn is_connected(&self) -> bool {
+        self.state().connected
+    }
+}
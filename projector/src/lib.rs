@@ -0,0 +1,5 @@
+//! The projector's wire protocol, shared by the backend and the projector simulator.
+
+pub mod cip;
+pub mod protocol;
+pub mod simulator;
@@ -1,170 +1,327 @@
-use std::{
-    env,
-    net::{Ipv4Addr, SocketAddr},
-};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
 use anyhow::Result;
 use axum::{
-    routing::{any, get},
+    extract::{FromRef, State},
+    routing::{get, MethodRouter},
     Json, Router,
 };
-use hyper::{Method, StatusCode, Uri};
-use protocol::{
-    commands::{input, menu, picture, power, volume},
-    Command,
+use clap::Parser;
+use config::Config;
+use driver::{Delivery, Operation, Sent};
+use hyper::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    Method, StatusCode,
 };
-use serde_json::{json, Value};
-use tokio::{io::AsyncWriteExt, net::TcpStream};
-use tower_http::{
-    cors::{Any, CorsLayer},
-    services::{ServeDir, ServeFile},
+use projectors::{Projector, Projectors, SelectedProjector};
+use realraum_backend_common::{
+    auth::{self, Auth, Role, TokensCommand},
+    config::{self as common_config, ConfigArgs},
+    logging, metrics,
+    openapi::Routes,
+    response,
+    routes::{self, Check},
+    server, Error,
 };
+use realraum_backend_projector::{cip, protocol, protocol::ProjectorCommand};
+use scenes::Runs;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tower_http::cors::{Any, CorsLayer};
+
+mod api_v2;
+mod config;
+mod crestron;
+mod dispatch;
+mod driver;
+mod pjlink;
+mod projectors;
+mod scenes;
+mod serial;
+mod state;
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(flatten)]
+    overrides: CliOverrides,
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
 
-pub mod protocol;
+#[derive(Debug, clap::Subcommand)]
+enum CliCommand {
+    /// Manage API tokens
+    #[command(subcommand)]
+    Tokens(TokensCommand),
+}
+
+/// Flags overriding single values of the [`Config`].
+#[derive(Debug, clap::Args, Serialize)]
+struct CliOverrides {
+    /// Address to listen on
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    addr: Option<SocketAddr>,
+    /// Directory of the frontend's static assets
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    static_dir: Option<PathBuf>,
+    /// Address of the default projector's control port
+    #[arg(long)]
+    #[serde(skip)]
+    projector_addr: Option<SocketAddr>,
+}
 
-// This is hard-coded for now
-// TODO make this configurable to account for DHCP
-// Or just use a fixed IP address in the projector like we did
-const IP_ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 33, 41);
+/// State shared by all handlers, which extract the parts they need via [`FromRef`].
+#[derive(Clone)]
+pub struct AppState {
+    config: Arc<Config>,
+    projectors: Projectors,
+    runs: Runs,
+}
 
-// TODO make this configurable
-const PORT: u16 = 41794;
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Projectors {
+    fn from_ref(state: &AppState) -> Self {
+        state.projectors.clone()
+    }
+}
+
+impl FromRef<AppState> for Runs {
+    fn from_ref(state: &AppState) -> Self {
+        state.runs.clone()
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let mut config: Config = common_config::load(&cli.config, &cli.overrides)?;
+    // Projectors are a list, which can't be overridden key by key like the rest
+    if let Some(addr) = cli.overrides.projector_addr {
+        config.projectors[0].set_addr(addr);
+    }
+    if cli.config.print_config {
+        return common_config::print(&config);
+    }
+    if let Some(CliCommand::Tokens(command)) = cli.command {
+        return command.run(&config.auth).await;
+    }
+    let log_handle = logging::init(&config.log)?;
+    let config = Arc::new(config);
+    let metrics = metrics::install()?;
+    let projectors = Projectors::spawn(&config.projectors);
+
     let cors = CorsLayer::new()
-        // allow `GET` and `POST` when accessing the resource
-        .allow_methods([Method::GET, Method::POST])
+        // allow `GET` and `POST` when accessing the resource, and `PUT` for the log level
+        .allow_methods([Method::GET, Method::POST, Method::PUT])
+        // allow JSON bodies and tokens
+        .allow_headers([CONTENT_TYPE, AUTHORIZATION])
         // allow requests from any origin
         .allow_origin(Any);
 
+    let api_v1 = Router::new()
+        .route("/projectors", get(handle_list_projectors))
+        .route("/commands", get(handle_commands))
+        .merge(scenes::routes())
+        .merge(projector_v1(&config))
+        .nest("/projectors/:projector", projector_v1(&config));
+
     let app = Router::new()
-        .layer(cors)
         .nest(
             "/api",
-            Router::new()
-                .route("/", get(api::greeting))
-                .route("/*any", any(api::fallback))
-                .nest(
-                    "/v1",
-                    Router::new()
-                        .nest(
-                            "/input",
-                            Router::new()
-                                .route("/vga_a", get(|| handle_command(input::VGA_A)))
-                                .route("/vga_b", get(|| handle_command(input::VGA_B)))
-                                .route("/composite_1", get(|| handle_command(input::COMPOSITE_1)))
-                                .route("/composite_2", get(|| handle_command(input::COMPOSITE_2)))
-                                .route("/s_video", get(|| handle_command(input::S_VIDEO)))
-                                .route("/hdmi", get(|| handle_command(input::HDMI)))
-                                .route("/source_button", get(|| handle_command(input::SOURCE_BUTTON)))
-                        )
-                        .nest(
-                            "/volume",
-                            Router::new()
-                                .route("/up", get(|| handle_command(volume::UP)))
-                                .route("/down", get(|| handle_command(volume::DOWN)))
-                                .route("/mute", get(|| handle_command(volume::MUTE)))
-                                .route("/un_mute", get(|| handle_command(volume::UN_MUTE))),
-                        )
-                        .nest(
-                            "/power",
-                            Router::new()
-                                .route("/on", get(|| handle_command(power::ON)))
-                                .route("/off", get(|| handle_command(power::OFF))),
-                        )
-                        .nest(
-                            "/menu",
-                            Router::new()
-                                .route("/menu_button", get(|| handle_command(menu::MENU_BUTTON)))
-                                .route("/up", get(|| handle_command(menu::UP)))
-                                .route("/down", get(|| handle_command(menu::DOWN)))
-                                .route("/left", get(|| handle_command(menu::LEFT)))
-                                .route("/right", get(|| handle_command(menu::RIGHT)))
-                                .route("/ok", get(|| handle_command(menu::OK)))
-                                .route("/auto_button", get(|| handle_command(menu::AUTO_BUTTON))),
-                        )
-                        .nest(
-                            "/picture",
-                            Router::new()
-                                .route("/blank", get(|| handle_command(picture::BLANK)))
-                                .route("/un_blank", get(|| handle_command(picture::UN_BLANK)))
-                                .route("/freeze", get(|| handle_command(picture::FREEZE)))
-                                .route("/un_freeze", get(|| handle_command(picture::UN_FREEZE)))
-                                .route("/contrast_up", get(|| handle_command(picture::CONTRAST_UP)))
-                                .route("/contrast_down", get(|| handle_command(picture::CONTRAST_DOWN)))
-                                .route("/brightness_up", get(|| handle_command(picture::BRIGHTNESS_UP)))
-                                .route("/brightness_down", get(|| handle_command(picture::BRIGHTNESS_DOWN)))
-                                .route("/color_up", get(|| handle_command(picture::COLOR_UP)))
-                                .route("/color_down", get(|| handle_command(picture::COLOR_DOWN)))
-                                .route("/sharpness_up", get(|| handle_command(picture::SHARPNESS_UP)))
-                                .route("/sharpness_down", get(|| handle_command(picture::SHARPNESS_DOWN)))
-                        ),
-                ),
+            routes::api(
+                "Welcome to the Realraum Projector-Remote API",
+                env!("CARGO_PKG_VERSION"),
+            )
+            .nest("/v1", api_v1)
+            .nest("/v2", api_v2::routes().into()),
         )
-        .nest_service(
-            "/",
-            ServeDir::new("dist").not_found_service(ServeFile::new("dist/index.html")),
-        )
-        // .with_state(connection)
-        ;
-
-    let addr: SocketAddr = env::var("R3_PROJECTOR_ADDR")
-        .map_err(|_| ())
-        .and_then(|s| s.parse().map_err(|_| ()))
-        .unwrap_or_else(|_| "0.0.0.0:4201".parse().unwrap());
+        .merge(routes::health({
+            let projectors = projectors.clone();
+            move || readiness(projectors.clone())
+        }))
+        .merge(metrics::routes(metrics))
+        .merge(logging::routes(log_handle).route_layer(auth::require(Role::Admin)))
+        .route_layer(axum::middleware::from_fn(metrics::track_requests))
+        .nest_service("/", routes::static_files(&config.static_dir))
+        .with_state(AppState {
+            config: config.clone(),
+            projectors,
+            runs: Runs::default(),
+        })
+        .layer(axum::middleware::from_fn_with_state(
+            Auth::new(config.auth.clone()),
+            auth::authenticate,
+        ))
+        // Outermost, so preflights and rejected requests get the CORS headers too
+        .layer(cors);
+    let app = logging::trace_requests(app);
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?;
+    server::serve(app, config.addr).await
+}
 
-    Ok(())
+/// The v1 routes controlling one projector.
+///
+/// v2 only takes `POST`, so prefetchers and crawlers can't control the projector,
+/// the v1 `GET` commands are deprecated and only served if `policies.legacy_get_routes` is set.
+fn projector_v1(config: &Config) -> Router<AppState> {
+    let mut router = Router::new()
+        .route("/status", get(handle_status))
+        .merge(dispatch::routes())
+        .merge(scenes::projector_routes());
+    if config.policies.legacy_get_routes {
+        router = router.merge(
+            Router::from(commands(get_command))
+                .route_layer(axum::middleware::from_fn(routes::deprecated)),
+        );
+    }
+    router
 }
 
-// async fn handle_command(command: Command, connection: Arc<Mutex<TcpStream>>) -> Json<Value> {
-//     connection
-//         .lock()
-//         .unwrap()
-//         .write_all(dbg!(&commands::power::ON))
-//         .await
-//         .unwrap();
-//     // stream.write_all(&commands::power::OFF).await.unwrap();
+/// Route of a command.
+type CommandRoute = fn(ProjectorCommand) -> MethodRouter<AppState>;
+
+/// Routes of all [`ProjectorCommand::ALL`] on `/{group}/{name}`, using `route` to build each route.
+fn commands(route: CommandRoute) -> Routes<AppState> {
+    let mut member = Routes::new();
+    let mut admin = Routes::new();
+    for command in ProjectorCommand::ALL {
+        let path = format!("/{command}");
+        if required_role(command) == Role::Admin {
+            admin = admin.route(&path, route(command));
+        } else {
+            member = member.route(&path, route(command));
+        }
+    }
+
+    member
+        .route_layer(auth::require(Role::Member))
+        .merge(admin.route_layer(auth::require(Role::Admin)))
+}
 
-//     Json(json!({ "status": "ok", "message": "Killed all mplayer instances" }))
-// }
+/// Powering off is reserved for admins, members may send every other command.
+fn required_role(command: ProjectorCommand) -> Role {
+    match command {
+        ProjectorCommand::PowerOff => Role::Admin,
+        _ => Role::Member,
+    }
+}
 
-async fn handle_command(command: Command) -> Json<Value> {
-    let mut connection = TcpStream::connect((IP_ADDRESS, PORT)).await.unwrap();
+/// Deprecated route sending `command` to the projector on `GET`.
+fn get_command(command: ProjectorCommand) -> MethodRouter<AppState> {
+    get(move |SelectedProjector(projector)| handle_command(projector, command.into()))
+}
 
-    connection.write_all(&command).await.unwrap();
-    // stream.write_all(&commands::power::OFF).await.unwrap();
+async fn handle_command(projector: Projector, operation: Operation) -> Result<Json<Value>, Error> {
+    let sent = send(&projector, operation).await?;
 
-    Json(json!({
-        "status": "ok",
+    Ok(response::ok(json!({
         "message": "Command sent successfully",
-        "command": &command
-    }))
+        "command": operation.command().to_string(),
+        "bytes": sent.bytes,
+        "confirmed": sent.delivery == Delivery::Confirmed,
+    })))
 }
 
-mod api {
+/// What we know about the projector on `/api/v1/status`, each value with its `source` and `confidence`.
+async fn handle_status(SelectedProjector(projector): SelectedProjector) -> Json<Value> {
+    response::ok(json!(projector.state()))
+}
 
-    use super::*;
+/// A command as listed by `/api/v1/commands`.
+#[derive(Debug, Serialize)]
+struct CatalogEntry {
+    group: &'static str,
+    group_label: &'static str,
+    name: &'static str,
+    label: &'static str,
+    /// Who may send it
+    role: Role,
+}
 
-    pub async fn fallback(_: Uri) -> (StatusCode, Json<Value>) {
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({ "status": "error", "message": "No such API route" })),
-        )
-    }
+/// Every command on `/api/v1/commands`, for building a remote.
+async fn handle_commands() -> Json<Value> {
+    let commands: Vec<_> = ProjectorCommand::ALL
+        .into_iter()
+        .map(|command| CatalogEntry {
+            group: command.group().name(),
+            group_label: command.group().label(),
+            name: command.name(),
+            label: command.label(),
+            role: required_role(command),
+        })
+        .collect();
+    response::ok(json!({ "commands": commands }))
+}
 
-    pub async fn greeting(_: Uri) -> (StatusCode, Json<Value>) {
-        (
-            StatusCode::OK,
-            Json(json!({
-                "status": "ok",
-                "message": "Welcome to the Realraum Projector-Remote API",
-                "server_version": env!("CARGO_PKG_VERSION"),
-            })),
-        )
-    }
+/// All projectors on `/api/v1/projectors`.
+async fn handle_list_projectors(State(projectors): State<Projectors>) -> Json<Value> {
+    response::ok(json!({ "projectors": projectors.list() }))
+}
+
+/// Sends `operation` to the projector, failing with `502` if it's unreachable.
+#[tracing::instrument(skip(projector), fields(projector = projector.name()))]
+async fn send(projector: &Projector, operation: Operation) -> Result<Sent, Error> {
+    let result = projector.execute(operation).await;
+    let outcome = match &result {
+        Ok(sent) if sent.delivery == Delivery::Confirmed => "confirmed",
+        Ok(_) => "unconfirmed",
+        Err(driver::Error::Unsupported(_)) => "unsupported",
+        Err(_) => "failure",
+    };
+    ::metrics::counter!(
+        "projector_commands_total",
+        "projector" => projector.name().to_string(),
+        "result" => outcome
+    )
+    .increment(1);
+
+    result.map_err(|e| {
+        tracing::warn!("Failed to send command to the projector: {e}");
+        match e {
+            driver::Error::Busy => Error::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "projector_busy",
+                "Too many commands waiting for the projector, try again",
+            ),
+            driver::Error::Unsupported(_) => Error::new(
+                StatusCode::NOT_IMPLEMENTED,
+                "unsupported",
+                "The projector's protocol has no such command",
+            ),
+            driver::Error::Refused(reason) => Error::new(
+                StatusCode::CONFLICT,
+                "projector_refused",
+                format!("The projector refused the command: {reason}"),
+            ),
+            _ => Error::new(
+                StatusCode::BAD_GATEWAY,
+                "projector_unreachable",
+                "Projector unreachable",
+            ),
+        }
+    })
+}
+
+/// The projector backend is ready while the connections to all projectors are up.
+async fn readiness(projectors: Projectors) -> Vec<Check> {
+    projectors
+        .iter()
+        .map(|projector| {
+            let result = match projector.is_connected() {
+                true => Ok(()),
+                false => Err("not connected, reconnecting"),
+            };
+            Check::new(format!("projector:{}", projector.name()), result)
+        })
+        .collect()
 }
@@ -0,0 +1,576 @@
+//! The [`ProjectorDriver`] for projectors speaking [PJLink](https://pjlink.jbmia.or.jp/english/),
+//! class 1 or 2, on TCP port 4352.
+//!
+//! PJLink projectors close idle connections after a few seconds, so every exchange opens
+//! its own connection. Exchanges take turns on a lock, so commands from several clients
+//! never interleave. Projectors with a password set greet us with a random number,
+//! and expect the MD5 digest of that number and the password in front of the first command.
+//!
+//! PJLink doesn't report changes by itself, a background task asks for the projector's
+//! state every `heartbeat_secs` instead. An `OK` only says the projector took a command,
+//! e.g. `POWR 1` is acknowledged right away but the projector then warms up for a while,
+//! so only these polls confirm values.
+
+use std::{
+    io,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::{watch, Mutex},
+    time,
+};
+use tracing::Instrument;
+
+use crate::{
+    config::ProjectorConfig,
+    driver::{Delivery, Direction, Error, Operation, ProjectorDriver, Sent},
+    state::{Input, Power, PowerState, ProjectorState, Source},
+};
+
+/// How long the projector may take to answer a command.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Handle to the projector, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct PjLinkDriver {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    host: String,
+    port: u16,
+    password: Option<String>,
+    connect_timeout: Duration,
+    write_timeout: Duration,
+    /// The projector's class once we asked for it, held during an exchange
+    class: Mutex<Option<u8>>,
+    state: watch::Sender<ProjectorState>,
+}
+
+impl PjLinkDriver {
+    /// Spawns the task polling the projector's state, which connects right away.
+    pub fn spawn(config: ProjectorConfig) -> Self {
+        let inner = Arc::new(Inner {
+            host: config.host.clone(),
+            port: config.port(),
+            password: config.password.clone(),
+            connect_timeout: Duration::from_secs(config.connect_timeout_secs),
+            write_timeout: Duration::from_secs(config.write_timeout_secs),
+            class: Mutex::new(None),
+            state: watch::channel(ProjectorState::default()).0,
+        });
+        tokio::spawn(
+            poll(
+                Arc::downgrade(&inner),
+                Duration::from_secs(config.heartbeat_secs.max(1)),
+            )
+            .instrument(tracing::info_span!("projector", name = %config.name)),
+        );
+
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl ProjectorDriver for PjLinkDriver {
+    async fn execute(&self, operation: Operation) -> Result<Sent, Error> {
+        let (class, command, param) = command(operation).ok_or(Error::Unsupported(operation))?;
+
+        let mut known_class = self.inner.class.lock().await;
+        let result = async {
+            let mut connection = self.inner.connect().await?;
+            if detect_class(&mut connection, &mut known_class).await? < class {
+                return Err(Error::Unsupported(operation));
+            }
+            let reply = connection.request(class, command, param).await?;
+            check(operation, &reply)
+        }
+        .await;
+        self.inner.track(&result);
+
+        let delivery = result?;
+        // Until the next poll tells what the projector made of it
+        self.inner
+            .state
+            .send_modify(|state| state.apply(operation, Source::Command));
+        Ok(Sent {
+            bytes: line(class, command, param).into_bytes(),
+            delivery,
+        })
+    }
+
+    fn subscribe(&self) -> watch::Receiver<ProjectorState> {
+        self.inner.state.subscribe()
+    }
+}
+
+impl Inner {
+    async fn connect(&self) -> Result<Connection, Error> {
+        let stream = time::timeout(
+            self.connect_timeout,
+            TcpStream::connect((self.host.as_str(), self.port)),
+        )
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))
+        .and_then(|connected| connected)
+        .map_err(Error::Unreachable)?;
+
+        let mut connection = Connection {
+            stream: BufReader::new(stream),
+            digest: None,
+            write_timeout: self.write_timeout,
+        };
+        let greeting = connection.read_line().await?;
+        connection.digest = match greeting.strip_prefix("PJLINK ") {
+            Some("0") => None,
+            Some(challenge) => match (challenge.strip_prefix("1 "), &self.password) {
+                (Some(random), Some(password)) => Some(digest(random, password)),
+                (Some(_), None) => {
+                    return Err(Error::Refused("The projector requires a password".into()))
+                }
+                (None, _) => return Err(invalid(&greeting)),
+            },
+            None => return Err(invalid(&greeting)),
+        };
+        Ok(connection)
+    }
+
+    /// Asks the projector for everything we track.
+    async fn poll(&self) {
+        let mut known_class = self.class.lock().await;
+        let result = async {
+            let mut connection = self.connect().await?;
+            let class = detect_class(&mut connection, &mut known_class).await?;
+            let power = connection.request(1, "POWR", "?").await?;
+            let input = connection.request(1, "INPT", "?").await?;
+            let mute = connection.request(1, "AVMT", "?").await?;
+            let freeze = match class {
+                2.. => Some(connection.request(2, "FREZ", "?").await?),
+                _ => None,
+            };
+
+            self.state.send_modify(|state| {
+                state.power.set(parse_power(&power), Source::Feedback);
+                state.input.set(parse_input(&input), Source::Feedback);
+                if let Some((blanked, muted)) = parse_mute(&mute) {
+                    state.apply(Operation::Blank(blanked), Source::Feedback);
+                    state.apply(Operation::Mute(muted), Source::Feedback);
+                }
+                match freeze.as_deref() {
+                    Some("1") => state.apply(Operation::Freeze(true), Source::Feedback),
+                    Some("0") => state.apply(Operation::Freeze(false), Source::Feedback),
+                    _ => {}
+                }
+            });
+            Ok(())
+        }
+        .await;
+        self.track(&result);
+    }
+
+    /// Records whether the projector answered, it's disconnected if we couldn't reach it.
+    fn track<T>(&self, result: &Result<T, Error>) {
+        let connected = !matches!(result, Err(Error::Unreachable(_)));
+        self.state.send_if_modified(|state| {
+            let changed = state.connected != connected;
+            if changed {
+                match result {
+                    Err(e) => tracing::warn!("Lost the projector: {e}"),
+                    Ok(_) => tracing::info!("Projector reachable"),
+                }
+            }
+            state.set_connected(connected);
+            changed
+        });
+    }
+}
+
+/// Polls the projector every `period`, until the driver is dropped.
+async fn poll(inner: Weak<Inner>, period: Duration) {
+    let mut interval = time::interval(period);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(inner) = inner.upgrade() else {
+            break;
+        };
+        inner.poll().await;
+    }
+}
+
+/// A connection after the projector's greeting.
+struct Connection {
+    stream: BufReader<TcpStream>,
+    /// Authenticates the first command, if the projector asked for a password
+    digest: Option<String>,
+    write_timeout: Duration,
+}
+
+impl Connection {
+    /// Sends `%{class}{command} {param}` and returns the projector's answer,
+    /// e.g. `OK`, `ERR3` or the value asked for.
+    async fn request(&mut self, class: u8, command: &str, param: &str) -> Result<String, Error> {
+        let mut request = self.digest.take().unwrap_or_default();
+        request.push_str(&line(class, command, param));
+        time::timeout(
+            self.write_timeout,
+            self.stream.get_mut().write_all(request.as_bytes()),
+        )
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "write timed out"))
+        .and_then(|written| written)
+        .map_err(Error::Unreachable)?;
+
+        let reply = self.read_line().await?;
+        if reply == "PJLINK ERRA" {
+            return Err(Error::Refused("Wrong PJLink password".into()));
+        }
+        match reply.strip_prefix(&format!("%{class}{command}=")) {
+            Some(answer) => Ok(answer.to_string()),
+            None => Err(invalid(&reply)),
+        }
+    }
+
+    /// Reads one `\r` terminated line, without the `\r`.
+    async fn read_line(&mut self) -> Result<String, Error> {
+        let mut line = Vec::new();
+        let read = time::timeout(REPLY_TIMEOUT, self.stream.read_until(b'\r', &mut line))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no answer from the projector"))
+            .and_then(|read| read)
+            .map_err(Error::Unreachable)?;
+        if read == 0 || line.pop() != Some(b'\r') {
+            return Err(Error::Unreachable(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+}
+
+/// The projector's class, asking for it unless we know it already.
+async fn detect_class(connection: &mut Connection, known: &mut Option<u8>) -> Result<u8, Error> {
+    if let Some(class) = *known {
+        return Ok(class);
+    }
+    let class = match connection.request(1, "CLSS", "?").await?.as_str() {
+        "2" => 2,
+        _ => 1,
+    };
+    *known = Some(class);
+    Ok(class)
+}
+
+/// The class, command and parameter performing `operation`, if PJLink has one.
+fn command(operation: Operation) -> Option<(u8, &'static str, &'static str)> {
+    Some(match operation {
+        Operation::Power(Power::On) => (1, "POWR", "1"),
+        Operation::Power(Power::Off) => (1, "POWR", "0"),
+        Operation::Input(input) => (1, "INPT", input_code(input)),
+        Operation::Mute(true) => (1, "AVMT", "21"),
+        Operation::Mute(false) => (1, "AVMT", "20"),
+        Operation::Blank(true) => (1, "AVMT", "11"),
+        Operation::Blank(false) => (1, "AVMT", "10"),
+        Operation::Freeze(true) => (2, "FREZ", "1"),
+        Operation::Freeze(false) => (2, "FREZ", "0"),
+        Operation::Volume(Direction::Up) => (2, "SVOL", "1"),
+        Operation::Volume(Direction::Down) => (2, "SVOL", "0"),
+        Operation::NextInput | Operation::Adjust(..) | Operation::Menu(_) => return None,
+    })
+}
+
+/// The input's number, its type as first digit: 1 RGB, 2 video, 3 digital.
+fn input_code(input: Input) -> &'static str {
+    match input {
+        Input::VgaA => "11",
+        Input::VgaB => "12",
+        Input::Composite1 => "21",
+        Input::Composite2 => "22",
+        Input::SVideo => "23",
+        Input::Hdmi => "31",
+    }
+}
+
+fn line(class: u8, command: &str, param: &str) -> String {
+    format!("%{class}{command} {param}\r")
+}
+
+/// How the projector answered an operation.
+fn check(operation: Operation, answer: &str) -> Result<Delivery, Error> {
+    match answer {
+        "OK" => Ok(Delivery::Confirmed),
+        "ERR1" | "ERR2" => Err(Error::Unsupported(operation)),
+        "ERR3" => Err(Error::Refused(
+            "Unavailable right now, e.g. while warming up".into(),
+        )),
+        "ERR4" => Err(Error::Refused("Projector failure".into())),
+        _ => Err(Error::Refused(format!("Unexpected answer {answer:?}"))),
+    }
+}
+
+fn parse_power(answer: &str) -> Option<PowerState> {
+    match answer {
+        "0" => Some(PowerState::Off),
+        "1" => Some(PowerState::On),
+        "2" => Some(PowerState::CoolingDown),
+        "3" => Some(PowerState::WarmingUp),
+        _ => None,
+    }
+}
+
+fn parse_input(answer: &str) -> Option<Input> {
+    [
+        Input::VgaA,
+        Input::VgaB,
+        Input::Composite1,
+        Input::Composite2,
+        Input::SVideo,
+        Input::Hdmi,
+    ]
+    .into_iter()
+    .find(|&input| input_code(input) == answer)
+}
+
+/// Whether video and audio are muted, which is how PJLink calls blanked and muted.
+fn parse_mute(answer: &str) -> Option<(bool, bool)> {
+    match answer {
+        "11" => Some((true, false)),
+        "21" => Some((false, true)),
+        "31" => Some((true, true)),
+        "30" => Some((false, false)),
+        _ => None,
+    }
+}
+
+fn digest(random: &str, password: &str) -> String {
+    format!("{:x}", md5::compute(format!("{random}{password}")))
+}
+
+fn invalid(line: &str) -> Error {
+    Error::Unreachable(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Not a PJLink projector, it sent {line:?}"),
+    ))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::{
+        net::TcpListener,
+        sync::mpsc::{self, UnboundedReceiver},
+    };
+
+    use super::*;
+    use crate::{config::Driver, state::Confidence};
+
+    pub(crate) fn config(addr: SocketAddr, password: Option<&str>) -> ProjectorConfig {
+        let mut config = ProjectorConfig {
+            driver: Driver::PjLink,
+            password: password.map(Into::into),
+            heartbeat_secs: 3600,
+            ..Default::default()
+        };
+        config.set_addr(addr);
+        config
+    }
+
+    /// Serves PJLink on `listener`, greeting with `greeting` and answering with `answer`.
+    /// Returns the lines the driver sent.
+    pub(crate) fn mock(
+        listener: TcpListener,
+        greeting: &'static str,
+        mut answer: impl FnMut(&str) -> String + Send + 'static,
+    ) -> UnboundedReceiver<String> {
+        let (lines, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                stream
+                    .get_mut()
+                    .write_all(format!("{greeting}\r").as_bytes())
+                    .await
+                    .unwrap();
+                loop {
+                    let mut line = Vec::new();
+                    if stream.read_until(b'\r', &mut line).await.unwrap() == 0 {
+                        break;
+                    }
+                    line.pop();
+                    let line = String::from_utf8(line).unwrap();
+                    let reply = answer(&line);
+                    // Tests may not care about the lines
+                    let _ = lines.send(line);
+                    stream
+                        .get_mut()
+                        .write_all(format!("{reply}\r").as_bytes())
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+        received
+    }
+
+    /// A class 1 projector that's on, showing HDMI with audio muted.
+    fn class_1(line: &str) -> String {
+        let (command, param) = line.split_once(' ').unwrap();
+        let answer = match (command, param) {
+            ("%1CLSS", "?") => "1",
+            ("%1POWR", "?") => "1",
+            ("%1INPT", "?") => "31",
+            ("%1AVMT", "?") => "21",
+            ("%1POWR" | "%1INPT" | "%1AVMT", _) => "OK",
+            _ => "ERR1",
+        };
+        format!("{command}={answer}")
+    }
+
+    /// A class 2 projector that's warming up, refusing commands.
+    fn class_2(line: &str) -> String {
+        let (command, param) = line.split_once(' ').unwrap();
+        let answer = match (command, param) {
+            ("%1CLSS", "?") => "2",
+            ("%1POWR", "?") => "3",
+            _ => "ERR3",
+        };
+        format!("{command}={answer}")
+    }
+
+    #[test]
+    fn digests_like_the_spec() {
+        assert_eq!(
+            digest("498e4a67", "JBMIAProjectorLink"),
+            "5d8409bc1c3fa39749434aa3a5c38682"
+        );
+    }
+
+    #[test]
+    fn parses_answers() {
+        assert_eq!(parse_power("3"), Some(PowerState::WarmingUp));
+        assert_eq!(parse_power("2"), Some(PowerState::CoolingDown));
+        assert_eq!(parse_power("ERR3"), None);
+        assert_eq!(parse_input("23"), Some(Input::SVideo));
+        assert_eq!(parse_mute("31"), Some((true, true)));
+    }
+
+    #[tokio::test]
+    async fn sends_commands_and_polls_the_state() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let projector = PjLinkDriver::spawn(config(listener.local_addr().unwrap(), None));
+        let mut lines = mock(listener, "PJLINK 0", class_1);
+
+        let mut state = projector.subscribe();
+        state
+            .wait_for(|state| state.power.value.is_some())
+            .await
+            .unwrap();
+        let state = projector.state();
+        assert!(state.connected);
+        assert_eq!(state.power.value, Some(PowerState::On));
+        assert_eq!(state.power.confidence, Confidence::Confirmed);
+        assert_eq!(state.input.value, Some(Input::Hdmi));
+        assert_eq!(state.muted.value, Some(true));
+        assert_eq!(state.blanked.value, Some(false));
+        for line in ["%1CLSS ?", "%1POWR ?", "%1INPT ?", "%1AVMT ?"] {
+            assert_eq!(lines.recv().await.unwrap(), line);
+        }
+
+        let sent = projector
+            .execute(Operation::Input(Input::VgaB))
+            .await
+            .unwrap();
+        assert_eq!(sent.bytes, b"%1INPT 12\r");
+        assert_eq!(sent.delivery, Delivery::Confirmed);
+        assert_eq!(lines.recv().await.unwrap(), "%1INPT 12");
+        // Taken, but only the next poll tells whether it switched
+        let input = projector.state().input;
+        assert_eq!(input.value, Some(Input::VgaB));
+        assert_eq!(input.confidence, Confidence::Assumed);
+
+        // Class 1 has no freeze, and PJLink no menu at all
+        assert!(matches!(
+            projector.execute(Operation::Freeze(true)).await,
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            projector
+                .execute(Operation::Menu(crate::driver::MenuButton::Ok))
+                .await,
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn authenticates_the_first_command() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _projector = PjLinkDriver::spawn(config(
+            listener.local_addr().unwrap(),
+            Some("JBMIAProjectorLink"),
+        ));
+        let mut lines = mock(listener, "PJLINK 1 498e4a67", |line| {
+            match line.strip_prefix("5d8409bc1c3fa39749434aa3a5c38682") {
+                Some(line) => class_1(line),
+                None if line.starts_with('%') => class_1(line),
+                None => "PJLINK ERRA".to_string(),
+            }
+        });
+
+        assert_eq!(
+            lines.recv().await.unwrap(),
+            "5d8409bc1c3fa39749434aa3a5c38682%1CLSS ?"
+        );
+        assert_eq!(lines.recv().await.unwrap(), "%1POWR ?");
+    }
+
+    #[tokio::test]
+    async fn fails_with_the_wrong_password() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let projector = PjLinkDriver::spawn(config(listener.local_addr().unwrap(), Some("wrong")));
+        mock(listener, "PJLINK 1 498e4a67", |_| "PJLINK ERRA".to_string());
+
+        assert!(matches!(
+            projector.execute(Operation::Power(Power::On)).await,
+            Err(Error::Refused(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reports_refused_commands() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let projector = PjLinkDriver::spawn(config(listener.local_addr().unwrap(), None));
+        mock(listener, "PJLINK 0", class_2);
+
+        assert!(matches!(
+            projector.execute(Operation::Freeze(true)).await,
+            Err(Error::Refused(_))
+        ));
+        let mut state = projector.subscribe();
+        state
+            .wait_for(|state| state.power.value.is_some())
+            .await
+            .unwrap();
+        assert_eq!(projector.state().power.value, Some(PowerState::WarmingUp));
+        assert_eq!(projector.state().frozen.value, None);
+    }
+
+    #[tokio::test]
+    async fn fails_while_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let projector = PjLinkDriver::spawn(config(addr, None));
+        assert!(matches!(
+            projector.execute(Operation::Power(Power::On)).await,
+            Err(Error::Unreachable(_))
+        ));
+        assert!(!projector.is_connected());
+    }
+}
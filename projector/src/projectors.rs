@@ -0,0 +1,161 @@
+//! All configured projectors, addressed by name on `/projectors/{name}/...`.
+//!
+//! The routes without a projector name are aliases for the default projector,
+//! the first one in the config, so existing clients keep working.
+
+use std::{collections::HashMap, ops::Deref, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Path},
+    http::request::Parts,
+};
+use hyper::StatusCode;
+use realraum_backend_common::Error;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    config::{Driver, ProjectorConfig},
+    crestron::CrestronDriver,
+    driver::ProjectorDriver,
+    pjlink::PjLinkDriver,
+    serial::SerialDriver,
+};
+
+/// A projector's driver together with its name, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Projector {
+    name: Arc<str>,
+    driver: Arc<dyn ProjectorDriver>,
+}
+
+impl Projector {
+    /// Starts the driver `config` asks for.
+    pub fn spawn(config: ProjectorConfig) -> Self {
+        let name = config.name.as_str().into();
+        let driver: Arc<dyn ProjectorDriver> = match config.driver {
+            Driver::Crestron => Arc::new(CrestronDriver::spawn(config)),
+            Driver::PjLink => Arc::new(PjLinkDriver::spawn(config)),
+            Driver::Serial => Arc::new(SerialDriver::spawn(config)),
+        };
+        Self { name, driver }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Deref for Projector {
+    type Target = dyn ProjectorDriver;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.driver
+    }
+}
+
+/// Connections to all projectors, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Projectors(Arc<Vec<(ProjectorConfig, Projector)>>);
+
+/// A projector as listed by the API.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProjectorInfo {
+    pub name: String,
+    pub driver: Driver,
+    /// Hostname or IP address of projectors on the network
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Serial device of projectors on a serial line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Whether the routes without `/projectors/{name}` control this one
+    pub default: bool,
+    pub connected: bool,
+}
+
+impl Projectors {
+    /// Connects to all `projectors`, of which there must be at least one.
+    pub fn spawn(projectors: &[ProjectorConfig]) -> Self {
+        assert!(!projectors.is_empty(), "No projectors configured");
+        Self(Arc::new(
+            projectors
+                .iter()
+                .map(|config| (config.clone(), Projector::spawn(config.clone())))
+                .collect(),
+        ))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Projector> {
+        self.0
+            .iter()
+            .find(|(config, _)| config.name == name)
+            .map(|(_, projector)| projector)
+    }
+
+    /// The projector the routes without a name control.
+    pub fn default_projector(&self) -> &Projector {
+        &self.0[0].1
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Projector> {
+        self.0.iter().map(|(_, projector)| projector)
+    }
+
+    pub fn list(&self) -> Vec<ProjectorInfo> {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, (config, projector))| {
+                let network = config.driver != Driver::Serial;
+                ProjectorInfo {
+                    name: config.name.clone(),
+                    driver: config.driver,
+                    host: network.then(|| config.host.clone()),
+                    port: network.then(|| config.port()),
+                    device: (!network).then(|| config.serial.device.clone()),
+                    default: i == 0,
+                    connected: projector.is_connected(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Extracts the projector named by the `:projector` route param, or the default projector
+/// on routes without one. Unknown names are rejected with `404`.
+#[derive(Debug, Clone)]
+pub struct SelectedProjector(pub Projector);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SelectedProjector
+where
+    S: Send + Sync,
+    Projectors: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let projectors = Projectors::from_ref(state);
+        let params = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map(|Path(params)| params)
+            .unwrap_or_default();
+
+        let projector = match params.get("projector") {
+            None => Some(projectors.default_projector()),
+            Some(name) => projectors.get(name),
+        };
+        match projector {
+            Some(projector) => Ok(SelectedProjector(projector.clone())),
+            None => Err(Error::new(
+                StatusCode::NOT_FOUND,
+                "unknown_projector",
+                "No projector with that name",
+            )),
+        }
+    }
+}
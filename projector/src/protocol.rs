@@ -7,6 +7,11 @@
 //! to find (some of) the binary API used by the projector to communicate with the device by sending the same requests
 //! the Creston UI would send.
 
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use lazy_static::lazy_static;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
 /// The protocol uses binary values to communicate with the projector.
 ///
 /// This module contains constants representing the individual commands, as well as the prefix and suffix bytes.
@@ -106,3 +111,374 @@ const fn make_command(command: u8, suffix: u8) -> Command {
 
     payload
 }
+
+/// The groups the commands are sorted into, as on the remote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommandGroup {
+    Input,
+    Volume,
+    Power,
+    Menu,
+    Picture,
+}
+
+impl CommandGroup {
+    /// The name in routes like `/{group}/{name}`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            CommandGroup::Input => "input",
+            CommandGroup::Volume => "volume",
+            CommandGroup::Power => "power",
+            CommandGroup::Menu => "menu",
+            CommandGroup::Picture => "picture",
+        }
+    }
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            CommandGroup::Input => "Input",
+            CommandGroup::Volume => "Volume",
+            CommandGroup::Power => "Power",
+            CommandGroup::Menu => "Menu",
+            CommandGroup::Picture => "Picture",
+        }
+    }
+}
+
+/// Every command we know, the typed version of the [`commands`] constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProjectorCommand {
+    InputVgaA,
+    InputVgaB,
+    InputComposite1,
+    InputComposite2,
+    InputSVideo,
+    InputHdmi,
+    InputSourceButton,
+    VolumeUp,
+    VolumeDown,
+    VolumeMute,
+    VolumeUnMute,
+    PowerOn,
+    PowerOff,
+    MenuButton,
+    MenuUp,
+    MenuDown,
+    MenuLeft,
+    MenuRight,
+    MenuOk,
+    MenuAutoButton,
+    PictureBlank,
+    PictureUnBlank,
+    PictureFreeze,
+    PictureUnFreeze,
+    PictureContrastUp,
+    PictureContrastDown,
+    PictureBrightnessUp,
+    PictureBrightnessDown,
+    PictureColorUp,
+    PictureColorDown,
+    PictureSharpnessUp,
+    PictureSharpnessDown,
+}
+
+/// Each command with its group, name, label and bytes.
+const CATALOG: [(ProjectorCommand, CommandGroup, &str, &str, Command); 32] = {
+    use CommandGroup::*;
+    use ProjectorCommand::*;
+    [
+        (InputVgaA, Input, "vga_a", "VGA A", commands::input::VGA_A),
+        (InputVgaB, Input, "vga_b", "VGA B", commands::input::VGA_B),
+        (
+            InputComposite1,
+            Input,
+            "composite_1",
+            "Composite 1",
+            commands::input::COMPOSITE_1,
+        ),
+        (
+            InputComposite2,
+            Input,
+            "composite_2",
+            "Composite 2",
+            commands::input::COMPOSITE_2,
+        ),
+        (
+            InputSVideo,
+            Input,
+            "s_video",
+            "S-Video",
+            commands::input::S_VIDEO,
+        ),
+        (InputHdmi, Input, "hdmi", "HDMI", commands::input::HDMI),
+        (
+            InputSourceButton,
+            Input,
+            "source_button",
+            "Next source",
+            commands::input::SOURCE_BUTTON,
+        ),
+        (VolumeUp, Volume, "up", "Volume up", commands::volume::UP),
+        (
+            VolumeDown,
+            Volume,
+            "down",
+            "Volume down",
+            commands::volume::DOWN,
+        ),
+        (VolumeMute, Volume, "mute", "Mute", commands::volume::MUTE),
+        (
+            VolumeUnMute,
+            Volume,
+            "un_mute",
+            "Unmute",
+            commands::volume::UN_MUTE,
+        ),
+        (PowerOn, Power, "on", "Power on", commands::power::ON),
+        (PowerOff, Power, "off", "Power off", commands::power::OFF),
+        (
+            MenuButton,
+            Menu,
+            "menu_button",
+            "Menu",
+            commands::menu::MENU_BUTTON,
+        ),
+        (MenuUp, Menu, "up", "Up", commands::menu::UP),
+        (MenuDown, Menu, "down", "Down", commands::menu::DOWN),
+        (MenuLeft, Menu, "left", "Left", commands::menu::LEFT),
+        (MenuRight, Menu, "right", "Right", commands::menu::RIGHT),
+        (MenuOk, Menu, "ok", "OK", commands::menu::OK),
+        (
+            MenuAutoButton,
+            Menu,
+            "auto_button",
+            "Auto adjust",
+            commands::menu::AUTO_BUTTON,
+        ),
+        (
+            PictureBlank,
+            Picture,
+            "blank",
+            "Blank",
+            commands::picture::BLANK,
+        ),
+        (
+            PictureUnBlank,
+            Picture,
+            "un_blank",
+            "Unblank",
+            commands::picture::UN_BLANK,
+        ),
+        (
+            PictureFreeze,
+            Picture,
+            "freeze",
+            "Freeze",
+            commands::picture::FREEZE,
+        ),
+        (
+            PictureUnFreeze,
+            Picture,
+            "un_freeze",
+            "Unfreeze",
+            commands::picture::UN_FREEZE,
+        ),
+        (
+            PictureContrastUp,
+            Picture,
+            "contrast_up",
+            "Contrast up",
+            commands::picture::CONTRAST_UP,
+        ),
+        (
+            PictureContrastDown,
+            Picture,
+            "contrast_down",
+            "Contrast down",
+            commands::picture::CONTRAST_DOWN,
+        ),
+        (
+            PictureBrightnessUp,
+            Picture,
+            "brightness_up",
+            "Brightness up",
+            commands::picture::BRIGHTNESS_UP,
+        ),
+        (
+            PictureBrightnessDown,
+            Picture,
+            "brightness_down",
+            "Brightness down",
+            commands::picture::BRIGHTNESS_DOWN,
+        ),
+        (
+            PictureColorUp,
+            Picture,
+            "color_up",
+            "Color up",
+            commands::picture::COLOR_UP,
+        ),
+        (
+            PictureColorDown,
+            Picture,
+            "color_down",
+            "Color down",
+            commands::picture::COLOR_DOWN,
+        ),
+        (
+            PictureSharpnessUp,
+            Picture,
+            "sharpness_up",
+            "Sharpness up",
+            commands::picture::SHARPNESS_UP,
+        ),
+        (
+            PictureSharpnessDown,
+            Picture,
+            "sharpness_down",
+            "Sharpness down",
+            commands::picture::SHARPNESS_DOWN,
+        ),
+    ]
+};
+
+impl ProjectorCommand {
+    /// Every command, grouped like on the remote.
+    pub const ALL: [ProjectorCommand; 32] = {
+        let mut all = [ProjectorCommand::PowerOn; 32];
+        let mut i = 0;
+        while i < CATALOG.len() {
+            all[i] = CATALOG[i].0;
+            i += 1;
+        }
+        all
+    };
+
+    fn entry(
+        self,
+    ) -> &'static (
+        ProjectorCommand,
+        CommandGroup,
+        &'static str,
+        &'static str,
+        Command,
+    ) {
+        &CATALOG[self as usize]
+    }
+
+    pub fn group(self) -> CommandGroup {
+        self.entry().1
+    }
+
+    /// The name within its group, e.g. `on` of `power/on`.
+    pub fn name(self) -> &'static str {
+        self.entry().2
+    }
+
+    /// A name for people, e.g. `Power on`.
+    pub fn label(self) -> &'static str {
+        self.entry().3
+    }
+
+    /// The bytes pressing the command's button.
+    pub fn encode(self) -> Command {
+        self.entry().4
+    }
+
+    /// The command whose button `bytes` press.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        CATALOG
+            .iter()
+            .find(|entry| entry.4 == bytes)
+            .map(|entry| entry.0)
+    }
+
+    /// The command named `{group}/{name}`.
+    pub fn from_name(group: &str, name: &str) -> Option<Self> {
+        BY_NAME.get(&(group, name)).copied()
+    }
+}
+
+lazy_static! {
+    /// Every command by its group and name.
+    static ref BY_NAME: HashMap<(&'static str, &'static str), ProjectorCommand> = ProjectorCommand::ALL
+        .into_iter()
+        .map(|command| ((command.group().name(), command.name()), command))
+        .collect();
+}
+
+/// Parses `{group}/{name}`, like `power/on`.
+impl FromStr for ProjectorCommand {
+    type Err = UnknownCommand;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_once('/')
+            .and_then(|(group, name)| Self::from_name(group, name))
+            .ok_or(UnknownCommand)
+    }
+}
+
+/// Serialized as `{group}/{name}`, like in config files.
+impl Serialize for ProjectorCommand {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProjectorCommand {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse()
+            .map_err(|_| de::Error::custom(format!("unknown command {name:?}")))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownCommand;
+
+impl fmt::Display for UnknownCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("No such command")
+    }
+}
+
+impl std::error::Error for UnknownCommand {}
+
+impl fmt::Display for ProjectorCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.group().name(), self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalog_is_in_declaration_order() {
+        for (i, command) in ProjectorCommand::ALL.into_iter().enumerate() {
+            assert_eq!(command as usize, i, "{command}");
+        }
+    }
+
+    #[test]
+    fn commands_round_trip() {
+        for command in ProjectorCommand::ALL {
+            assert_eq!(ProjectorCommand::decode(&command.encode()), Some(command));
+            assert_eq!(
+                ProjectorCommand::from_name(command.group().name(), command.name()),
+                Some(command)
+            );
+        }
+        assert_eq!(ProjectorCommand::decode(&[0x05, 0x00]), None);
+        assert_eq!(ProjectorCommand::from_name("power", "toggle"), None);
+        assert_eq!(ProjectorCommand::PowerOn.to_string(), "power/on");
+        assert_eq!("menu/ok".parse(), Ok(ProjectorCommand::MenuOk));
+        assert_eq!("menu".parse::<ProjectorCommand>(), Err(UnknownCommand));
+        assert_eq!(
+            serde_json::from_str::<ProjectorCommand>(r#""input/hdmi""#).unwrap(),
+            ProjectorCommand::InputHdmi
+        );
+        assert!(serde_json::from_str::<ProjectorCommand>(r#""input/dvi""#).is_err());
+    }
+}
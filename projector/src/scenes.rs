@@ -0,0 +1,778 @@
+//! Scenes, named macros like "presentation" that send commands, wait, and wait for the projector
+//! to get somewhere, e.g. until it's warmed up.
+//!
+//! `POST /scenes/{name}/run` starts a run in the background and answers with it right away.
+//! Its progress is on `GET /scenes/runs/{id}`, the WebSocket `/scenes/runs/{id}/events` streams
+//! its events, and `POST /scenes/runs/{id}/cancel` stops it in the middle of a step.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use hyper::StatusCode;
+use realraum_backend_common::{
+    auth::{self, Identity, Role},
+    response, Error,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::{watch, Notify};
+
+use crate::{
+    config::{Config, SceneConfig, SceneStep},
+    driver::Delivery,
+    projectors::{Projector, SelectedProjector},
+    protocol::ProjectorCommand,
+    required_role, AppState,
+};
+
+/// How many finished runs we remember.
+const FINISHED_RUNS: usize = 20;
+
+/// `GET /scenes` and the routes of runs, which aren't tied to a projector.
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/scenes/runs/:id/cancel", post(handle_cancel))
+        .route_layer(auth::require(Role::Member))
+        .route("/scenes", get(handle_list))
+        .route("/scenes/runs/:id", get(handle_run))
+        .route("/scenes/runs/:id/events", get(handle_events))
+}
+
+/// `POST /scenes/{name}/run` on a projector.
+pub fn projector_routes() -> Router<AppState> {
+    Router::new()
+        .route("/scenes/:name/run", post(handle_start))
+        .route_layer(auth::require(Role::Member))
+}
+
+/// Who may run a scene, whoever may send all of its commands.
+fn scene_role(scene: &SceneConfig) -> Role {
+    scene
+        .steps
+        .iter()
+        .filter_map(|step| match step {
+            SceneStep::Command { command } => Some(required_role(*command)),
+            _ => None,
+        })
+        .fold(Role::Member, Ord::max)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// What happened during a run.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A step started
+    Step {
+        index: usize,
+        step: SceneStep,
+    },
+    /// The command of the current step got sent
+    Sent {
+        command: ProjectorCommand,
+        confirmed: bool,
+    },
+    Completed,
+    /// The current step failed, the rest didn't run
+    Failed {
+        message: String,
+    },
+    /// Cancelled during the current step
+    Cancelled,
+}
+
+/// A run of a scene as reported by the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunStatus {
+    pub id: u64,
+    pub scene: String,
+    pub projector: String,
+    pub state: RunState,
+    /// Index of the current step, or of the last one when finished
+    pub step: Option<usize>,
+    /// Number of steps of the scene
+    pub steps: usize,
+    pub events: Vec<Event>,
+}
+
+impl RunStatus {
+    fn record(&mut self, event: Event) {
+        match event {
+            Event::Step { index, .. } => self.step = Some(index),
+            Event::Sent { .. } => {}
+            Event::Completed => self.state = RunState::Completed,
+            Event::Failed { .. } => self.state = RunState::Failed,
+            Event::Cancelled => self.state = RunState::Cancelled,
+        }
+        self.events.push(event);
+    }
+
+    fn is_running(&self) -> bool {
+        self.state == RunState::Running
+    }
+}
+
+#[derive(Debug)]
+struct Run {
+    status: watch::Receiver<RunStatus>,
+    cancel: Arc<Notify>,
+    /// Who may cancel it, whoever may start it
+    role: Role,
+}
+
+/// The runs of scenes, the ongoing ones and the last finished ones, cheap to clone.
+#[derive(Debug, Clone, Default)]
+pub struct Runs(Arc<Mutex<RunsInner>>);
+
+#[derive(Debug, Default)]
+struct RunsInner {
+    next_id: u64,
+    runs: VecDeque<(u64, Run)>,
+}
+
+impl Runs {
+    /// Runs `scene` on `projector` in the background, unless it's already running a scene.
+    ///
+    /// Consecutive commands are `delay` apart, as the projector drops presses that come too fast.
+    pub fn start(
+        &self,
+        scene: SceneConfig,
+        projector: Projector,
+        delay: Duration,
+    ) -> Result<watch::Receiver<RunStatus>, Error> {
+        let mut inner = self.0.lock().unwrap();
+        if let Some((id, _)) = inner.runs.iter().find(|(_, run)| {
+            let status = run.status.borrow();
+            status.is_running() && status.projector == projector.name()
+        }) {
+            return Err(Error::new(
+                StatusCode::CONFLICT,
+                "scene_running",
+                format!("Run {id} is still running on this projector, cancel it first"),
+            ));
+        }
+
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let (status, receiver) = watch::channel(RunStatus {
+            id,
+            scene: scene.name.clone(),
+            projector: projector.name().to_string(),
+            state: RunState::Running,
+            step: None,
+            steps: scene.steps.len(),
+            events: Vec::new(),
+        });
+        let cancel = Arc::new(Notify::new());
+        inner.runs.push_back((
+            id,
+            Run {
+                status: receiver.clone(),
+                cancel: cancel.clone(),
+                role: scene_role(&scene),
+            },
+        ));
+        while inner.runs.len() > FINISHED_RUNS {
+            match inner
+                .runs
+                .iter()
+                .position(|(_, run)| !run.status.borrow().is_running())
+            {
+                Some(oldest) => inner.runs.remove(oldest),
+                None => break,
+            };
+        }
+
+        tokio::spawn(run(scene, projector, delay, status, cancel));
+        Ok(receiver)
+    }
+
+    pub fn get(&self, id: u64) -> Option<watch::Receiver<RunStatus>> {
+        self.find(id, |run| run.status.clone())
+    }
+
+    /// The role needed to cancel the run.
+    pub fn role(&self, id: u64) -> Option<Role> {
+        self.find(id, |run| run.role)
+    }
+
+    /// Cancels the run and waits for it to stop, `None` if there's no such run.
+    pub async fn cancel(&self, id: u64) -> Option<RunStatus> {
+        let (mut status, cancel) = self.find(id, |run| (run.status.clone(), run.cancel.clone()))?;
+        // Stores a permit if the run is between two polls
+        cancel.notify_one();
+        // Fails only once the run's task is gone, which records why it stopped before
+        let _ = status.wait_for(|status| !status.is_running()).await;
+        let status = status.borrow().clone();
+        Some(status)
+    }
+
+    fn find<T>(&self, id: u64, f: impl FnOnce(&Run) -> T) -> Option<T> {
+        let inner = self.0.lock().unwrap();
+        inner
+            .runs
+            .iter()
+            .find(|(run_id, _)| *run_id == id)
+            .map(|(_, run)| f(run))
+    }
+}
+
+#[tracing::instrument(skip_all, fields(scene = scene.name, projector = projector.name(), run = status.borrow().id))]
+async fn run(
+    scene: SceneConfig,
+    projector: Projector,
+    delay: Duration,
+    status: watch::Sender<RunStatus>,
+    cancel: Arc<Notify>,
+) {
+    tracing::info!("Running scene");
+    let event = tokio::select! {
+        result = run_steps(&scene, &projector, delay, &status) => match result {
+            Ok(()) => Event::Completed,
+            Err(message) => Event::Failed { message },
+        },
+        () = cancel.notified() => Event::Cancelled,
+    };
+    match &event {
+        Event::Failed { message } => tracing::warn!("Scene failed: {message}"),
+        event => tracing::info!("Scene finished: {event:?}"),
+    }
+    status.send_modify(|status| status.record(event));
+}
+
+async fn run_steps(
+    scene: &SceneConfig,
+    projector: &Projector,
+    delay: Duration,
+    status: &watch::Sender<RunStatus>,
+) -> Result<(), String> {
+    let mut after_command = false;
+    for (index, step) in scene.steps.iter().enumerate() {
+        if after_command && matches!(step, SceneStep::Command { .. }) {
+            tokio::time::sleep(delay).await;
+        }
+        status.send_modify(|status| {
+            status.record(Event::Step {
+                index,
+                step: step.clone(),
+            })
+        });
+
+        match step {
+            SceneStep::Command { command } => {
+                let sent = crate::send(projector, (*command).into())
+                    .await
+                    .map_err(|e| e.message)?;
+                status.send_modify(|status| {
+                    status.record(Event::Sent {
+                        command: *command,
+                        confirmed: sent.delivery == Delivery::Confirmed,
+                    })
+                });
+            }
+            SceneStep::Wait { wait_ms } => {
+                tokio::time::sleep(Duration::from_millis(*wait_ms)).await;
+            }
+            SceneStep::WaitFor {
+                wait_for,
+                timeout_secs,
+            } => {
+                let mut state = projector.subscribe();
+                let reached = tokio::time::timeout(
+                    Duration::from_secs(*timeout_secs),
+                    state.wait_for(|state| wait_for.holds(state)),
+                )
+                .await
+                .map(|result| result.is_ok());
+                match reached {
+                    Ok(true) => {}
+                    Ok(false) => return Err("The projector's driver stopped".into()),
+                    Err(_) => {
+                        return Err(format!(
+                            "The projector didn't get there within {timeout_secs}s"
+                        ))
+                    }
+                }
+            }
+        }
+        after_command = matches!(step, SceneStep::Command { .. });
+    }
+    Ok(())
+}
+
+/// A scene as listed by `/api/v1/scenes`.
+#[derive(Debug, Serialize)]
+struct SceneInfo<'a> {
+    #[serde(flatten)]
+    scene: &'a SceneConfig,
+    /// Who may run it
+    role: Role,
+}
+
+/// All configured scenes on `GET /api/v1/scenes`.
+async fn handle_list(State(config): State<Arc<Config>>) -> Json<Value> {
+    let scenes: Vec<_> = config
+        .scenes
+        .iter()
+        .map(|scene| SceneInfo {
+            scene,
+            role: scene_role(scene),
+        })
+        .collect();
+    response::ok(json!({ "scenes": scenes }))
+}
+
+async fn handle_start(
+    State(config): State<Arc<Config>>,
+    State(runs): State<Runs>,
+    SelectedProjector(projector): SelectedProjector,
+    identity: Option<Extension<Identity>>,
+    // All params, as the route may be nested below `/projectors/:projector`
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<(StatusCode, Json<Value>), Response> {
+    let name = &params["name"];
+    let scene = config
+        .scenes
+        .iter()
+        .find(|scene| scene.name == *name)
+        .ok_or_else(|| {
+            Error::new(
+                StatusCode::NOT_FOUND,
+                "unknown_scene",
+                format!("No scene {name:?}, see /api/v1/scenes"),
+            )
+            .into_response()
+        })?;
+    auth::authorize(identity.as_deref(), scene_role(scene)).map_err(IntoResponse::into_response)?;
+
+    let delay = Duration::from_millis(config.batch.delay_ms);
+    let status = runs
+        .start(scene.clone(), projector, delay)
+        .map_err(IntoResponse::into_response)?;
+    let run = status.borrow().clone();
+    Ok((
+        StatusCode::ACCEPTED,
+        response::ok(json!({ "message": "Scene started", "run": run })),
+    ))
+}
+
+async fn handle_run(State(runs): State<Runs>, Path(id): Path<u64>) -> Result<Json<Value>, Error> {
+    let status = runs.get(id).ok_or_else(|| unknown_run(id))?;
+    let run = status.borrow().clone();
+    Ok(response::ok(json!({ "run": run })))
+}
+
+/// Cancels a run, for whoever may run its scene, so members can't stop an admin's shutdown.
+async fn handle_cancel(
+    State(runs): State<Runs>,
+    identity: Option<Extension<Identity>>,
+    Path(id): Path<u64>,
+) -> Result<Json<Value>, Response> {
+    let (status, role) = runs
+        .get(id)
+        .zip(runs.role(id))
+        .ok_or_else(|| unknown_run(id).into_response())?;
+    auth::authorize(identity.as_deref(), role).map_err(IntoResponse::into_response)?;
+    if !status.borrow().is_running() {
+        return Err(Error::new(
+            StatusCode::CONFLICT,
+            "run_finished",
+            format!("Run {id} already finished"),
+        )
+        .into_response());
+    }
+
+    let run = runs
+        .cancel(id)
+        .await
+        .ok_or_else(|| unknown_run(id).into_response())?;
+    Ok(response::ok(
+        json!({ "message": "Scene cancelled", "run": run }),
+    ))
+}
+
+/// Streams the events of a run as JSON messages, from the first one until it finishes.
+async fn handle_events(
+    State(runs): State<Runs>,
+    Path(id): Path<u64>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, Error> {
+    let status = runs.get(id).ok_or_else(|| unknown_run(id))?;
+    Ok(ws.on_upgrade(move |socket| stream_events(socket, status)))
+}
+
+async fn stream_events(mut socket: WebSocket, mut status: watch::Receiver<RunStatus>) {
+    let mut sent = 0;
+    loop {
+        let (events, running) = {
+            let status = status.borrow_and_update();
+            (status.events[sent..].to_vec(), status.is_running())
+        };
+        sent += events.len();
+        for event in events {
+            let message = serde_json::to_string(&event).unwrap();
+            if socket.send(Message::Text(message)).await.is_err() {
+                return;
+            }
+        }
+        if !running || status.changed().await.is_err() {
+            break;
+        }
+    }
+    let _ = socket.close().await;
+}
+
+fn unknown_run(id: u64) -> Error {
+    Error::new(
+        StatusCode::NOT_FOUND,
+        "unknown_run",
+        format!("No run {id}, only the last {FINISHED_RUNS} finished runs are kept"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProjectorConfig;
+    use crate::pjlink;
+    use crate::state::{Condition, Confidence, PowerState};
+    use realraum_backend_projector::simulator::Simulator;
+    use tokio::net::TcpListener;
+
+    fn scene(steps: Vec<SceneStep>) -> SceneConfig {
+        SceneConfig {
+            name: "presentation".into(),
+            label: None,
+            steps,
+        }
+    }
+
+    async fn simulated() -> (Simulator, Projector) {
+        let simulator = Simulator::bind("127.0.0.1:0".parse().unwrap(), Some(0x03))
+            .await
+            .unwrap();
+        let addr = simulator.local_addr();
+        let projector = Projector::spawn(ProjectorConfig {
+            host: addr.ip().to_string(),
+            port: Some(addr.port()),
+            ..Default::default()
+        });
+        // Registered once it reports its state
+        projector
+            .subscribe()
+            .wait_for(|state| state.power.confidence == Confidence::Confirmed)
+            .await
+            .unwrap();
+        (simulator, projector)
+    }
+
+    async fn finished(mut status: watch::Receiver<RunStatus>) -> RunStatus {
+        status
+            .wait_for(|status| !status.is_running())
+            .await
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn parses_steps() {
+        let config: Config = serde_json::from_value(json!({
+            "scenes": [{
+                "name": "presentation",
+                "steps": [
+                    {"command": "power/on"},
+                    {"wait_for": {"power": "on"}, "timeout_secs": 90},
+                    {"wait_ms": 500},
+                    {"wait_for": {"connected": true}},
+                ],
+            }],
+        }))
+        .unwrap();
+        assert_eq!(
+            config.scenes[0].steps,
+            [
+                SceneStep::Command {
+                    command: ProjectorCommand::PowerOn
+                },
+                SceneStep::WaitFor {
+                    wait_for: Condition {
+                        power: Some(PowerState::On),
+                        ..Condition::default()
+                    },
+                    timeout_secs: 90,
+                },
+                SceneStep::Wait { wait_ms: 500 },
+                SceneStep::WaitFor {
+                    wait_for: Condition {
+                        connected: Some(true),
+                        ..Condition::default()
+                    },
+                    timeout_secs: 60,
+                },
+            ]
+        );
+
+        let step = |step| serde_json::from_value::<SceneStep>(step).is_err();
+        assert!(step(json!({"command": "power/toggle"})));
+        assert!(step(json!({"wait_for": {"powered": true}})));
+        assert!(step(json!({"sleep_ms": 100})));
+    }
+
+    #[test]
+    fn powering_off_needs_an_admin() {
+        let command = |command| SceneStep::Command { command };
+        assert_eq!(
+            scene_role(&scene(vec![SceneStep::Wait { wait_ms: 1 }])),
+            Role::Member
+        );
+        assert_eq!(
+            scene_role(&scene(vec![
+                command(ProjectorCommand::VolumeMute),
+                command(ProjectorCommand::PowerOff)
+            ])),
+            Role::Admin
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_steps_in_order() {
+        let (simulator, projector) = simulated().await;
+        let runs = Runs::default();
+        let status = runs
+            .start(
+                scene(vec![
+                    SceneStep::Command {
+                        command: ProjectorCommand::PowerOn,
+                    },
+                    SceneStep::WaitFor {
+                        wait_for: Condition {
+                            power: Some(PowerState::On),
+                            ..Condition::default()
+                        },
+                        timeout_secs: 5,
+                    },
+                    SceneStep::Command {
+                        command: ProjectorCommand::InputHdmi,
+                    },
+                    SceneStep::Command {
+                        command: ProjectorCommand::VolumeUp,
+                    },
+                ]),
+                projector,
+                Duration::from_millis(10),
+            )
+            .unwrap();
+
+        let status = finished(status).await;
+        assert_eq!(status.state, RunState::Completed, "{:?}", status.events);
+        assert_eq!(status.step, Some(3));
+        assert_eq!(status.events.len(), 4 + 3 + 1);
+        assert!(matches!(
+            status.events[1],
+            Event::Sent {
+                command: ProjectorCommand::PowerOn,
+                confirmed: true
+            }
+        ));
+        let state = simulator.state();
+        assert!(state.power);
+        assert_eq!(state.volume, 11);
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_projector_doesnt_get_there() {
+        let (_simulator, projector) = simulated().await;
+        let status = Runs::default()
+            .start(
+                scene(vec![
+                    SceneStep::WaitFor {
+                        wait_for: Condition {
+                            power: Some(PowerState::On),
+                            ..Condition::default()
+                        },
+                        timeout_secs: 1,
+                    },
+                    SceneStep::Command {
+                        command: ProjectorCommand::InputHdmi,
+                    },
+                ]),
+                projector,
+                Duration::ZERO,
+            )
+            .unwrap();
+
+        let status = finished(status).await;
+        assert_eq!(status.state, RunState::Failed);
+        assert_eq!(status.step, Some(0));
+        assert!(matches!(status.events.last(), Some(Event::Failed { .. })));
+    }
+
+    #[tokio::test]
+    async fn cancels_runs() {
+        let (simulator, projector) = simulated().await;
+        let runs = Runs::default();
+        let steps = vec![
+            SceneStep::Command {
+                command: ProjectorCommand::PowerOn,
+            },
+            SceneStep::Wait { wait_ms: 60_000 },
+            SceneStep::Command {
+                command: ProjectorCommand::InputHdmi,
+            },
+        ];
+        let mut status = runs
+            .start(scene(steps.clone()), projector.clone(), Duration::ZERO)
+            .unwrap();
+        let id = status.borrow().id;
+        status
+            .wait_for(|status| status.step == Some(1))
+            .await
+            .unwrap();
+
+        // One run per projector at a time
+        let error = runs
+            .start(scene(steps), projector, Duration::ZERO)
+            .unwrap_err();
+        assert_eq!(error.code, "scene_running");
+
+        let status = runs.cancel(id).await.unwrap();
+        assert_eq!(status.state, RunState::Cancelled);
+        assert_eq!(status.step, Some(1));
+        assert!(simulator.state().power);
+        assert_ne!(
+            simulator.state().input,
+            realraum_backend_projector::simulator::Input::Hdmi
+        );
+        assert!(runs.cancel(id + 1).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn waits_for_pjlink_projectors_to_warm_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = pjlink::tests::config(listener.local_addr().unwrap(), None);
+        config.heartbeat_secs = 1;
+        let projector = Projector::spawn(config);
+        // Acknowledges `POWR 1` right away, but warms up for two polls, refusing inputs meanwhile
+        let mut warm_up_polls = None;
+        let mut lines = pjlink::tests::mock(listener, "PJLINK 0", move |line| {
+            let (command, param) = line.split_once(' ').unwrap();
+            let warm = warm_up_polls == Some(0);
+            let answer = match (command, param) {
+                ("%1CLSS", "?") => "1",
+                ("%1POWR", "1") => {
+                    warm_up_polls = Some(2);
+                    "OK"
+                }
+                ("%1POWR", "?") => match warm_up_polls {
+                    None => "0",
+                    Some(0) => "1",
+                    Some(polls) => {
+                        warm_up_polls = Some(polls - 1);
+                        "3"
+                    }
+                },
+                ("%1INPT", "?") if warm => "31",
+                ("%1AVMT", "?") if warm => "30",
+                ("%1INPT", "31") if warm => "OK",
+                _ => "ERR3",
+            };
+            format!("{command}={answer}")
+        });
+
+        let runs = Runs::default();
+        let status = runs
+            .start(
+                scene(vec![
+                    SceneStep::Command {
+                        command: ProjectorCommand::PowerOn,
+                    },
+                    SceneStep::WaitFor {
+                        wait_for: Condition {
+                            power: Some(PowerState::On),
+                            ..Condition::default()
+                        },
+                        timeout_secs: 10,
+                    },
+                    SceneStep::Command {
+                        command: ProjectorCommand::InputHdmi,
+                    },
+                ]),
+                projector.clone(),
+                Duration::ZERO,
+            )
+            .unwrap();
+        let status = finished(status).await;
+        assert_eq!(status.state, RunState::Completed, "{:?}", status.events);
+        assert_eq!(projector.state().power.value, Some(PowerState::On));
+
+        let mut sent = Vec::new();
+        while let Ok(line) = lines.try_recv() {
+            sent.push(line);
+        }
+        let power_on = sent.iter().position(|line| line == "%1POWR 1").unwrap();
+        let input = sent.iter().position(|line| line == "%1INPT 31").unwrap();
+        // Two polls answered with warming up, the third with on
+        let polls = sent[power_on..input]
+            .iter()
+            .filter(|line| *line == "%1POWR ?")
+            .count();
+        assert_eq!(polls, 3, "{sent:?}");
+    }
+
+    #[tokio::test]
+    async fn only_lets_admins_cancel_admin_scenes() {
+        let (_simulator, projector) = simulated().await;
+        let runs = Runs::default();
+        let status = runs
+            .start(
+                scene(vec![
+                    SceneStep::Wait { wait_ms: 60_000 },
+                    SceneStep::Command {
+                        command: ProjectorCommand::PowerOff,
+                    },
+                ]),
+                projector,
+                Duration::ZERO,
+            )
+            .unwrap();
+        let id = status.borrow().id;
+        let cancel = |role| {
+            handle_cancel(
+                State(runs.clone()),
+                Some(Extension(Identity {
+                    name: Some("alice".into()),
+                    role,
+                })),
+                Path(id),
+            )
+        };
+
+        let response = cancel(Role::Member).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(status.borrow().is_running());
+
+        let Json(body) = cancel(Role::Admin).await.unwrap();
+        assert_eq!(body["run"]["state"], "cancelled");
+        assert_eq!(status.borrow().state, RunState::Cancelled);
+    }
+}
@@ -0,0 +1,381 @@
+//! The [`ProjectorDriver`] for projectors on a serial line, e.g. through a USB-serial cable
+//! when the projector's network stack hangs.
+//!
+//! RS-232 command sets differ between vendors and often between models of the same vendor,
+//! and none of them are the CIP presses of the web UI. So the bytes of each command come from
+//! the config, copied from the RS-232 table of the projector's manual, see
+//! [`SerialConfig::commands`]. Operations without bytes fail with [`Error::Unsupported`].
+//!
+//! The device is opened right away and reopened with the next command after it went away.
+//! Projectors don't report changes on their own here, so the state is what we sent,
+//! and commands are only confirmed if the manual documents an acknowledgement to wait for.
+
+use std::{fmt, io, ops::Deref, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::{watch, Mutex},
+    time::{self, Instant},
+};
+use tokio_serial::{
+    ClearBuffer, DataBits, FlowControl, SerialPort, SerialPortBuilderExt, SerialStream, StopBits,
+};
+
+use crate::{
+    config::{Parity, ProjectorConfig, SerialConfig},
+    driver::{Delivery, Error, Operation, ProjectorDriver, Sent},
+    state::{ProjectorState, Source},
+};
+
+/// How long we wait for the projector to acknowledge a command.
+const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Bytes sent or expected on the serial line, written as hex like `"50 57 52 0D"` in the config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame(Vec<u8>);
+
+impl Deref for Frame {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{byte:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Frame {
+    type Err = String;
+
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        let bytes = hex
+            .split_whitespace()
+            .map(|byte| match byte.len() {
+                1 | 2 => u8::from_str_radix(byte, 16).map_err(|_| byte),
+                _ => Err(byte),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|byte| format!("{byte:?} isn't a hex byte, write bytes like \"0D 0A\""))?;
+        if bytes.is_empty() {
+            return Err("Frames need at least one byte".to_string());
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl Serialize for Frame {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Frame {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Handle to the serial line, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct SerialDriver {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: SerialConfig,
+    write_timeout: Duration,
+    /// The open device, held while sending so commands never interleave
+    port: Mutex<Option<SerialStream>>,
+    state: watch::Sender<ProjectorState>,
+}
+
+impl SerialDriver {
+    /// Opens the serial device, or fails the first command trying again if it's missing.
+    pub fn spawn(config: ProjectorConfig) -> Self {
+        let port = open(&config.serial)
+            .inspect_err(|e| {
+                tracing::warn!(projector = %config.name, "Failed to open {}: {e}", config.serial.device);
+            })
+            .ok();
+        let mut state = ProjectorState::default();
+        state.set_connected(port.is_some());
+
+        Self {
+            inner: Arc::new(Inner {
+                config: config.serial,
+                write_timeout: Duration::from_secs(config.write_timeout_secs),
+                port: Mutex::new(port),
+                state: watch::channel(state).0,
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl ProjectorDriver for SerialDriver {
+    async fn execute(&self, operation: Operation) -> Result<Sent, Error> {
+        let frame = self
+            .inner
+            .config
+            .commands
+            .get(&operation.command())
+            .ok_or(Error::Unsupported(operation))?;
+
+        let mut port = self.inner.port.lock().await;
+        let result = self.inner.send(&mut port, frame).await;
+        self.inner.track(&result);
+
+        let delivery = result?;
+        // The projector won't tell us, e.g. when it's still warming up
+        self.inner
+            .state
+            .send_modify(|state| state.apply(operation, Source::Command));
+        Ok(Sent {
+            bytes: frame.to_vec(),
+            delivery,
+        })
+    }
+
+    fn subscribe(&self) -> watch::Receiver<ProjectorState> {
+        self.inner.state.subscribe()
+    }
+}
+
+impl Inner {
+    /// Sends `frame`, opening the device if needed and closing it if that fails.
+    async fn send(&self, port: &mut Option<SerialStream>, frame: &[u8]) -> Result<Delivery, Error> {
+        let stream = match port {
+            Some(stream) => stream,
+            None => port.insert(open(&self.config).map_err(Error::Unreachable)?),
+        };
+        let result = self.exchange(stream, frame).await;
+        if result.is_err() {
+            *port = None;
+        }
+        result
+    }
+
+    /// Writes `frame` and waits for the acknowledgement, if the projector sends one.
+    async fn exchange(&self, stream: &mut SerialStream, frame: &[u8]) -> Result<Delivery, Error> {
+        // Drop acknowledgements that came too late for earlier commands, so they can't confirm this one
+        stream
+            .clear(ClearBuffer::Input)
+            .map_err(|e| Error::Unreachable(e.into()))?;
+        time::timeout(self.write_timeout, stream.write_all(frame))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "write timed out"))
+            .and_then(|written| written)
+            .map_err(Error::Unreachable)?;
+
+        let Some(ack) = &self.config.ack else {
+            return Ok(Delivery::Unconfirmed);
+        };
+        let deadline = Instant::now() + ACK_TIMEOUT;
+        let mut received = Vec::new();
+        let mut buffer = [0; 64];
+        loop {
+            let read = match time::timeout_at(deadline, stream.read(&mut buffer)).await {
+                // Projectors ignore commands while they're busy, e.g. warming up
+                Err(_) => return Ok(Delivery::Unconfirmed),
+                Ok(read) => read.map_err(Error::Unreachable)?,
+            };
+            if read == 0 {
+                return Err(Error::Unreachable(io::ErrorKind::UnexpectedEof.into()));
+            }
+            received.extend_from_slice(&buffer[..read]);
+            if received.windows(ack.len()).any(|window| window == &ack[..]) {
+                return Ok(Delivery::Confirmed);
+            }
+        }
+    }
+
+    /// Records whether the device is usable.
+    fn track<T>(&self, result: &Result<T, Error>) {
+        let connected = !matches!(result, Err(Error::Unreachable(_)));
+        self.state.send_if_modified(|state| {
+            let changed = state.connected != connected;
+            if changed {
+                match result {
+                    Err(e) => tracing::warn!("Lost the serial line: {e}"),
+                    Ok(_) => tracing::info!("Serial line back"),
+                }
+            }
+            state.set_connected(connected);
+            changed
+        });
+    }
+}
+
+/// Opens the serial device with the configured framing.
+pub fn open(config: &SerialConfig) -> io::Result<SerialStream> {
+    let data_bits = match config.data_bits {
+        5 => DataBits::Five,
+        6 => DataBits::Six,
+        7 => DataBits::Seven,
+        _ => DataBits::Eight,
+    };
+    let stop_bits = match config.stop_bits {
+        2 => StopBits::Two,
+        _ => StopBits::One,
+    };
+    let parity = match config.parity {
+        Parity::None => tokio_serial::Parity::None,
+        Parity::Odd => tokio_serial::Parity::Odd,
+        Parity::Even => tokio_serial::Parity::Even,
+    };
+
+    tokio_serial::new(&config.device, config.baud_rate)
+        .data_bits(data_bits)
+        .parity(parity)
+        .stop_bits(stop_bits)
+        .flow_control(FlowControl::None)
+        .open_native_async()
+        .map_err(io::Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Driver,
+        protocol::ProjectorCommand,
+        state::{Confidence, Power, PowerState},
+    };
+
+    /// A pseudo-terminal pair, the projector's end and the path of the device for the driver.
+    fn pty() -> (SerialStream, String) {
+        let (projector, device) = SerialStream::pair().unwrap();
+        // The driver opens the device again by its path, which needs its lock
+        (projector, device.name().unwrap())
+    }
+
+    fn frame(hex: &str) -> Frame {
+        hex.parse().unwrap()
+    }
+
+    /// A projector taking Epson's ESC/VP21, `PWR ON\r` and `PWR OFF\r`.
+    fn config(device: String, ack: Option<&str>) -> ProjectorConfig {
+        ProjectorConfig {
+            driver: Driver::Serial,
+            serial: SerialConfig {
+                device,
+                baud_rate: 9600,
+                parity: Parity::Even,
+                data_bits: 7,
+                stop_bits: 2,
+                commands: [
+                    (ProjectorCommand::PowerOn, frame("50 57 52 20 4F 4E 0D")),
+                    (ProjectorCommand::PowerOff, frame("50 57 52 20 4F 46 46 0D")),
+                ]
+                .into(),
+                ack: ack.map(frame),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_frames() {
+        assert_eq!(frame("be EF 3 0d"), Frame(vec![0xbe, 0xef, 0x03, 0x0d]));
+        assert_eq!(frame("BE EF 03 0D").to_string(), "BE EF 03 0D");
+        for invalid in ["", " ", "BEEF", "0x0D", "GG", "0D,0A"] {
+            assert!(invalid.parse::<Frame>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn opens_with_the_configured_framing() {
+        let (_projector, path) = pty();
+        let port = open(&config(path, None).serial).unwrap();
+        assert_eq!(port.baud_rate().unwrap(), 9600);
+        assert_eq!(port.stop_bits().unwrap(), StopBits::Two);
+        // Linux ptys ignore data bits and parity, they're always 8N
+    }
+
+    #[tokio::test]
+    async fn sends_the_configured_frames() {
+        let (mut projector, path) = pty();
+        let driver = SerialDriver::spawn(config(path, None));
+        assert!(driver.is_connected());
+
+        let sent = driver.execute(Operation::Power(Power::On)).await.unwrap();
+        assert_eq!(sent.bytes, b"PWR ON\r");
+        assert_eq!(sent.delivery, Delivery::Unconfirmed);
+        let mut received = [0; 7];
+        projector.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"PWR ON\r");
+
+        let state = driver.state();
+        assert_eq!(state.power.value, Some(PowerState::On));
+        assert_eq!(state.power.confidence, Confidence::Assumed);
+
+        // Nobody copied the menu buttons from the manual
+        assert!(matches!(
+            driver
+                .execute(Operation::Menu(crate::driver::MenuButton::Ok))
+                .await,
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn confirms_acknowledged_commands() {
+        let (mut projector, path) = pty();
+        let driver = SerialDriver::spawn(config(path, Some("3A")));
+
+        let (sent, ()) = tokio::join!(driver.execute(Operation::Power(Power::Off)), async {
+            let mut received = [0; 8];
+            projector.read_exact(&mut received).await.unwrap();
+            assert_eq!(&received, b"PWR OFF\r");
+            projector.write_all(b":").await.unwrap();
+        });
+        assert_eq!(sent.unwrap().delivery, Delivery::Confirmed);
+
+        // No answer, e.g. while it's busy cooling down
+        let sent = driver.execute(Operation::Power(Power::On)).await.unwrap();
+        assert_eq!(sent.delivery, Delivery::Unconfirmed);
+    }
+
+    #[tokio::test]
+    async fn ignores_late_acknowledgements() {
+        let (mut projector, path) = pty();
+        let driver = SerialDriver::spawn(config(path, Some("3A")));
+
+        let sent = driver.execute(Operation::Power(Power::Off)).await.unwrap();
+        assert_eq!(sent.delivery, Delivery::Unconfirmed);
+        let mut received = [0; 8];
+        projector.read_exact(&mut received).await.unwrap();
+        // Cooling down took the projector longer than we waited
+        projector.write_all(b":").await.unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+
+        let sent = driver.execute(Operation::Power(Power::On)).await.unwrap();
+        assert_eq!(sent.delivery, Delivery::Unconfirmed);
+    }
+
+    #[tokio::test]
+    async fn fails_without_the_device() {
+        let driver = SerialDriver::spawn(config("/dev/does-not-exist".into(), None));
+        assert!(!driver.is_connected());
+        assert!(matches!(
+            driver.execute(Operation::Power(Power::On)).await,
+            Err(Error::Unreachable(_))
+        ));
+        assert!(!driver.is_connected());
+    }
+}
@@ -0,0 +1,470 @@
+//! A simulated projector speaking [CIP](crate::cip) like ours, for working on the backend
+//! without being in the space, and for the backend's integration tests.
+//!
+//! It asks clients to register, answers heartbeats, and keeps a [`SimulatedState`] that the
+//! [button presses](crate::protocol::commands) change. Changes are reported to all clients as
+//! feedback like the real projector does, also those from [`Simulator::press`], which stands
+//! in for the IR remote. [`Faults`] make it misbehave like a flaky projector.
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+    task::JoinHandle,
+    time,
+};
+
+use crate::{
+    cip::{Data, Decoder, Packet},
+    protocol::{commands, Command},
+};
+
+/// Highest volume level.
+pub const MAX_VOLUME: u8 = 20;
+
+/// Highest level of the picture settings.
+pub const MAX_PICTURE: u8 = 100;
+
+/// Ways to misbehave, all off by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Faults {
+    /// Hang up after this many packets from a client
+    pub drop_after: Option<u32>,
+    /// Wait this long before every packet we send
+    pub delay: Duration,
+    /// Garble every nth packet we send, keeping its framing so the rest stays readable
+    pub garble_every: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    VgaA,
+    VgaB,
+    Composite1,
+    Composite2,
+    SVideo,
+    Hdmi,
+}
+
+/// Each input with the button selecting it, in the order the source button cycles through them.
+const INPUTS: [(Input, Command); 6] = [
+    (Input::VgaA, commands::input::VGA_A),
+    (Input::VgaB, commands::input::VGA_B),
+    (Input::Composite1, commands::input::COMPOSITE_1),
+    (Input::Composite2, commands::input::COMPOSITE_2),
+    (Input::SVideo, commands::input::S_VIDEO),
+    (Input::Hdmi, commands::input::HDMI),
+];
+
+/// What the simulated projector is doing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatedState {
+    pub power: bool,
+    pub input: Input,
+    /// Up to [`MAX_VOLUME`]
+    pub volume: u8,
+    pub muted: bool,
+    pub blanked: bool,
+    pub frozen: bool,
+    /// Up to [`MAX_PICTURE`], like the other picture settings
+    pub contrast: u8,
+    pub brightness: u8,
+    pub color: u8,
+    pub sharpness: u8,
+}
+
+impl Default for SimulatedState {
+    fn default() -> Self {
+        Self {
+            power: false,
+            input: Input::VgaA,
+            volume: 10,
+            muted: false,
+            blanked: false,
+            frozen: false,
+            contrast: 50,
+            brightness: 50,
+            color: 50,
+            sharpness: 50,
+        }
+    }
+}
+
+impl SimulatedState {
+    /// Presses `command`'s button, returning the feedback reporting the change.
+    ///
+    /// Like the real projector, it only listens to the power button while it's off.
+    fn press(&mut self, command: Command) -> Vec<Packet> {
+        if !self.power && command != commands::power::ON {
+            return Vec::new();
+        }
+
+        let adjust = |level: &mut u8, up: bool, max: u8| {
+            *level = match up {
+                true => level.saturating_add(1).min(max),
+                false => level.saturating_sub(1),
+            };
+            Vec::new()
+        };
+        match command {
+            commands::power::ON | commands::power::OFF => {
+                self.power = command == commands::power::ON;
+                toggle(self.power, commands::power::ON, commands::power::OFF)
+            }
+            commands::input::SOURCE_BUTTON => {
+                let next = INPUTS
+                    .iter()
+                    .cycle()
+                    .skip_while(|(input, _)| *input != self.input)
+                    .nth(1)
+                    .map_or(Input::VgaA, |&(input, _)| input);
+                self.select(next)
+            }
+            commands::volume::MUTE | commands::volume::UN_MUTE => {
+                self.muted = command == commands::volume::MUTE;
+                toggle(
+                    self.muted,
+                    commands::volume::MUTE,
+                    commands::volume::UN_MUTE,
+                )
+            }
+            commands::picture::BLANK | commands::picture::UN_BLANK => {
+                self.blanked = command == commands::picture::BLANK;
+                toggle(
+                    self.blanked,
+                    commands::picture::BLANK,
+                    commands::picture::UN_BLANK,
+                )
+            }
+            commands::picture::FREEZE | commands::picture::UN_FREEZE => {
+                self.frozen = command == commands::picture::FREEZE;
+                toggle(
+                    self.frozen,
+                    commands::picture::FREEZE,
+                    commands::picture::UN_FREEZE,
+                )
+            }
+            commands::volume::UP => adjust(&mut self.volume, true, MAX_VOLUME),
+            commands::volume::DOWN => adjust(&mut self.volume, false, MAX_VOLUME),
+            commands::picture::CONTRAST_UP => adjust(&mut self.contrast, true, MAX_PICTURE),
+            commands::picture::CONTRAST_DOWN => adjust(&mut self.contrast, false, MAX_PICTURE),
+            commands::picture::BRIGHTNESS_UP => adjust(&mut self.brightness, true, MAX_PICTURE),
+            commands::picture::BRIGHTNESS_DOWN => adjust(&mut self.brightness, false, MAX_PICTURE),
+            commands::picture::COLOR_UP => adjust(&mut self.color, true, MAX_PICTURE),
+            commands::picture::COLOR_DOWN => adjust(&mut self.color, false, MAX_PICTURE),
+            commands::picture::SHARPNESS_UP => adjust(&mut self.sharpness, true, MAX_PICTURE),
+            commands::picture::SHARPNESS_DOWN => adjust(&mut self.sharpness, false, MAX_PICTURE),
+            command => match INPUTS.iter().find(|(_, button)| *button == command) {
+                Some(&(input, _)) => self.select(input),
+                // The menu buttons, the simulator has no menu
+                None => Vec::new(),
+            },
+        }
+    }
+
+    fn select(&mut self, input: Input) -> Vec<Packet> {
+        let mut feedback = Vec::new();
+        if input != self.input {
+            feedback.push(digital(input_button(self.input), false));
+        }
+        feedback.push(digital(input_button(input), true));
+        self.input = input;
+        feedback
+    }
+
+    /// All joins we report, for an update request.
+    fn report(&self) -> Vec<Packet> {
+        let mut report = toggle(self.power, commands::power::ON, commands::power::OFF);
+        report.extend(
+            INPUTS
+                .iter()
+                .map(|&(input, button)| digital(button, input == self.input)),
+        );
+        report.extend(toggle(
+            self.muted,
+            commands::volume::MUTE,
+            commands::volume::UN_MUTE,
+        ));
+        report.extend(toggle(
+            self.blanked,
+            commands::picture::BLANK,
+            commands::picture::UN_BLANK,
+        ));
+        report.extend(toggle(
+            self.frozen,
+            commands::picture::FREEZE,
+            commands::picture::UN_FREEZE,
+        ));
+        report.push(Packet::Data(Data::EndOfQuery));
+        report
+    }
+}
+
+fn input_button(input: Input) -> Command {
+    INPUTS
+        .iter()
+        .find(|(candidate, _)| *candidate == input)
+        .map(|&(_, button)| button)
+        .expect("every input has a button")
+}
+
+/// Feedback for a setting with a button for either value, like mute and un-mute.
+fn toggle(on: bool, on_button: Command, off_button: Command) -> Vec<Packet> {
+    vec![digital(on_button, on), digital(off_button, !on)]
+}
+
+/// The button's join, `high` while pressed or selected.
+fn digital(button: Command, high: bool) -> Packet {
+    match Packet::decode(&button) {
+        Some((Packet::Data(Data::Digital { join, .. }), _)) => {
+            Packet::Data(Data::Digital { join, high })
+        }
+        _ => unreachable!("commands are digital joins"),
+    }
+}
+
+/// The button whose join is `join`.
+fn button(join: u16) -> Command {
+    let mut command = [0; 9];
+    command.copy_from_slice(&Packet::Data(Data::Digital { join, high: true }).encode());
+    command
+}
+
+#[derive(Debug, Clone)]
+enum Event {
+    Feedback(Vec<Packet>),
+    Disconnect,
+}
+
+#[derive(Debug)]
+struct Shared {
+    /// The IP ID clients must register with, any if unset
+    ip_id: Option<u8>,
+    state: Mutex<SimulatedState>,
+    faults: Mutex<Faults>,
+    events: broadcast::Sender<Event>,
+}
+
+/// A simulated projector listening for clients, it stops when dropped.
+#[derive(Debug)]
+pub struct Simulator {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    listener: JoinHandle<()>,
+}
+
+impl Simulator {
+    /// Listens on `addr`, accepting clients registering with `ip_id`, or any IP ID if unset.
+    pub async fn bind(addr: SocketAddr, ip_id: Option<u8>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            ip_id,
+            state: Mutex::default(),
+            faults: Mutex::default(),
+            events: broadcast::channel(64).0,
+        });
+
+        let listener = tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, client)) => {
+                            tracing::info!("Client {client} connected");
+                            let shared = shared.clone();
+                            tokio::spawn(async move {
+                                match serve(stream, &shared).await {
+                                    Ok(()) => tracing::info!("Client {client} disconnected"),
+                                    Err(e) => tracing::info!("Client {client} disconnected: {e}"),
+                                }
+                            });
+                        }
+                        Err(e) => tracing::warn!("Failed to accept a client: {e}"),
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            addr,
+            shared,
+            listener,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn state(&self) -> SimulatedState {
+        self.shared.state.lock().unwrap().clone()
+    }
+
+    pub fn set_faults(&self, faults: Faults) {
+        *self.shared.faults.lock().unwrap() = faults;
+    }
+
+    /// Presses `command`'s button like the IR remote, reporting the change to all clients.
+    pub fn press(&self, command: Command) {
+        press(&self.shared, command);
+    }
+
+    /// Hangs up on all clients, they may connect again.
+    pub fn disconnect_all(&self) {
+        let _ = self.shared.events.send(Event::Disconnect);
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        self.listener.abort();
+        self.disconnect_all();
+    }
+}
+
+fn press(shared: &Shared, command: Command) {
+    let (feedback, state) = {
+        let mut state = shared.state.lock().unwrap();
+        (state.press(command), state.clone())
+    };
+    tracing::debug!("Pressed {command:02x?}: {state:?}");
+    if !feedback.is_empty() {
+        // Nobody may be listening, that's fine
+        let _ = shared.events.send(Event::Feedback(feedback));
+    }
+}
+
+/// Talks to one client until either side hangs up.
+async fn serve(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let mut events = shared.events.subscribe();
+    let mut decoder = Decoder::default();
+    let mut buffer = [0; 256];
+    let mut received = 0;
+    let mut sent = 0;
+
+    send(
+        &mut stream,
+        shared,
+        &mut sent,
+        &[Packet::RegistrationRequest],
+    )
+    .await?;
+    loop {
+        tokio::select! {
+            read = stream.read(&mut buffer) => {
+                let n = read?;
+                if n == 0 {
+                    return Ok(());
+                }
+                decoder.extend(&buffer[..n]);
+                while let Some(packet) = decoder.next_packet() {
+                    received += 1;
+                    tracing::trace!("Received {packet}");
+                    let answer = match packet {
+                        Packet::Register { ip_id } if shared.ip_id.is_none_or(|id| id == ip_id) => {
+                            vec![Packet::RegistrationAccepted]
+                        }
+                        Packet::Register { .. } => vec![Packet::RegistrationRejected],
+                        Packet::Heartbeat => vec![Packet::HeartbeatResponse],
+                        Packet::Data(Data::UpdateRequest) => shared.state.lock().unwrap().report(),
+                        Packet::Data(Data::Digital { join, high: true }) => {
+                            press(shared, button(join));
+                            Vec::new()
+                        }
+                        Packet::Disconnect => return Ok(()),
+                        _ => Vec::new(),
+                    };
+                    send(&mut stream, shared, &mut sent, &answer).await?;
+
+                    let drop_after = shared.faults.lock().unwrap().drop_after;
+                    if drop_after.is_some_and(|after| received >= after) {
+                        tracing::info!("Dropping the connection after {received} packets");
+                        return Ok(());
+                    }
+                }
+            }
+            event = events.recv() => match event {
+                Ok(Event::Feedback(feedback)) => {
+                    send(&mut stream, shared, &mut sent, &feedback).await?;
+                }
+                Ok(Event::Disconnect) | Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+            },
+        }
+    }
+}
+
+/// Sends `packets`, delaying and garbling them as the faults say.
+async fn send(
+    stream: &mut TcpStream,
+    shared: &Shared,
+    sent: &mut u32,
+    packets: &[Packet],
+) -> io::Result<()> {
+    let faults = shared.faults.lock().unwrap().clone();
+    for packet in packets {
+        time::sleep(faults.delay).await;
+        *sent += 1;
+        let mut bytes = packet.encode();
+        if faults
+            .garble_every
+            .is_some_and(|every| sent.is_multiple_of(every))
+        {
+            tracing::debug!("Garbling {packet}");
+            for byte in &mut bytes[3..] {
+                *byte ^= 0xff;
+            }
+        }
+        stream.write_all(&bytes).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_change_the_state() {
+        let mut state = SimulatedState::default();
+        assert!(state.press(commands::input::HDMI).is_empty(), "it's off");
+
+        assert_eq!(
+            state.press(commands::power::ON),
+            vec![
+                digital(commands::power::ON, true),
+                digital(commands::power::OFF, false)
+            ]
+        );
+        assert_eq!(
+            state.press(commands::input::HDMI),
+            vec![
+                digital(commands::input::VGA_A, false),
+                digital(commands::input::HDMI, true)
+            ]
+        );
+        state.press(commands::input::SOURCE_BUTTON);
+        assert_eq!(state.input, Input::VgaA);
+        for _ in 0..=MAX_VOLUME {
+            state.press(commands::volume::UP);
+        }
+        state.press(commands::picture::CONTRAST_DOWN);
+        assert_eq!((state.volume, state.contrast), (MAX_VOLUME, 49));
+    }
+
+    #[test]
+    fn joins_map_back_to_buttons() {
+        let Packet::Data(Data::Digital { join, .. }) = digital(commands::menu::OK, true) else {
+            unreachable!();
+        };
+        assert_eq!(button(join), commands::menu::OK);
+    }
+}
@@ -0,0 +1,270 @@
+//! What we know about the projector: whether it's on, its input, and whether it's muted,
+//! blanked or frozen.
+//!
+//! Drivers update it from what the projector reports, e.g. Crestron's `input/hdmi` join
+//! goes high when HDMI gets selected, also when that happened with the IR remote.
+//! Projectors that don't report anything leave us with the commands we sent,
+//! so every value says where it came from and how much to trust it.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::driver::Operation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Power {
+    On,
+    Off,
+}
+
+/// Whether the projector is on, including the transitions projectors report
+/// while the lamp warms up or cools down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerState {
+    On,
+    Off,
+    /// Turned on, but it doesn't take most commands yet
+    WarmingUp,
+    /// Turned off, it can't be turned on again yet
+    CoolingDown,
+}
+
+impl From<Power> for PowerState {
+    fn from(power: Power) -> Self {
+        match power {
+            Power::On => PowerState::On,
+            Power::Off => PowerState::Off,
+        }
+    }
+}
+
+/// The inputs, named like their `input/{name}` commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Input {
+    VgaA,
+    VgaB,
+    #[serde(rename = "composite_1")]
+    Composite1,
+    #[serde(rename = "composite_2")]
+    Composite2,
+    SVideo,
+    Hdmi,
+}
+
+/// Where a value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// The projector reported it
+    Feedback,
+    /// We sent a command setting it
+    Command,
+}
+
+/// How much to trust a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    /// Reported by the projector since we're connected
+    Confirmed,
+    /// Set by a command, the IR remote may have changed it since
+    Assumed,
+    /// Reported before the connection dropped, it may have changed since
+    Stale,
+    /// We don't know anything about it
+    Unknown,
+}
+
+/// A value together with where it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Tracked<T> {
+    pub value: Option<T>,
+    pub source: Option<Source>,
+    pub confidence: Confidence,
+}
+
+impl<T> Default for Tracked<T> {
+    fn default() -> Self {
+        Self {
+            value: None,
+            source: None,
+            confidence: Confidence::Unknown,
+        }
+    }
+}
+
+impl<T> Tracked<T> {
+    pub fn set(&mut self, value: Option<T>, source: Source) {
+        self.value = value;
+        self.source = Some(source);
+        self.confidence = match (&self.value, source) {
+            (None, _) => Confidence::Unknown,
+            (Some(_), Source::Feedback) => Confidence::Confirmed,
+            (Some(_), Source::Command) => Confidence::Assumed,
+        };
+    }
+
+    fn mark_stale(&mut self) {
+        if self.confidence == Confidence::Confirmed {
+            self.confidence = Confidence::Stale;
+        }
+    }
+}
+
+/// The state of the projector as far as we know it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ProjectorState {
+    /// Whether we're connected to the projector
+    pub connected: bool,
+    #[schema(inline)]
+    pub power: Tracked<PowerState>,
+    /// `null` after cycling inputs with `input/source_button`
+    #[schema(inline)]
+    pub input: Tracked<Input>,
+    #[schema(inline)]
+    pub muted: Tracked<bool>,
+    #[schema(inline)]
+    pub blanked: Tracked<bool>,
+    #[schema(inline)]
+    pub frozen: Tracked<bool>,
+}
+
+impl ProjectorState {
+    /// Updates the state after `operation`, sent by us or as the projector reports.
+    pub fn apply(&mut self, operation: Operation, source: Source) {
+        match operation {
+            Operation::Power(power) => self.power.set(Some(power.into()), source),
+            Operation::Input(input) => self.input.set(Some(input), source),
+            Operation::NextInput => self.input.set(None, source),
+            Operation::Mute(muted) => self.muted.set(Some(muted), source),
+            Operation::Blank(blanked) => self.blanked.set(Some(blanked), source),
+            Operation::Freeze(frozen) => self.frozen.set(Some(frozen), source),
+            Operation::Volume(_) | Operation::Adjust(..) | Operation::Menu(_) => {}
+        }
+    }
+
+    /// Records the connection going up or down, what the projector reported before is stale then.
+    pub fn set_connected(&mut self, connected: bool) {
+        self.connected = connected;
+        if !connected {
+            self.power.mark_stale();
+            self.input.mark_stale();
+            self.muted.mark_stale();
+            self.blanked.mark_stale();
+            self.frozen.mark_stale();
+        }
+    }
+}
+
+/// What a scene can wait for, everything that's set has to hold.
+///
+/// Values only count once the projector reported them, commands we sent don't tell
+/// whether the projector is e.g. done warming up.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Condition {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connected: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power: Option<PowerState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<Input>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub muted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blanked: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frozen: Option<bool>,
+}
+
+impl Condition {
+    pub fn holds(&self, state: &ProjectorState) -> bool {
+        fn reported<T: PartialEq>(expected: Option<T>, tracked: &Tracked<T>) -> bool {
+            expected.is_none_or(|expected| {
+                tracked.confidence == Confidence::Confirmed && tracked.value == Some(expected)
+            })
+        }
+
+        self.connected
+            .is_none_or(|connected| state.connected == connected)
+            && reported(self.power, &state.power)
+            && reported(self.input, &state.input)
+            && reported(self.muted, &state.muted)
+            && reported(self.blanked, &state.blanked)
+            && reported(self.frozen, &state.frozen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::MenuButton;
+
+    #[test]
+    fn feedback_overrides_commands() {
+        let mut state = ProjectorState::default();
+        state.apply(Operation::Input(Input::Hdmi), Source::Command);
+        assert_eq!(state.input.value, Some(Input::Hdmi));
+        assert_eq!(state.input.confidence, Confidence::Assumed);
+
+        // Someone switched with the IR remote
+        state.apply(Operation::Input(Input::VgaA), Source::Feedback);
+        assert_eq!(state.input.value, Some(Input::VgaA));
+        assert_eq!(state.input.source, Some(Source::Feedback));
+        assert_eq!(state.input.confidence, Confidence::Confirmed);
+
+        state.apply(Operation::NextInput, Source::Command);
+        assert_eq!(state.input.value, None);
+        assert_eq!(state.input.confidence, Confidence::Unknown);
+    }
+
+    #[test]
+    fn feedback_goes_stale_when_disconnected() {
+        let mut state = ProjectorState::default();
+        state.set_connected(true);
+        state.apply(Operation::Power(Power::On), Source::Feedback);
+        state.apply(Operation::Mute(true), Source::Command);
+        state.apply(Operation::Menu(MenuButton::Ok), Source::Command);
+
+        state.set_connected(false);
+        assert_eq!(state.power.value, Some(PowerState::On));
+        assert_eq!(state.power.confidence, Confidence::Stale);
+        assert_eq!(state.muted.confidence, Confidence::Assumed);
+        assert_eq!(state.blanked, Tracked::default());
+    }
+
+    #[test]
+    fn conditions_need_feedback() {
+        let mut state = ProjectorState::default();
+        state.set_connected(true);
+        let powered_on = Condition {
+            power: Some(PowerState::On),
+            ..Condition::default()
+        };
+        assert!(Condition::default().holds(&state));
+        assert!(!powered_on.holds(&state));
+
+        // Sending power/on doesn't mean it's warmed up
+        state.apply(Operation::Power(Power::On), Source::Command);
+        assert!(!powered_on.holds(&state));
+        state
+            .power
+            .set(Some(PowerState::WarmingUp), Source::Feedback);
+        assert!(!powered_on.holds(&state));
+        state.apply(Operation::Power(Power::On), Source::Feedback);
+        assert!(powered_on.holds(&state));
+
+        let on_hdmi = Condition {
+            input: Some(Input::Hdmi),
+            ..powered_on.clone()
+        };
+        assert!(!on_hdmi.holds(&state));
+        state.apply(Operation::Input(Input::Hdmi), Source::Feedback);
+        assert!(on_hdmi.holds(&state));
+        state.set_connected(false);
+        assert!(!on_hdmi.holds(&state));
+    }
+}
@@ -0,0 +1,408 @@
+//! Runs the backend against the simulated projector.
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use hyper::{Body, Client, Method, Request, StatusCode};
+use realraum_backend_projector::{
+    protocol::commands,
+    simulator::{Faults, Input, Simulator},
+};
+use serde_json::{json, Value};
+use tokio::process::{Child, Command};
+
+/// The backend binary, controlling the simulator as its only projector.
+struct Backend {
+    addr: SocketAddr,
+    dir: PathBuf,
+    _process: Child,
+}
+
+impl Backend {
+    fn spawn(projector: SocketAddr) -> Self {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("projector-simulator-{}", addr.port()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("projector.toml");
+        std::fs::write(
+            &config,
+            format!(
+                r#"
+                addr = "{addr}"
+                static_dir = "{dir}"
+
+                [log]
+                level = "warn"
+
+                [auth]
+                tokens_file = "{dir}/tokens.json"
+                anonymous_role = "member"
+
+                [[projectors]]
+                name = "simulator"
+                host = "{host}"
+                port = {port}
+                heartbeat_secs = 1
+
+                [[scenes]]
+                name = "presentation"
+                steps = [
+                    {{ command = "power/on" }},
+                    {{ wait_for = {{ power = "on" }}, timeout_secs = 10 }},
+                    {{ command = "input/hdmi" }},
+                    {{ command = "volume/up" }},
+                ]
+
+                [[scenes]]
+                name = "slow"
+                steps = [{{ command = "volume/mute" }}, {{ wait_ms = 60000 }}, {{ command = "volume/up" }}]
+
+                [[scenes]]
+                name = "shutdown"
+                steps = [{{ command = "power/off" }}]
+                "#,
+                dir = dir.display(),
+                host = projector.ip(),
+                port = projector.port(),
+            ),
+        )
+        .unwrap();
+
+        let process = Command::new(env!("CARGO_BIN_EXE_realraum_backend_projector"))
+            .arg("--config")
+            .arg(&config)
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        Self {
+            addr,
+            dir,
+            _process: process,
+        }
+    }
+
+    async fn request(&self, method: Method, path: &str) -> (StatusCode, Value) {
+        self.send(method, path, Body::empty()).await
+    }
+
+    async fn post_json(&self, path: &str, body: Value) -> (StatusCode, Value) {
+        self.send(Method::POST, path, Body::from(body.to_string()))
+            .await
+    }
+
+    async fn send(&self, method: Method, path: &str, body: Body) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{path}", self.addr))
+            .header("content-type", "application/json")
+            .body(body)
+            .unwrap();
+        let response = Client::new().request(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    /// Polls `/api/v2/status` until `check` accepts it.
+    async fn wait_for_status(&self, check: impl Fn(&Value) -> bool) -> Value {
+        for _ in 0..100 {
+            let request = Request::get(format!("http://{}/api/v2/status", self.addr))
+                .body(Body::empty())
+                .unwrap();
+            if let Ok(response) = Client::new().request(request).await {
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                let status: Value = serde_json::from_slice(&body).unwrap();
+                if check(&status) {
+                    return status;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("The status never got there");
+    }
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn simulator() -> Simulator {
+    Simulator::bind("127.0.0.1:0".parse().unwrap(), Some(0x03))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn controls_the_simulated_projector() {
+    let simulator = simulator().await;
+    let backend = Backend::spawn(simulator.local_addr());
+    // Registered and reported the simulator's joins
+    let status = backend
+        .wait_for_status(|status| status["power"]["value"] == "off")
+        .await;
+    assert_eq!(status["connected"], true);
+    assert_eq!(status["power"]["confidence"], "confirmed");
+
+    let (status, body) = backend.request(Method::POST, "/api/v2/power/on").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["confirmed"], true);
+    let (_, body) = backend.request(Method::POST, "/api/v2/input/hdmi").await;
+    assert_eq!(body["confirmed"], true);
+    let (_, body) = backend.request(Method::POST, "/api/v2/volume/up").await;
+    assert_eq!(body["confirmed"], false, "volume isn't reported");
+    let (status, body) = backend.request(Method::GET, "/api/v1/input/hdmi").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["command"], "input/hdmi");
+    assert!(body["bytes"].is_array());
+
+    let state = simulator.state();
+    assert!(state.power);
+    assert_eq!(state.input, Input::Hdmi);
+    assert_eq!(state.volume, 11);
+
+    // Someone switches inputs with the IR remote
+    simulator.press(commands::input::S_VIDEO);
+    let status = backend
+        .wait_for_status(|status| status["input"]["value"] == "s_video")
+        .await;
+    assert_eq!(status["input"]["source"], "feedback");
+}
+
+#[tokio::test]
+async fn recovers_from_faults() {
+    let simulator = simulator().await;
+    let backend = Backend::spawn(simulator.local_addr());
+    backend
+        .wait_for_status(|status| status["power"]["value"] == "off")
+        .await;
+
+    // Slow and garbled feedback leaves commands unconfirmed, but they still arrive
+    simulator.set_faults(Faults {
+        delay: Duration::from_millis(600),
+        garble_every: Some(2),
+        ..Faults::default()
+    });
+    let (status, body) = backend.request(Method::POST, "/api/v2/power/on").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["confirmed"], false);
+    assert!(simulator.state().power);
+
+    // The backend reconnects after the projector hangs up
+    simulator.set_faults(Faults::default());
+    simulator.disconnect_all();
+    backend
+        .wait_for_status(|status| status["connected"] == false)
+        .await;
+    backend
+        .wait_for_status(|status| status["connected"] == true)
+        .await;
+
+    simulator.set_faults(Faults {
+        drop_after: Some(1),
+        ..Faults::default()
+    });
+    let (status, _) = backend.request(Method::POST, "/api/v2/volume/mute").await;
+    assert!(
+        status == StatusCode::OK || status == StatusCode::BAD_GATEWAY,
+        "{status}"
+    );
+    simulator.set_faults(Faults::default());
+    // Reported again after reconnecting
+    backend
+        .wait_for_status(|status| {
+            status["connected"] == true
+                && status["power"]["value"] == "on"
+                && status["power"]["confidence"] == "confirmed"
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn sends_commands_by_name() {
+    let simulator = simulator().await;
+    let backend = Backend::spawn(simulator.local_addr());
+    backend
+        .wait_for_status(|status| status["power"]["value"] == "off")
+        .await;
+
+    let (status, body) = backend
+        .request(Method::POST, "/api/v1/command/power/on")
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"][0]["command"], "power/on");
+    assert_eq!(body["results"][0]["confirmed"], true);
+
+    let started = Instant::now();
+    let (status, body) = backend
+        .post_json(
+            "/api/v1/projectors/simulator/command",
+            json!({
+                "commands": [
+                    {"group": "input", "name": "hdmi", "delay_ms": 200},
+                    {"group": "volume", "name": "up"},
+                    {"group": "volume", "name": "up"},
+                ],
+                "delay_ms": 100,
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    let commands: Vec<_> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["command"].as_str().unwrap())
+        .collect();
+    assert_eq!(commands, ["input/hdmi", "volume/up", "volume/up"]);
+    let state = simulator.state();
+    assert_eq!(state.input, Input::Hdmi);
+    assert_eq!(state.volume, 12);
+
+    // Nothing is sent if any command is unknown
+    let (status, body) = backend
+        .post_json(
+            "/api/v1/command",
+            json!({"commands": [{"group": "volume", "name": "up"}, {"group": "volume", "name": "max"}]}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "unknown_command");
+    // Powering off needs an admin, the anonymous member may authenticate
+    let (status, _) = backend
+        .post_json("/api/v1/command", json!({"group": "power", "name": "off"}))
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(simulator.state().volume, 12);
+    assert!(simulator.state().power);
+}
+
+#[tokio::test]
+async fn runs_scenes() {
+    let simulator = simulator().await;
+    let backend = Backend::spawn(simulator.local_addr());
+    backend
+        .wait_for_status(|status| status["power"]["value"] == "off")
+        .await;
+
+    let (status, body) = backend.request(Method::GET, "/api/v1/scenes").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["scenes"][0]["name"], "presentation");
+    assert_eq!(body["scenes"][2]["role"], "admin");
+
+    let (status, body) = backend
+        .request(Method::POST, "/api/v1/scenes/presentation/run")
+        .await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{body}");
+    let run = format!("/api/v1/scenes/runs/{}", body["run"]["id"]);
+    let mut body = Value::Null;
+    for _ in 0..50 {
+        (_, body) = backend.request(Method::GET, &run).await;
+        if body["run"]["state"] != "running" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(body["run"]["state"], "completed", "{body}");
+    assert_eq!(body["run"]["events"][0]["event"], "step");
+    assert_eq!(body["run"]["events"][0]["step"]["command"], "power/on");
+    let state = simulator.state();
+    assert!(state.power);
+    assert_eq!(state.input, Input::Hdmi);
+    assert_eq!(state.volume, 11);
+
+    let (status, body) = backend
+        .request(Method::POST, "/api/v1/projectors/simulator/scenes/slow/run")
+        .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let run = format!("/api/v1/scenes/runs/{}", body["run"]["id"]);
+    let (status, body) = backend
+        .request(Method::POST, "/api/v1/scenes/presentation/run")
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "scene_running");
+
+    // Cancel it while it waits after muting
+    for _ in 0..50 {
+        let (_, body) = backend.request(Method::GET, &run).await;
+        if body["run"]["step"] == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let (status, body) = backend
+        .request(Method::POST, &format!("{run}/cancel"))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["run"]["state"], "cancelled");
+    assert_eq!(body["run"]["step"], 1);
+    for _ in 0..50 {
+        if simulator.state().muted {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(simulator.state().muted);
+    assert_eq!(simulator.state().volume, 11);
+
+    let (status, _) = backend
+        .request(Method::POST, "/api/v1/scenes/shutdown/run")
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = backend
+        .request(Method::POST, "/api/v1/scenes/party/run")
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn allows_cross_origin_requests() {
+    let simulator = simulator().await;
+    let backend = Backend::spawn(simulator.local_addr());
+    backend
+        .wait_for_status(|status| status["connected"] == true)
+        .await;
+
+    let cors = |method: Method, path: &str| {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{path}", backend.addr))
+            .header("origin", "http://remote.example")
+            .header("access-control-request-method", "POST")
+            .header(
+                "access-control-request-headers",
+                "content-type, authorization",
+            )
+            .body(Body::empty())
+            .unwrap();
+        async move {
+            let response = Client::new().request(request).await.unwrap();
+            (response.status(), response.headers().clone())
+        }
+    };
+
+    let (status, headers) = cors(Method::OPTIONS, "/api/v2/power/on").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["access-control-allow-origin"], "*");
+    assert_eq!(
+        headers["access-control-allow-headers"],
+        "content-type,authorization"
+    );
+    assert_eq!(headers["access-control-allow-methods"], "GET,POST,PUT");
+    let (_, headers) = cors(Method::GET, "/api/v1/commands").await;
+    assert_eq!(headers["access-control-allow-origin"], "*");
+    // Also when a route refuses the client
+    let (status, headers) = cors(Method::POST, "/api/v2/power/off").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(headers["access-control-allow-origin"], "*");
+}
@@ -1,105 +1,629 @@
-use std::{
-    process::Command,
-    sync::{Arc, Mutex},
-};
+use std::sync::Arc;
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
     Json,
 };
-use hyper::{StatusCode, Uri};
-use rusqlite::Connection;
+use hyper::StatusCode;
+use realraum_backend_common::response;
 use serde::Deserialize;
 use serde_json::{json, Value};
+use utoipa::IntoParams;
 
-use crate::{db, playback::play_sound_from_path, BASE_PATH};
+use crate::{
+    config::Config,
+    data::{self, Board, Sound, Waveform},
+    db::Database,
+    error::{ApiError, ApiResult},
+    files,
+    playback::play_sound_from_path,
+    search::{self, SoundFilter},
+    throttle::{Client, Throttle},
+    waveform,
+};
 
-pub async fn fallback(_: Uri) -> (StatusCode, Json<Value>) {
-    (
-        StatusCode::NOT_FOUND,
-        Json(json!({ "status": "error", "message": "No such API route" })),
-    )
+/// API endpoint for listing all sounds on `/api/sounds`, optionally filtered by [`SoundFilter`]
+pub async fn sounds_handler(
+    Query(filter): Query<SoundFilter>,
+    State(db): State<Database>,
+) -> ApiResult<Json<Value>> {
+    let sounds = search::search_sounds(&db, &filter).await?;
+    let response = json!(sounds);
+    Ok(Json(response))
 }
 
-pub async fn greeting(_: Uri) -> (StatusCode, Json<Value>) {
-    (
-        StatusCode::OK,
-        Json(json!({
-            "status": "ok",
-            "message": "Welcome to the Realraum Sounds API",
-            "server_version": env!("CARGO_PKG_VERSION"),
-        })),
-    )
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlayRandomQuery {
+    /// Weight sounds by inverse play count
+    #[serde(default)]
+    pub(crate) weighted: bool,
 }
 
-/// API endpoint for listing all sounds on `/api/sounds`
-pub async fn sounds_handler(State(db): State<Arc<Mutex<Connection>>>) -> Json<Value> {
-    let sounds = db::get_sounds_list(&db.lock().unwrap()).unwrap();
-    let response = json!(sounds);
-    Json(response)
+/// API endpoint playing a random sound matching a [`SoundFilter`] on `/api/v1/play_random`
+pub async fn handle_play_random(
+    Query(filter): Query<SoundFilter>,
+    Query(query): Query<PlayRandomQuery>,
+    State(db): State<Database>,
+    State(config): State<Arc<Config>>,
+    State(throttle): State<Arc<Throttle>>,
+    client: Client,
+) -> ApiResult<Json<Value>> {
+    play_random(&db, &config, &throttle, &client, &filter, query.weighted)
+        .await
+        .map(played)
+}
+
+/// Plays a random sound matching `filter`, see [`play_sound`].
+pub(crate) async fn play_random(
+    db: &Database,
+    config: &Config,
+    throttle: &Throttle,
+    client: &Client,
+    filter: &SoundFilter,
+    weighted: bool,
+) -> ApiResult<Sound> {
+    let sounds = search::search_sounds(db, filter).await?;
+    let sound = search::pick_random(&sounds, weighted).ok_or(ApiError::NoMatchingSound)?;
+
+    play_sound(db, config, throttle, client, &sound.path, "random").await
+}
+
+/// API endpoint listing the tags of a sound on `/api/v1/sounds/:id/tags`
+pub async fn handle_get_tags(
+    Path(id): Path<i64>,
+    State(db): State<Database>,
+) -> ApiResult<Json<Value>> {
+    let tags = get_tags(&db, id).await?;
+    Ok(Json(json!(tags)))
+}
+
+/// Returns the tags of a sound, failing if there is no such sound.
+pub(crate) async fn get_tags(db: &Database, id: i64) -> ApiResult<Vec<String>> {
+    db.get_sound_by_id(id)
+        .await?
+        .ok_or(ApiError::SoundNotFound)?;
+
+    Ok(db.tags().await?.remove(&id).unwrap_or_default())
+}
+
+/// API endpoint replacing the tags of a sound on `/api/v1/sounds/:id/tags`
+pub async fn handle_set_tags(
+    Path(id): Path<i64>,
+    State(db): State<Database>,
+    Json(tags): Json<Vec<String>>,
+) -> ApiResult<Json<Value>> {
+    let tags = set_tags(&db, id, tags).await?;
+    Ok(Json(json!(tags)))
+}
+
+/// Replaces the tags of a sound with the normalized `tags`, returning them.
+pub(crate) async fn set_tags(db: &Database, id: i64, tags: Vec<String>) -> ApiResult<Vec<String>> {
+    db.get_sound_by_id(id)
+        .await?
+        .ok_or(ApiError::SoundNotFound)?;
+
+    let tags: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    db.set_sound_tags(id, tags.clone()).await?;
+
+    Ok(tags)
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WaveformQuery {
+    /// `json` (the default), `svg` or `png`
+    pub(crate) format: Option<String>,
+}
+
+/// API endpoint for the waveform preview of a sound on `/api/v1/sounds/:id/waveform`
+///
+/// Responds with JSON peak data by default, or with an image for `?format=svg` and `?format=png`.
+pub async fn handle_waveform(
+    Path(id): Path<i64>,
+    Query(query): Query<WaveformQuery>,
+    State(db): State<Database>,
+) -> ApiResult<Response> {
+    render_waveform(&db, id, query.format.as_deref(), |waveform| {
+        Json(json!(waveform)).into_response()
+    })
+    .await
+}
+
+/// Renders the waveform of a sound in `format`, leaving the JSON response to the API version.
+pub(crate) async fn render_waveform(
+    db: &Database,
+    id: i64,
+    format: Option<&str>,
+    json: impl FnOnce(Waveform) -> Response,
+) -> ApiResult<Response> {
+    let sound = db
+        .get_sound_by_id(id)
+        .await?
+        .ok_or(ApiError::SoundNotFound)?;
+    let waveform = db
+        .get_waveform(sound.md5sum)
+        .await?
+        .ok_or(ApiError::WaveformNotFound)?;
+
+    match format {
+        None | Some("json") => Ok(json(waveform)),
+        Some("svg") => Ok((
+            [(header::CONTENT_TYPE, "image/svg+xml")],
+            waveform::render_svg(&waveform),
+        )
+            .into_response()),
+        Some("png") => Ok((
+            [(header::CONTENT_TYPE, "image/png")],
+            waveform::render_png(&waveform),
+        )
+            .into_response()),
+        Some(_) => Err(ApiError::UnsupportedWaveformFormat),
+    }
+}
+
+/// API endpoint for listing all soundboards on `/api/v1/boards`
+pub async fn boards_handler(State(db): State<Database>) -> ApiResult<Json<Value>> {
+    let boards = db.list_boards().await?;
+    Ok(Json(json!(boards)))
+}
+
+pub async fn handle_get_board(
+    Path(name): Path<String>,
+    State(db): State<Database>,
+) -> ApiResult<Json<Value>> {
+    let board = db.get_board(&name).await?.ok_or(ApiError::BoardNotFound)?;
+    Ok(Json(json!(board)))
+}
+
+pub async fn handle_create_board(
+    State(db): State<Database>,
+    Json(board): Json<Board>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    create_board(&db, board.clone()).await?;
+    Ok((StatusCode::CREATED, Json(json!(board))))
 }
 
-/*
-pub async fn play_sound_handler(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
-    let filename = req.uri().path().trim_start_matches("/play/");
-    let filepath = format!("{}/{}", BASE_PATH, filename);
-    if Path::new(&filepath).exists() {
-        let _lock = AUDIO_LOCK.lock().unwrap();
-        thread::spawn(move || play_sound_from_path(filename));
-        Ok(Response::new(Body::empty()))
-    } else {
-        let response = "File not found";
-        Ok(Response::builder()
-            .status(404)
-            .header("Content-Type", "text/plain")
-            .body(Body::from(response))
-            .unwrap())
+pub(crate) async fn create_board(db: &Database, board: Board) -> ApiResult<()> {
+    validate_board(db, &board).await?;
+    if db.get_board(&board.name).await?.is_some() {
+        return Err(ApiError::BoardExists);
     }
+
+    db.insert_board(board).await?;
+    Ok(())
+}
+
+pub async fn handle_update_board(
+    Path(name): Path<String>,
+    State(db): State<Database>,
+    Json(board): Json<Board>,
+) -> ApiResult<Json<Value>> {
+    update_board(&db, &name, board.clone()).await?;
+    Ok(Json(json!(board)))
+}
+
+/// Replaces the board `name` with `board`, which may rename it.
+pub(crate) async fn update_board(db: &Database, name: &str, board: Board) -> ApiResult<()> {
+    validate_board(db, &board).await?;
+    if board.name != name && db.get_board(&board.name).await?.is_some() {
+        return Err(ApiError::BoardExists);
+    }
+
+    if !db.update_board(name, board).await? {
+        return Err(ApiError::BoardNotFound);
+    }
+    Ok(())
 }
-*/
 
-#[derive(Debug, Deserialize)]
-pub struct PlaySoundPayload {
-    name: String,
+pub async fn handle_delete_board(
+    Path(name): Path<String>,
+    State(db): State<Database>,
+) -> ApiResult<Json<Value>> {
+    delete_board(&db, &name).await?;
+    Ok(response::ok(json!({ "message": "Board deleted" })))
+}
+
+pub(crate) async fn delete_board(db: &Database, name: &str) -> ApiResult<()> {
+    if !db.delete_board(name).await? {
+        return Err(ApiError::BoardNotFound);
+    }
+    Ok(())
+}
+
+/// Board names end up in URLs of the compat page, so they are restricted to `[a-z0-9_-]`.
+pub(crate) fn is_valid_board_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+}
+
+async fn validate_board(db: &Database, board: &Board) -> ApiResult<()> {
+    if !is_valid_board_name(&board.name) {
+        return Err(ApiError::InvalidBoard(
+            "Board names may only contain a-z, 0-9, - and _".to_string(),
+        ));
+    }
+    if board.title.trim().is_empty() {
+        return Err(ApiError::InvalidBoard("Board title is empty".to_string()));
+    }
+    for &sound_id in &board.sounds {
+        if db.get_sound_by_id(sound_id).await?.is_none() {
+            return Err(ApiError::InvalidBoard(format!(
+                "No sound with id {sound_id}"
+            )));
+        }
+    }
+
+    Ok(())
 }
 
 pub async fn handle_play_sound(
     Path(sound_path): Path<String>,
-    State(db_con): State<Arc<Mutex<Connection>>>,
-) -> Json<Value> {
-    let mut base_path = BASE_PATH.clone();
-    dbg!(&sound_path);
-    base_path.push(&sound_path);
-    let filepath = base_path;
-    dbg!(&filepath);
-    if filepath.exists() {
-        dbg!("exists");
-        // let _lock = AUDIO_LOCK.lock().unwrap();
-
-        let has_played = play_sound_from_path(&sound_path);
-        if has_played {
-            let db = db_con.lock().unwrap();
-            let sound = db::get_sound_by_name(&db, &sound_path).unwrap().unwrap();
-            db::increment_play_count(&db, sound.id).unwrap();
-            Json(json!({ "status": "ok", "has_played": has_played }))
-        } else {
-            Json(json!({
-                "status": "error",
-                "message": "Failed to play sound",
-                "has_played": has_played
-            }))
-        }
-    } else {
-        dbg!("not exists");
-        Json(json!({ "status": "error", "message": "File not found" }))
+    State(db): State<Database>,
+    State(config): State<Arc<Config>>,
+    State(throttle): State<Arc<Throttle>>,
+    client: Client,
+) -> ApiResult<Json<Value>> {
+    play_sound(&db, &config, &throttle, &client, &sound_path, "api")
+        .await
+        .map(played)
+}
+
+/// API endpoint playing a sound by its stable id on `/api/v1/sounds/:id/play`
+pub async fn handle_play_sound_by_id(
+    Path(id): Path<i64>,
+    State(db): State<Database>,
+    State(config): State<Arc<Config>>,
+    State(throttle): State<Arc<Throttle>>,
+    client: Client,
+) -> ApiResult<Json<Value>> {
+    let sound = db
+        .get_sound_by_id(id)
+        .await?
+        .ok_or(ApiError::SoundNotFound)?;
+    play_sound(&db, &config, &throttle, &client, &sound.path, "api")
+        .await
+        .map(played)
+}
+
+/// API endpoint playing a sound by the hex md5sum of its contents
+/// on `/api/v1/sounds/by-hash/:md5sum/play`, which survives renaming the file.
+pub async fn handle_play_sound_by_hash(
+    Path(md5sum): Path<String>,
+    State(db): State<Database>,
+    State(config): State<Arc<Config>>,
+    State(throttle): State<Arc<Throttle>>,
+    client: Client,
+) -> ApiResult<Json<Value>> {
+    let md5sum = data::md5sum_from_hex(&md5sum).ok_or(ApiError::InvalidMd5sum)?;
+
+    let sound = db
+        .get_sound_by_md5sum(md5sum)
+        .await?
+        .ok_or(ApiError::SoundNotFound)?;
+    play_sound(&db, &config, &throttle, &client, &sound.path, "api")
+        .await
+        .map(played)
+}
+
+/// The v1 response to playing `sound`.
+fn played(sound: Sound) -> Json<Value> {
+    response::ok(json!({ "has_played": true, "sound": sound }))
+}
+
+/// Plays a sound by its path relative to [`Config::base_path`] and records it in the history,
+/// unless the [`Throttle`] refuses it. Shared by the API and the compat page.
+#[tracing::instrument(skip(db, config, throttle), fields(sound_id, %client))]
+pub(crate) async fn play_sound(
+    db: &Database,
+    config: &Config,
+    throttle: &Throttle,
+    client: &Client,
+    sound_path: &str,
+    source: &'static str,
+) -> ApiResult<Sound> {
+    let (sound, filepath): (Sound, _) =
+        files::resolve_sound(db, &config.base_path, sound_path).await?;
+    tracing::Span::current().record("sound_id", sound.id);
+    let permit = throttle.try_play(client, sound.id)?;
+
+    // Sounds that failed to start don't start any cooldowns
+    if let Err(e) = play_sound_from_path(&config.player, &filepath) {
+        metrics::counter!("sounds_playback_failures_total").increment(1);
+        return Err(ApiError::Playback(e));
     }
+    permit.commit();
+    metrics::counter!("sounds_plays_total", "sound" => sound.path.clone()).increment(1);
+    tracing::info!("Playing sound");
+
+    // The sound is already playing, so a failure to record it shouldn't fail the request
+    if let Err(e) = db.record_play(sound.id, source).await {
+        tracing::warn!("Failed to record play: {e:#}");
+    }
+
+    Ok(sound)
+}
+
+pub async fn handle_killall_mplayer(State(config): State<Arc<Config>>) -> ApiResult<Json<Value>> {
+    killall(&config)?;
+    Ok(response::ok(
+        json!({ "message": "Killed all mplayer instances" }),
+    ))
 }
 
-pub async fn handle_killall_mplayer() -> Json<Value> {
-    Command::new("killall")
-        .args(&["mplayer"])
+/// Stops all sounds by killing the player processes.
+pub(crate) fn killall(config: &Config) -> ApiResult<()> {
+    tokio::process::Command::new("killall")
+        .arg(config.player.process_name())
         .spawn()
-        .expect("Failed to execute killall");
+        .map_err(ApiError::Playback)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    async fn state() -> (TempDir, Database) {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path().join("sounds.db")).unwrap();
+        db.reconcile_sound(Sound {
+            name: "beep.wav".to_string(),
+            path: "beep.wav".to_string(),
+            md5sum: [1; 16],
+            id: 0,
+            play_count: 0,
+        })
+        .await
+        .unwrap();
+        (dir, db)
+    }
+
+    fn config() -> State<Arc<Config>> {
+        State(Arc::new(Config::default()))
+    }
+
+    fn throttle() -> State<Arc<Throttle>> {
+        State(Arc::new(Throttle::new(Default::default())))
+    }
+
+    fn client() -> Client {
+        Client::new("ip:127.0.0.1")
+    }
+
+    fn board(name: &str, sounds: Vec<i64>) -> Board {
+        Board {
+            name: name.to_string(),
+            title: "Event".to_string(),
+            owner: None,
+            layout: Default::default(),
+            sounds,
+        }
+    }
+
+    async fn assert_error<T: IntoResponse>(result: ApiResult<T>, status: StatusCode, code: &str) {
+        let response = match result {
+            Ok(_) => panic!("expected {code}, got a successful response"),
+            Err(e) => e.into_response(),
+        };
+        assert_eq!(response.status(), status);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "error");
+        assert_eq!(body["code"], code);
+        assert!(body["message"].is_string());
+    }
+
+    #[tokio::test]
+    async fn reports_database_errors() {
+        let (dir, db) = state().await;
+        Connection::open(dir.path().join("sounds.db"))
+            .unwrap()
+            .execute("DROP TABLE sounds", [])
+            .unwrap();
+
+        let result = sounds_handler(Query(SoundFilter::default()), State(db)).await;
+        assert_error(result, StatusCode::INTERNAL_SERVER_ERROR, "database_error").await;
+    }
+
+    #[tokio::test]
+    async fn reports_invalid_filters() {
+        let (_dir, db) = state().await;
+        let filter = |hours: i64| {
+            Query(serde_json::from_value(json!({ "not_played_hours": hours })).unwrap())
+        };
+
+        for hours in [i64::MAX, 1_000_001, -1, i64::MIN] {
+            let result = sounds_handler(filter(hours), State(db.clone())).await;
+            assert_error(result, StatusCode::BAD_REQUEST, "invalid_filter").await;
+        }
+        assert!(sounds_handler(filter(1_000_000), State(db)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reports_unknown_sounds() {
+        let (_dir, db) = state().await;
+
+        let result =
+            handle_play_sound_by_id(Path(42), State(db.clone()), config(), throttle(), client())
+                .await;
+        assert_error(result, StatusCode::NOT_FOUND, "sound_not_found").await;
+
+        let result = handle_play_sound_by_hash(
+            Path("00".repeat(16)),
+            State(db.clone()),
+            config(),
+            throttle(),
+            client(),
+        )
+        .await;
+        assert_error(result, StatusCode::NOT_FOUND, "sound_not_found").await;
+
+        let result = handle_get_tags(Path(42), State(db.clone())).await;
+        assert_error(result, StatusCode::NOT_FOUND, "sound_not_found").await;
 
-    Json(json!({ "status": "ok", "message": "Killed all mplayer instances" }))
+        let result = handle_set_tags(Path(42), State(db), Json(vec![])).await;
+        assert_error(result, StatusCode::NOT_FOUND, "sound_not_found").await;
+    }
+
+    #[tokio::test]
+    async fn reports_invalid_md5sums() {
+        let (_dir, db) = state().await;
+
+        for md5sum in ["", "zz", &"g".repeat(32), &"ä".repeat(16)] {
+            let result = handle_play_sound_by_hash(
+                Path(md5sum.to_string()),
+                State(db.clone()),
+                config(),
+                throttle(),
+                client(),
+            )
+            .await;
+            assert_error(result, StatusCode::BAD_REQUEST, "invalid_md5sum").await;
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_hostile_paths() {
+        let (_dir, db) = state().await;
+
+        let result = handle_play_sound(
+            Path("../../etc/passwd".to_string()),
+            State(db),
+            config(),
+            throttle(),
+            client(),
+        )
+        .await;
+        assert_error(result, StatusCode::BAD_REQUEST, "invalid_path").await;
+    }
+
+    #[tokio::test]
+    async fn failed_playback_starts_no_cooldown() {
+        let (dir, db) = state().await;
+        std::fs::write(dir.path().join("beep.wav"), b"").unwrap();
+        let mut config = Config {
+            base_path: dir.path().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        config.player.command = dir.path().join("missing-player").display().to_string();
+        config.player.args = vec![];
+        let State(throttle) = throttle();
+
+        let result = play_sound(&db, &config, &throttle, &client(), "beep.wav", "api").await;
+        assert_error(
+            result.map(played),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "playback_failed",
+        )
+        .await;
+        assert_eq!(throttle.cooldown(1), None);
+
+        config.player.command = "true".to_string();
+        play_sound(&db, &config, &throttle, &client(), "beep.wav", "api")
+            .await
+            .unwrap();
+        assert!(throttle.cooldown(1).is_some());
+    }
+
+    #[tokio::test]
+    async fn reports_when_nothing_matches() {
+        let (_dir, db) = state().await;
+        let filter = serde_json::from_value(json!({ "tag": "nope" })).unwrap();
+        let query = PlayRandomQuery { weighted: true };
+
+        let result = handle_play_random(
+            Query(filter),
+            Query(query),
+            State(db),
+            config(),
+            throttle(),
+            client(),
+        )
+        .await;
+        assert_error(result, StatusCode::NOT_FOUND, "no_matching_sound").await;
+    }
+
+    #[tokio::test]
+    async fn reports_missing_waveforms() {
+        let (_dir, db) = state().await;
+        let query = || Query(WaveformQuery { format: None });
+
+        let result = handle_waveform(Path(42), query(), State(db.clone())).await;
+        assert_error(result, StatusCode::NOT_FOUND, "sound_not_found").await;
+
+        let result = handle_waveform(Path(1), query(), State(db)).await;
+        assert_error(result, StatusCode::NOT_FOUND, "waveform_not_found").await;
+    }
+
+    #[tokio::test]
+    async fn reports_unsupported_waveform_formats() {
+        let (_dir, db) = state().await;
+        let waveform = data::Waveform {
+            duration_ms: 1000,
+            peaks: vec![0, 255],
+        };
+        db.insert_waveform([1; 16], waveform).await.unwrap();
+
+        let query = |format: &str| {
+            Query(WaveformQuery {
+                format: Some(format.to_string()),
+            })
+        };
+        let response = handle_waveform(Path(1), query("png"), State(db.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+
+        let result = handle_waveform(Path(1), query("gif"), State(db)).await;
+        assert_error(
+            result,
+            StatusCode::BAD_REQUEST,
+            "unsupported_waveform_format",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn reports_board_errors() {
+        let (_dir, db) = state().await;
+
+        for invalid in [board("Event", vec![]), board("event", vec![42])] {
+            let result = handle_create_board(State(db.clone()), Json(invalid)).await;
+            assert_error(result, StatusCode::BAD_REQUEST, "invalid_board").await;
+        }
+
+        let (status, _) = handle_create_board(State(db.clone()), Json(board("event", vec![1])))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let result = handle_create_board(State(db.clone()), Json(board("event", vec![]))).await;
+        assert_error(result, StatusCode::CONFLICT, "board_exists").await;
+
+        let result = handle_get_board(Path("lab".to_string()), State(db.clone())).await;
+        assert_error(result, StatusCode::NOT_FOUND, "board_not_found").await;
+
+        let result = handle_update_board(
+            Path("lab".to_string()),
+            State(db.clone()),
+            Json(board("lab", vec![])),
+        )
+        .await;
+        assert_error(result, StatusCode::NOT_FOUND, "board_not_found").await;
+
+        let result = handle_delete_board(Path("lab".to_string()), State(db)).await;
+        assert_error(result, StatusCode::NOT_FOUND, "board_not_found").await;
+    }
 }
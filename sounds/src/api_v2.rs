@@ -0,0 +1,598 @@
+//! The v2 API on `/api/v2`, with typed responses and an OpenAPI document on `/api/v2/openapi.json`.
+//!
+//! Unlike v1, every response is a `{ "status": "ok", ... }` object, and actions only take `POST`.
+//! The handlers share their logic with [`crate::api`].
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Json,
+};
+use hyper::StatusCode;
+use realraum_backend_common::{
+    auth::{self, Role},
+    error::ErrorBody,
+    openapi::{self, BearerAuth, Routes},
+    response::Status,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    api::{self, PlayRandomQuery, WaveformQuery},
+    config::Config,
+    data::{self, Board, Sound, Waveform},
+    db::Database,
+    error::{ApiError, ApiResult},
+    search::{self, SoundFilter},
+    throttle::{Client, Throttle},
+    AppState,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Realraum Sounds API"),
+    paths(
+        list_sounds,
+        get_waveform,
+        get_tags,
+        set_tags,
+        play_by_id,
+        play_by_hash,
+        play_by_path,
+        play_random,
+        killall_mplayer,
+        list_boards,
+        get_board,
+        create_board,
+        update_board,
+        delete_board,
+    ),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "sounds", description = "The sound library"),
+        (name = "playback", description = "Playing and stopping sounds"),
+        (name = "boards", description = "Soundboards, named collections of sounds"),
+    )
+)]
+struct ApiDoc;
+
+/// Routes of the v2 API, including its OpenAPI document.
+pub(crate) fn routes(config: &Config) -> Routes<AppState> {
+    // Everyone may browse and play sounds
+    let guest = Routes::new()
+        .route("/sounds", get(list_sounds))
+        .route("/sounds/:id/waveform", get(get_waveform))
+        .route("/sounds/:id/tags", get(get_tags))
+        .route("/sounds/:id/play", post(play_by_id))
+        .route("/sounds/by-hash/:md5sum/play", post(play_by_hash))
+        .route("/play/*path", post(play_by_path))
+        .route("/play_random", post(play_random))
+        .route("/boards", get(list_boards))
+        .route("/boards/:name", get(get_board))
+        .route_layer(auth::require(Role::Guest));
+
+    let mut member = Routes::new()
+        .route("/sounds/:id/tags", put(set_tags))
+        .route("/boards", post(create_board))
+        .route("/boards/:name", put(update_board));
+    if config.policies.allow_killall {
+        member = member.route("/killall_mplayer", post(killall_mplayer));
+    }
+    let member = member.route_layer(auth::require(Role::Member));
+
+    let admin = Routes::new()
+        .route("/boards/:name", delete(delete_board))
+        .route_layer(auth::require(Role::Admin));
+
+    guest
+        .merge(member)
+        .merge(admin)
+        .merge(openapi::routes(ApiDoc::openapi()))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct SoundList {
+    status: Status,
+    sounds: Vec<Sound>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct WaveformResponse {
+    status: Status,
+    waveform: Waveform,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct Tags {
+    status: Status,
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct SetTags {
+    /// Replace the tags of the sound with these, they are trimmed and lowercased
+    tags: Vec<String>,
+}
+
+/// A sound that started playing.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct Played {
+    status: Status,
+    sound: Sound,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct BoardList {
+    status: Status,
+    boards: Vec<Board>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct BoardResponse {
+    status: Status,
+    board: Board,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct Message {
+    status: Status,
+    message: String,
+}
+
+fn message(message: &str) -> Json<Message> {
+    Json(Message {
+        status: Status::Ok,
+        message: message.to_string(),
+    })
+}
+
+fn played(sound: Sound) -> Json<Played> {
+    Json(Played {
+        status: Status::Ok,
+        sound,
+    })
+}
+
+/// List the sounds matching the filters
+#[utoipa::path(
+    get,
+    path = "/api/v2/sounds",
+    tag = "sounds",
+    params(SoundFilter),
+    responses(
+        (status = 200, body = SoundList),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security((), ("token" = [])),
+)]
+async fn list_sounds(
+    Query(filter): Query<SoundFilter>,
+    State(db): State<Database>,
+) -> ApiResult<Json<SoundList>> {
+    let sounds = search::search_sounds(&db, &filter).await?;
+    Ok(Json(SoundList {
+        status: Status::Ok,
+        sounds,
+    }))
+}
+
+/// Get the waveform preview of a sound, as peak data or an SVG or PNG image
+#[utoipa::path(
+    get,
+    path = "/api/v2/sounds/{id}/waveform",
+    tag = "sounds",
+    params(("id" = i64, Path, description = "Sound id"), WaveformQuery),
+    responses(
+        (status = 200, content(
+            (WaveformResponse = "application/json"),
+            (String = "image/svg+xml"),
+            (Vec<u8> = "image/png"),
+        )),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security((), ("token" = [])),
+)]
+async fn get_waveform(
+    Path(id): Path<i64>,
+    Query(query): Query<WaveformQuery>,
+    State(db): State<Database>,
+) -> ApiResult<Response> {
+    api::render_waveform(&db, id, query.format.as_deref(), |waveform| {
+        Json(WaveformResponse {
+            status: Status::Ok,
+            waveform,
+        })
+        .into_response()
+    })
+    .await
+}
+
+/// Get the tags of a sound
+#[utoipa::path(
+    get,
+    path = "/api/v2/sounds/{id}/tags",
+    tag = "sounds",
+    params(("id" = i64, Path, description = "Sound id")),
+    responses(
+        (status = 200, body = Tags),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security((), ("token" = [])),
+)]
+async fn get_tags(Path(id): Path<i64>, State(db): State<Database>) -> ApiResult<Json<Tags>> {
+    let tags = api::get_tags(&db, id).await?;
+    Ok(Json(Tags {
+        status: Status::Ok,
+        tags,
+    }))
+}
+
+/// Replace the tags of a sound
+#[utoipa::path(
+    put,
+    path = "/api/v2/sounds/{id}/tags",
+    tag = "sounds",
+    params(("id" = i64, Path, description = "Sound id")),
+    request_body = SetTags,
+    responses(
+        (status = 200, body = Tags),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security(("token" = [])),
+)]
+async fn set_tags(
+    Path(id): Path<i64>,
+    State(db): State<Database>,
+    Json(body): Json<SetTags>,
+) -> ApiResult<Json<Tags>> {
+    let tags = api::set_tags(&db, id, body.tags).await?;
+    Ok(Json(Tags {
+        status: Status::Ok,
+        tags,
+    }))
+}
+
+/// Play a sound by its id
+#[utoipa::path(
+    post,
+    path = "/api/v2/sounds/{id}/play",
+    tag = "playback",
+    params(("id" = i64, Path, description = "Sound id")),
+    responses(
+        (status = 200, body = Played),
+        (status = 429, description = "Rate limited", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds until the sound may be played"))),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security((), ("token" = [])),
+)]
+async fn play_by_id(
+    Path(id): Path<i64>,
+    State(db): State<Database>,
+    State(config): State<Arc<Config>>,
+    State(throttle): State<Arc<Throttle>>,
+    client: Client,
+) -> ApiResult<Json<Played>> {
+    let sound = db
+        .get_sound_by_id(id)
+        .await?
+        .ok_or(ApiError::SoundNotFound)?;
+    api::play_sound(&db, &config, &throttle, &client, &sound.path, "api")
+        .await
+        .map(played)
+}
+
+/// Play a sound by the md5sum of its contents, which survives renaming the file
+#[utoipa::path(
+    post,
+    path = "/api/v2/sounds/by-hash/{md5sum}/play",
+    tag = "playback",
+    params(("md5sum" = String, Path, description = "Hex md5sum of the sound file")),
+    responses(
+        (status = 200, body = Played),
+        (status = 429, description = "Rate limited", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds until the sound may be played"))),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security((), ("token" = [])),
+)]
+async fn play_by_hash(
+    Path(md5sum): Path<String>,
+    State(db): State<Database>,
+    State(config): State<Arc<Config>>,
+    State(throttle): State<Arc<Throttle>>,
+    client: Client,
+) -> ApiResult<Json<Played>> {
+    let md5sum = data::md5sum_from_hex(&md5sum).ok_or(ApiError::InvalidMd5sum)?;
+    let sound = db
+        .get_sound_by_md5sum(md5sum)
+        .await?
+        .ok_or(ApiError::SoundNotFound)?;
+    api::play_sound(&db, &config, &throttle, &client, &sound.path, "api")
+        .await
+        .map(played)
+}
+
+/// Play a sound by its path in the sound library
+#[utoipa::path(
+    post,
+    path = "/api/v2/play/{path}",
+    tag = "playback",
+    params(("path" = String, Path, description = "Path relative to the sound library, may contain `/`")),
+    responses(
+        (status = 200, body = Played),
+        (status = 429, description = "Rate limited", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds until the sound may be played"))),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security((), ("token" = [])),
+)]
+async fn play_by_path(
+    Path(path): Path<String>,
+    State(db): State<Database>,
+    State(config): State<Arc<Config>>,
+    State(throttle): State<Arc<Throttle>>,
+    client: Client,
+) -> ApiResult<Json<Played>> {
+    api::play_sound(&db, &config, &throttle, &client, &path, "api")
+        .await
+        .map(played)
+}
+
+/// Play a random sound matching the filters
+#[utoipa::path(
+    post,
+    path = "/api/v2/play_random",
+    tag = "playback",
+    params(SoundFilter, PlayRandomQuery),
+    responses(
+        (status = 200, body = Played),
+        (status = 429, description = "Rate limited", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds until the sound may be played"))),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security((), ("token" = [])),
+)]
+async fn play_random(
+    Query(filter): Query<SoundFilter>,
+    Query(query): Query<PlayRandomQuery>,
+    State(db): State<Database>,
+    State(config): State<Arc<Config>>,
+    State(throttle): State<Arc<Throttle>>,
+    client: Client,
+) -> ApiResult<Json<Played>> {
+    api::play_random(&db, &config, &throttle, &client, &filter, query.weighted)
+        .await
+        .map(played)
+}
+
+/// Stop all playing sounds
+#[utoipa::path(
+    post,
+    path = "/api/v2/killall_mplayer",
+    tag = "playback",
+    responses(
+        (status = 200, body = Message),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security(("token" = [])),
+)]
+async fn killall_mplayer(State(config): State<Arc<Config>>) -> ApiResult<Json<Message>> {
+    api::killall(&config)?;
+    Ok(message("Killed all mplayer instances"))
+}
+
+/// List all soundboards
+#[utoipa::path(
+    get,
+    path = "/api/v2/boards",
+    tag = "boards",
+    responses(
+        (status = 200, body = BoardList),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security((), ("token" = [])),
+)]
+async fn list_boards(State(db): State<Database>) -> ApiResult<Json<BoardList>> {
+    let boards = db.list_boards().await?;
+    Ok(Json(BoardList {
+        status: Status::Ok,
+        boards,
+    }))
+}
+
+/// Get a soundboard
+#[utoipa::path(
+    get,
+    path = "/api/v2/boards/{name}",
+    tag = "boards",
+    params(("name" = String, Path, description = "Board name")),
+    responses(
+        (status = 200, body = BoardResponse),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security((), ("token" = [])),
+)]
+async fn get_board(
+    Path(name): Path<String>,
+    State(db): State<Database>,
+) -> ApiResult<Json<BoardResponse>> {
+    let board = db.get_board(&name).await?.ok_or(ApiError::BoardNotFound)?;
+    Ok(Json(BoardResponse {
+        status: Status::Ok,
+        board,
+    }))
+}
+
+/// Create a soundboard
+#[utoipa::path(
+    post,
+    path = "/api/v2/boards",
+    tag = "boards",
+    request_body = Board,
+    responses(
+        (status = 201, body = BoardResponse),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security(("token" = [])),
+)]
+async fn create_board(
+    State(db): State<Database>,
+    Json(board): Json<Board>,
+) -> ApiResult<(StatusCode, Json<BoardResponse>)> {
+    api::create_board(&db, board.clone()).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(BoardResponse {
+            status: Status::Ok,
+            board,
+        }),
+    ))
+}
+
+/// Replace a soundboard, which may rename it
+#[utoipa::path(
+    put,
+    path = "/api/v2/boards/{name}",
+    tag = "boards",
+    params(("name" = String, Path, description = "Current board name")),
+    request_body = Board,
+    responses(
+        (status = 200, body = BoardResponse),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security(("token" = [])),
+)]
+async fn update_board(
+    Path(name): Path<String>,
+    State(db): State<Database>,
+    Json(board): Json<Board>,
+) -> ApiResult<Json<BoardResponse>> {
+    api::update_board(&db, &name, board.clone()).await?;
+    Ok(Json(BoardResponse {
+        status: Status::Ok,
+        board,
+    }))
+}
+
+/// Delete a soundboard
+#[utoipa::path(
+    delete,
+    path = "/api/v2/boards/{name}",
+    tag = "boards",
+    params(("name" = String, Path, description = "Board name")),
+    responses(
+        (status = 200, body = Message),
+        (status = "default", description = "Error", body = ErrorBody),
+    ),
+    security(("token" = [])),
+)]
+async fn delete_board(
+    Path(name): Path<String>,
+    State(db): State<Database>,
+) -> ApiResult<Json<Message>> {
+    api::delete_board(&db, &name).await?;
+    Ok(message("Board deleted"))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Method, Request},
+        Router,
+    };
+    use std::collections::BTreeSet;
+
+    use realraum_backend_common::auth::Identity;
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// Status of unrouted requests, to tell them apart from handlers answering `404`.
+    const UNROUTED: StatusCode = StatusCode::IM_A_TEAPOT;
+
+    /// Sends a request as admin, so it gets past the role policies.
+    async fn status(app: &Router, method: Method, path: &str) -> StatusCode {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(Identity {
+            name: Some("test".to_string()),
+            role: Role::Admin,
+        });
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[test]
+    fn documents_all_routes() {
+        let routes = routes(&Config::default()).paths().clone();
+        let documented: BTreeSet<_> = ApiDoc::openapi()
+            .paths
+            .paths
+            .into_keys()
+            .map(|path| path.trim_start_matches("/api/v2").to_string())
+            .chain(openapi::REFERENCE_PATHS.map(str::to_string))
+            .collect();
+
+        assert_eq!(routes, documented);
+    }
+
+    #[tokio::test]
+    async fn spec_matches_route_methods() {
+        let dir = TempDir::new().unwrap();
+        let mut config = Config::default();
+        // Don't kill any real player while testing killall_mplayer
+        config.player.command = "realraum-test-player".to_string();
+        let config = Arc::new(config);
+        let app = Router::new()
+            .nest("/api/v2", routes(&config).into())
+            .fallback(|| async { UNROUTED })
+            .with_state(AppState {
+                db: Database::open(dir.path().join("sounds.db")).unwrap(),
+                throttle: Arc::new(Throttle::new(config.policies.rate_limit.clone())),
+                config,
+            });
+
+        let spec = ApiDoc::openapi();
+        assert!(!spec.paths.paths.is_empty());
+        for (path, item) in &spec.paths.paths {
+            // Handlers may reject the values, but any value matches the route
+            let uri = path.replace(['{', '}'], "");
+            let documented = [
+                (Method::GET, &item.get),
+                (Method::POST, &item.post),
+                (Method::PUT, &item.put),
+                (Method::DELETE, &item.delete),
+            ];
+
+            for (method, operation) in documented {
+                let status = status(&app, method.clone(), &uri).await;
+                if operation.is_some() {
+                    assert!(
+                        status != UNROUTED && status != StatusCode::METHOD_NOT_ALLOWED,
+                        "{method} {path} isn't routed"
+                    );
+                } else {
+                    assert_eq!(
+                        status,
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        "{method} {path} is routed but not documented"
+                    );
+                }
+            }
+        }
+
+        assert_eq!(
+            status(&app, Method::GET, "/api/v2/openapi.json").await,
+            StatusCode::OK
+        );
+    }
+}
@@ -1,19 +1,44 @@
-use std::{
-    process::Command,
-    sync::{Arc, Mutex},
-};
+use std::sync::Arc;
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::{Html, IntoResponse, Redirect},
 };
-use rusqlite::Connection;
+use serde::Deserialize;
 
-use crate::{data::Sound, db, playback::play_sound_from_path, BASE_PATH};
+use crate::{
+    api,
+    config::Config,
+    csrf::{CsrfProtected, CsrfToken},
+    data::{BoardLayout, Sound},
+    db::Database,
+    error::{ApiError, ApiResult},
+    throttle::{self, Client, Throttle},
+};
+
+/// Query of the pages, set when redirecting back after a refused play.
+#[derive(Debug, Default, Deserialize)]
+pub struct Notice {
+    retry_after: Option<u64>,
+}
+
+impl Notice {
+    fn to_html(&self) -> String {
+        match self.retry_after {
+            Some(secs) => format!("<p><strong>Rate limited, retry after {secs} s</strong></p>"),
+            None => String::new(),
+        }
+    }
+}
 
 /// API endpoint for listing all sounds on `/api/sounds`
-pub async fn html_page_handler(State(db): State<Arc<Mutex<Connection>>>) -> impl IntoResponse {
-    let sounds = db::get_sounds_list(&db.lock().unwrap()).unwrap();
+pub async fn html_page_handler(
+    csrf: CsrfToken,
+    Query(notice): Query<Notice>,
+    State(db): State<Database>,
+    State(throttle): State<Arc<Throttle>>,
+) -> ApiResult<impl IntoResponse> {
+    let sounds = db.list_sounds().await?;
 
     let mut html = String::from(
         "<html><head><title>realraum Sounds</title></head><body><h1>realraum Sounds</h1>",
@@ -24,69 +49,290 @@ pub async fn html_page_handler(State(db): State<Arc<Mutex<Connection>>>) -> impl
         env!("CARGO_PKG_VERSION")
     ));
 
-    html.push_str(
-        "<a href=\"/compat-sounds/api-c1/killall_mplayer\">Kill all mplayer instances</a></p><table>",
-    );
+    html.push_str(&format_killall_form(None, &csrf));
+    html.push_str("</p>");
+    html.push_str(&notice.to_html());
+    html.push_str("<table>");
 
     let mut sounds = sounds;
     sounds.sort_unstable_by(|a, b| a.name.cmp(&b.name));
-    sounds.sort_by(|a, b| b.play_count.cmp(&a.play_count));
+    sounds.sort_by_key(|sound| std::cmp::Reverse(sound.play_count));
 
-    fn format_table_cell(sound: &Sound) -> String {
-        let Sound {
-            name,
-            path,
-            play_count,
-            ..
-        } = sound;
+    for sound_batch in sounds.chunks(3) {
+        html.push_str("<tr>");
+        for sound in sound_batch {
+            html.push_str(&format_table_cell(sound, None, &throttle, &csrf));
+        }
+        html.push_str("</tr>");
+    }
+
+    html.push_str("</table></body></html>");
 
-        format!("<td><a href=\"/compat-sounds/api-c1/play/{path}\">{name}</a> ({play_count} plays)</td>")
+    Ok((csrf, Html(html)))
+}
+
+/// Page showing just the sounds of one board on `/compat-sounds/board/:name`, in board order
+pub async fn board_page_handler(
+    csrf: CsrfToken,
+    Path(board_name): Path<String>,
+    Query(notice): Query<Notice>,
+    State(db): State<Database>,
+    State(throttle): State<Arc<Throttle>>,
+) -> impl IntoResponse {
+    let Ok(Some(board)) = db.get_board(&board_name).await else {
+        return Redirect::to("/compat-sounds").into_response();
+    };
+    let mut sounds = Vec::with_capacity(board.sounds.len());
+    for &id in &board.sounds {
+        if let Ok(Some(sound)) = db.get_sound_by_id(id).await {
+            sounds.push(sound);
+        }
     }
 
-    for sound_batch in sounds.chunks(3) {
+    let title = escape_html(&board.title);
+    let mut html = format!(
+        "<html><head><title>{title} - realraum Sounds</title></head><body><h1>{title}</h1>"
+    );
+
+    html.push_str("<p><a href=\"/compat-sounds\">All sounds</a> &nbsp; - &nbsp;");
+    html.push_str(&format_killall_form(Some(&board.name), &csrf));
+    html.push_str("</p>");
+    html.push_str(&notice.to_html());
+    html.push_str("<table>");
+
+    let columns = match board.layout {
+        BoardLayout::Grid => 3,
+        BoardLayout::List => 1,
+    };
+    for sound_batch in sounds.chunks(columns) {
         html.push_str("<tr>");
         for sound in sound_batch {
-            html.push_str(&format_table_cell(sound));
+            html.push_str(&format_table_cell(
+                sound,
+                Some(&board.name),
+                &throttle,
+                &csrf,
+            ));
         }
         html.push_str("</tr>");
     }
 
     html.push_str("</table></body></html>");
 
-    Html(html)
+    (csrf, Html(html)).into_response()
 }
 
+/// Formats a button posting to `action`, returning to `board` afterwards if given.
+///
+/// Actions are forms instead of links, so link prefetchers and crawlers can't trigger them.
+fn format_form(action: &str, board: Option<&str>, label: &str, csrf: &CsrfToken) -> String {
+    let query = board
+        .map(|board| format!("?board={board}"))
+        .unwrap_or_default();
+    let action = escape_html(&format!("{action}{query}"));
+    format!(
+        "<form method=\"post\" action=\"{action}\" style=\"display: inline\">{}<button>{label}</button></form>",
+        csrf.form_field()
+    )
+}
+
+fn format_killall_form(board: Option<&str>, csrf: &CsrfToken) -> String {
+    format_form(
+        "/compat-sounds/api-c1/killall_mplayer",
+        board,
+        "Kill all mplayer instances",
+        csrf,
+    )
+}
+
+/// Formats a button playing `sound`, returning to `board` afterwards if given.
+fn format_table_cell(
+    sound: &Sound,
+    board: Option<&str>,
+    throttle: &Throttle,
+    csrf: &CsrfToken,
+) -> String {
+    let Sound {
+        id,
+        name,
+        path,
+        play_count,
+        ..
+    } = sound;
+    let name = escape_html(name);
+    let cooldown = throttle
+        .cooldown(*id)
+        .map(|cooldown| format!(", cooling down for {} s", throttle::ceil_secs(cooldown)))
+        .unwrap_or_default();
+
+    let form = format_form(
+        &format!("/compat-sounds/api-c1/play/{}", encode_path(path)),
+        board,
+        &name,
+        csrf,
+    );
+
+    format!("<td>{form} ({play_count} plays{cooldown})</td>")
+}
+
+/// Percent-encodes each segment of a sound path, so names with spaces, `?`, `#` or `%`
+/// reach the play route as they are.
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            segment
+                .bytes()
+                .map(|byte| match byte {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                        char::from(byte).to_string()
+                    }
+                    _ => format!("%{byte:02X}"),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReturnTo {
+    board: Option<String>,
+}
+
+impl ReturnTo {
+    /// Redirects back to the page the user came from,
+    /// with `303 See Other` so browsers don't submit the form again.
+    fn redirect(&self) -> Redirect {
+        Redirect::to(&self.page())
+    }
+
+    /// Redirects back, telling the user when they may retry.
+    fn redirect_throttled(&self, retry_after_secs: u64) -> Redirect {
+        Redirect::to(&format!("{}?retry_after={retry_after_secs}", self.page()))
+    }
+
+    /// The board's page, or the list of all sounds if `board` isn't a valid board name,
+    /// which also keeps anything but `[a-z0-9_-]` out of the `Location` header.
+    fn page(&self) -> String {
+        match &self.board {
+            Some(board) if api::is_valid_board_name(board) => {
+                format!("/compat-sounds/board/{board}")
+            }
+            _ => "/compat-sounds".to_string(),
+        }
+    }
+}
+
+/// Form playing a sound, see [`handle_play_sound`].
+pub async fn handle_play_sound_form(
+    path: Path<String>,
+    return_to: Query<ReturnTo>,
+    db: State<Database>,
+    config: State<Arc<Config>>,
+    throttle: State<Arc<Throttle>>,
+    client: Client,
+    _: CsrfProtected,
+) -> impl IntoResponse {
+    handle_play_sound(path, return_to, db, config, throttle, client).await
+}
+
+/// Deprecated `GET` route playing a sound, the page uses [`handle_play_sound_form`].
 pub async fn handle_play_sound(
     Path(sound_path): Path<String>,
-    State(db_con): State<Arc<Mutex<Connection>>>,
+    Query(return_to): Query<ReturnTo>,
+    State(db): State<Database>,
+    State(config): State<Arc<Config>>,
+    State(throttle): State<Arc<Throttle>>,
+    client: Client,
 ) -> impl IntoResponse {
-    let mut base_path = BASE_PATH.clone();
-    dbg!(&sound_path);
-    base_path.push(&sound_path);
-    let filepath = base_path;
-    dbg!(&filepath);
-    if filepath.exists() {
-        dbg!("exists");
-        // let _lock = AUDIO_LOCK.lock().unwrap();
-
-        let has_played = play_sound_from_path(&sound_path);
-        if has_played {
-            let db = db_con.lock().unwrap();
-            let sound = db::get_sound_by_name(&db, &sound_path).unwrap().unwrap();
-            db::increment_play_count(&db, sound.id).unwrap();
+    match api::play_sound(&db, &config, &throttle, &client, &sound_path, "compat").await {
+        Ok(_) => {}
+        Err(ApiError::RateLimited(throttled)) => {
+            tracing::info!("{throttled}");
+            return return_to.redirect_throttled(throttled.retry_after_secs());
         }
+        Err(ApiError::Playback(e)) => tracing::error!("Playback failed: {e}"),
+        Err(ApiError::Database(e)) => tracing::error!("Database error: {e:#}"),
+        Err(e) => tracing::info!("Refused to play sound {sound_path}: {e}"),
     }
 
     // We don't show errors to the user in the compat html page
-    Redirect::temporary("/compat-sounds")
+    return_to.redirect()
+}
+
+/// Form stopping all sounds, see [`handle_killall_mplayer`].
+pub async fn handle_killall_mplayer_form(
+    return_to: Query<ReturnTo>,
+    config: State<Arc<Config>>,
+    _: CsrfProtected,
+) -> impl IntoResponse {
+    handle_killall_mplayer(return_to, config).await
 }
 
-pub async fn handle_killall_mplayer() -> impl IntoResponse {
-    Command::new("killall")
-        .args(&["mplayer"])
-        .spawn()
-        .expect("Failed to execute killall");
+/// Deprecated `GET` route stopping all sounds, the page uses [`handle_killall_mplayer_form`].
+pub async fn handle_killall_mplayer(
+    Query(return_to): Query<ReturnTo>,
+    State(config): State<Arc<Config>>,
+) -> impl IntoResponse {
+    if let Err(e) = api::killall(&config) {
+        tracing::error!("Failed to execute killall: {e:?}");
+    }
 
     // We don't show errors to the user in the compat html page
-    Redirect::temporary("/compat-sounds")
+    return_to.redirect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_sound_paths_for_the_play_route() {
+        assert_eq!(encode_path("nested/deep.wav"), "nested/deep.wav");
+        assert_eq!(
+            encode_path("a b/100% \"loud\"?#<x>.mp3"),
+            "a%20b/100%25%20%22loud%22%3F%23%3Cx%3E.mp3"
+        );
+        assert_eq!(encode_path("Grüße.ogg"), "Gr%C3%BC%C3%9Fe.ogg");
+    }
+
+    #[test]
+    fn returns_only_to_valid_boards() {
+        let page = |board: &str| {
+            ReturnTo {
+                board: Some(board.to_string()),
+            }
+            .page()
+        };
+
+        assert_eq!(ReturnTo::default().page(), "/compat-sounds");
+        assert_eq!(page("event-2024_b"), "/compat-sounds/board/event-2024_b");
+        for board in [
+            "",
+            "a\r\nSet-Cookie: x=1",
+            "../../api",
+            "//evil.example",
+            "Board",
+            "ä",
+        ] {
+            assert_eq!(page(board), "/compat-sounds");
+        }
+        // Invalid header characters used to make `Redirect::to` panic
+        let response = ReturnTo {
+            board: Some("a\nb".to_string()),
+        }
+        .redirect_throttled(3)
+        .into_response();
+        assert_eq!(
+            response.headers()["location"],
+            "/compat-sounds?retry_after=3"
+        );
+    }
 }
@@ -0,0 +1,171 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use anyhow::{bail, Result};
+use realraum_backend_common::{auth::AuthConfig, logging::LogConfig};
+use serde::{Deserialize, Serialize};
+
+/// Configuration of the sounds backend, see [`realraum_backend_common::config`] for how it's loaded.
+///
+/// The pre-config env vars `R3_SOUNDS_ADDR` and `R3_SOUNDS_BASE_PATH` keep working as overrides.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Address the HTTP server listens on
+    pub addr: SocketAddr,
+    /// Directory containing the sound library
+    pub base_path: PathBuf,
+    /// SQLite database file, created if missing
+    pub database: PathBuf,
+    /// Directory of the frontend's static assets
+    pub static_dir: PathBuf,
+    pub log: LogConfig,
+    pub auth: AuthConfig,
+    pub player: PlayerConfig,
+    pub policies: Policies,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:4242".parse().unwrap(),
+            base_path: PathBuf::from("/home/realraum/welcomesounds"),
+            database: PathBuf::from("sounds.db"),
+            static_dir: PathBuf::from("dist"),
+            log: LogConfig::default(),
+            auth: AuthConfig::default(),
+            player: PlayerConfig::default(),
+            policies: Policies::default(),
+        }
+    }
+}
+
+impl realraum_backend_common::config::Config for Config {
+    const ENV_PREFIX: &'static str = "R3_SOUNDS_";
+    const DEFAULT_PATH: &'static str = "sounds.toml";
+
+    fn validate(&self) -> Result<()> {
+        if self.database.as_os_str().is_empty() {
+            bail!("database must not be empty");
+        }
+        if self.player.command.trim().is_empty() {
+            bail!("player.command must not be empty");
+        }
+        Ok(())
+    }
+}
+
+/// The external program playing the sounds, called with [`PlayerConfig::args`] followed by the file path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerConfig {
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        Self {
+            command: "mplayer".to_string(),
+            args: ["-really-quiet", "-nolirc", "-ao", "alsa"]
+                .map(str::to_string)
+                .to_vec(),
+        }
+    }
+}
+
+impl PlayerConfig {
+    /// Name of the player process, for `killall`.
+    pub fn process_name(&self) -> &str {
+        self.command.rsplit('/').next().unwrap_or(&self.command)
+    }
+}
+
+/// What clients are allowed to do.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Policies {
+    /// Serve the HTML page on `/compat-sounds` for clients without JavaScript
+    pub compat_page: bool,
+    /// Let clients stop all playing sounds through the `killall_mplayer` routes
+    pub allow_killall: bool,
+    /// Keep serving the deprecated `GET` routes playing sounds and killing the player,
+    /// which link prefetchers and crawlers can trigger by accident
+    pub legacy_get_routes: bool,
+    pub rate_limit: RateLimit,
+}
+
+impl Default for Policies {
+    fn default() -> Self {
+        Self {
+            compat_page: true,
+            allow_killall: true,
+            legacy_get_routes: true,
+            rate_limit: RateLimit::default(),
+        }
+    }
+}
+
+/// How often sounds may be played, see [`crate::throttle`]. Set a value to 0 to disable it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    /// Plays per client within a minute, clients are told apart by token or SSO user, else by IP
+    pub plays_per_minute: u32,
+    /// Seconds before the same sound can be played again
+    pub sound_cooldown_secs: u64,
+    /// Seconds between any two sounds
+    pub global_cooldown_secs: u64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            plays_per_minute: 10,
+            sound_cooldown_secs: 30,
+            global_cooldown_secs: 1,
+        }
+    }
+}
+
+#[cfg(test)]
+// The closures `Jail` runs return figment's error, which is large
+#[allow(clippy::result_large_err)]
+mod tests {
+    use figment::Jail;
+    use realraum_backend_common::config::{self as common_config, ConfigArgs};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn keeps_the_legacy_env_vars() {
+        Jail::expect_with(|jail| {
+            jail.set_env("R3_SOUNDS_ADDR", "127.0.0.1:1234");
+            jail.set_env("R3_SOUNDS_BASE_PATH", "/srv/sounds");
+            jail.set_env("R3_SOUNDS_PLAYER__COMMAND", "mpv");
+            let config: Config = common_config::load(&ConfigArgs::default(), json!({})).unwrap();
+            assert_eq!(config.addr, "127.0.0.1:1234".parse().unwrap());
+            assert_eq!(config.base_path, PathBuf::from("/srv/sounds"));
+            assert_eq!(config.player.command, "mpv");
+            assert_eq!(config.player.args, PlayerConfig::default().args);
+
+            // The flags still win over them
+            let config: Config =
+                common_config::load(&ConfigArgs::default(), json!({ "base_path": "/tmp" }))
+                    .unwrap();
+            assert_eq!(config.base_path, PathBuf::from("/tmp"));
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rejects_an_empty_player() {
+        Jail::expect_with(|jail| {
+            jail.create_file("sounds.toml", "[player]\ncommand = \" \"")?;
+            let error =
+                common_config::load::<Config>(&ConfigArgs::default(), json!({})).unwrap_err();
+            assert!(format!("{error:#}").contains("player.command"), "{error:#}");
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,170 @@
+//! CSRF protection of the compat page's forms with the double submit cookie pattern.
+//!
+//! The page sets a random token as cookie and puts the same token into every form,
+//! [`CsrfProtected`] then only accepts submissions where both match.
+//! Other sites can't read the cookie, so they can't forge a matching form.
+
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::{FromRequest, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Request},
+    response::{IntoResponseParts, ResponseParts},
+    BoxError, Form,
+};
+use hyper::StatusCode;
+use rand::RngCore;
+use serde::Deserialize;
+
+const COOKIE: &str = "csrf_token";
+
+/// The CSRF token of the client, taken from its cookie or newly generated.
+///
+/// Returning it as part of the response sets the cookie if it's new.
+#[derive(Debug, Clone)]
+pub struct CsrfToken {
+    value: String,
+    is_new: bool,
+}
+
+impl CsrfToken {
+    /// Hidden input carrying the token, for the forms of the page.
+    pub fn form_field(&self) -> String {
+        format!(
+            "<input type=\"hidden\" name=\"{COOKIE}\" value=\"{}\">",
+            self.value
+        )
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CsrfToken {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(match cookie_token(&parts.headers) {
+            Some(value) => CsrfToken {
+                value: value.to_string(),
+                is_new: false,
+            },
+            None => {
+                let mut bytes = [0; 32];
+                rand::thread_rng().fill_bytes(&mut bytes);
+                CsrfToken {
+                    value: bytes.iter().map(|byte| format!("{byte:02x}")).collect(),
+                    is_new: true,
+                }
+            }
+        })
+    }
+}
+
+impl IntoResponseParts for CsrfToken {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if self.is_new {
+            let cookie = format!(
+                "{COOKIE}={}; Path=/compat-sounds; SameSite=Strict; HttpOnly",
+                self.value
+            );
+            // The token is hex, so it's always a valid header value
+            if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+                res.headers_mut().insert(header::SET_COOKIE, cookie);
+            }
+        }
+        Ok(res)
+    }
+}
+
+fn cookie_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|&(name, value)| name == COOKIE && !value.is_empty())
+        .map(|(_, value)| value)
+}
+
+#[derive(Debug, Deserialize)]
+struct CsrfForm {
+    csrf_token: String,
+}
+
+/// Extractor rejecting form submissions without a valid CSRF token with `403`,
+/// has to be the last argument of a handler as it consumes the body.
+#[derive(Debug)]
+pub struct CsrfProtected;
+
+#[async_trait]
+impl<S, B> FromRequest<S, B> for CsrfProtected
+where
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = realraum_backend_common::Error;
+
+    async fn from_request(request: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let cookie = cookie_token(request.headers()).map(str::to_string);
+        let form = Form::<CsrfForm>::from_request(request, state).await;
+
+        match (cookie, form) {
+            (Some(cookie), Ok(Form(form))) if constant_time_eq(&cookie, &form.csrf_token) => {
+                Ok(CsrfProtected)
+            }
+            _ => Err(realraum_backend_common::Error::new(
+                StatusCode::FORBIDDEN,
+                "invalid_csrf_token",
+                "Invalid CSRF token, reload the page and try again",
+            )),
+        }
+    }
+}
+
+/// Compares without returning early, so response times don't leak how much of a token matched.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    fn form(cookie: Option<&str>, body: &str) -> Request<Body> {
+        let mut request = Request::post("/compat-sounds/api-c1/play/beep.wav")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        request.body(Body::from(body.to_string())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn accepts_matching_tokens() {
+        let request = form(Some("theme=dark; csrf_token=abc123"), "csrf_token=abc123");
+        assert!(CsrfProtected::from_request(request, &()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_forged_forms() {
+        for request in [
+            form(None, "csrf_token=abc123"),
+            form(Some("csrf_token=abc123"), "csrf_token=abc124"),
+            form(Some("csrf_token=abc123"), ""),
+            form(Some("csrf_token="), "csrf_token="),
+        ] {
+            let rejection = CsrfProtected::from_request(request, &()).await.unwrap_err();
+            assert_eq!(rejection.status, StatusCode::FORBIDDEN);
+        }
+    }
+}
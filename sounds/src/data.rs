@@ -1,12 +1,82 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize, Serializer};
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub(crate) struct Sound {
     pub(crate) name: String,
+    /// Path relative to the sound library
     pub(crate) path: String,
-    #[serde(skip)]
+    /// Hash of the file contents, serialized as lowercase hex
+    #[serde(serialize_with = "serialize_md5sum")]
+    #[schema(value_type = String, example = "73aac2c7174915911a70eae84c4f188a")]
     pub(crate) md5sum: [u8; 16],
     pub(crate) id: i64,
     pub(crate) play_count: i64,
     // last_played: Option<DateTime<Utc>>,
 }
+
+pub(crate) fn md5sum_to_hex(md5sum: &[u8; 16]) -> String {
+    md5sum.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Parses a hex-encoded md5sum as produced by [`md5sum_to_hex`], in either case.
+pub(crate) fn md5sum_from_hex(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut md5sum = [0; 16];
+    for (i, byte) in md5sum.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(md5sum)
+}
+
+fn serialize_md5sum<S: Serializer>(md5sum: &[u8; 16], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&md5sum_to_hex(md5sum))
+}
+
+/// Downsampled peak data of a sound, cached by the sound's `md5sum`.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct Waveform {
+    pub(crate) duration_ms: i64,
+    /// Absolute peak amplitude per bucket, scaled to `0..=255`.
+    pub(crate) peaks: Vec<u8>,
+}
+
+/// A named, ordered collection of sounds, e.g. the "event" board on the wall tablet.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub(crate) struct Board {
+    pub(crate) name: String,
+    pub(crate) title: String,
+    #[serde(default)]
+    pub(crate) owner: Option<String>,
+    #[serde(default)]
+    pub(crate) layout: BoardLayout,
+    /// Sound ids in display order
+    pub(crate) sounds: Vec<i64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BoardLayout {
+    #[default]
+    Grid,
+    List,
+}
+
+impl BoardLayout {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            BoardLayout::Grid => "grid",
+            BoardLayout::List => "list",
+        }
+    }
+
+    pub(crate) fn parse(layout: &str) -> Self {
+        match layout {
+            "list" => BoardLayout::List,
+            _ => BoardLayout::Grid,
+        }
+    }
+}
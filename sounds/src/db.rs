@@ -1,13 +1,163 @@
-// use chrono::{DateTime, TimeZone, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::Connection;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 
 use crate::data::{self};
 
-pub fn make_some_db() -> Result<Connection> {
-    let db = Connection::open("sounds.db")?;
+/// Number of SQLite connections, i.e. queries that can run at the same time.
+const POOL_SIZE: u32 = 4;
+
+/// How long a connection waits for another one to release a write lock.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Async access to the sounds database.
+///
+/// Queries run on tokio's blocking threads with their own pooled connection,
+/// so slow queries like searches don't stall other requests, e.g. playback.
+/// The database uses WAL mode, which lets readers and a writer work concurrently.
+#[derive(Clone)]
+pub struct Database {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+impl Database {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let manager = SqliteConnectionManager::file(path).with_init(|db| {
+            db.busy_timeout(BUSY_TIMEOUT)?;
+            db.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+            Ok(())
+        });
+        let pool = Pool::builder()
+            .max_size(POOL_SIZE)
+            .build(manager)
+            .context("Failed to open database")?;
+
+        create_tables(&*pool.get()?)?;
+
+        Ok(Self { pool })
+    }
+
+    /// Runs `query` with a pooled connection on a blocking thread.
+    async fn run<T, F>(&self, query: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || query(&*pool.get()?))
+            .await
+            .map_err(|e| anyhow!("Database query panicked: {e}"))?
+    }
+
+    /// Checks that the database can be queried at all, for readiness probes.
+    pub async fn ping(&self) -> Result<()> {
+        self.run(|db| Ok(db.query_row("SELECT 1", [], |_| Ok(()))?))
+            .await
+    }
+
+    pub async fn list_sounds(&self) -> Result<Vec<data::Sound>> {
+        self.run(get_sounds_list).await
+    }
+
+    pub async fn get_sound_by_id(&self, id: i64) -> Result<Option<data::Sound>> {
+        self.run(move |db| get_sound_by_id(db, id)).await
+    }
+
+    pub async fn get_sound_by_path(&self, path: &str) -> Result<Option<data::Sound>> {
+        let path = path.to_string();
+        self.run(move |db| get_sound_by_path(db, &path)).await
+    }
+
+    pub async fn get_sound_by_md5sum(&self, md5sum: [u8; 16]) -> Result<Option<data::Sound>> {
+        self.run(move |db| get_sound_by_md5sum(db, &md5sum)).await
+    }
+
+    /// Inserts a sound found on disk, or updates its `md5sum` if the file at that path changed.
+    pub async fn reconcile_sound(&self, sound: data::Sound) -> Result<Reconciliation> {
+        self.run(move |db| reconcile_sound(db, &sound)).await
+    }
+
+    /// Records a play of a sound in the history, along with what triggered it, e.g. `"random"`.
+    pub async fn record_play(&self, sound_id: i64, source: &'static str) -> Result<()> {
+        self.run(move |db| record_play(db, sound_id, source)).await
+    }
+
+    /// Returns when each sound that has ever been played was played last, by sound id.
+    pub async fn last_played(&self) -> Result<HashMap<i64, DateTime<Utc>>> {
+        self.run(get_last_played).await
+    }
+
+    /// Returns the duration of every sound with a cached waveform, by sound id.
+    pub async fn durations(&self) -> Result<HashMap<i64, i64>> {
+        self.run(get_durations).await
+    }
+
+    /// Returns the tags of every tagged sound, by sound id.
+    pub async fn tags(&self) -> Result<HashMap<i64, Vec<String>>> {
+        self.run(get_tags).await
+    }
+
+    pub async fn set_sound_tags(&self, sound_id: i64, tags: Vec<String>) -> Result<()> {
+        self.run(move |db| set_sound_tags(db, sound_id, &tags))
+            .await
+    }
+
+    pub async fn get_waveform(&self, md5sum: [u8; 16]) -> Result<Option<data::Waveform>> {
+        self.run(move |db| get_waveform(db, &md5sum)).await
+    }
+
+    pub async fn insert_waveform(&self, md5sum: [u8; 16], waveform: data::Waveform) -> Result<()> {
+        self.run(move |db| insert_waveform(db, &md5sum, &waveform))
+            .await
+    }
+
+    /// Removes the sounds whose file isn't among `paths` any more, along with their plays,
+    /// tags and places on boards. Returns how many sounds were removed.
+    pub async fn remove_missing_sounds(&self, paths: Vec<String>) -> Result<usize> {
+        self.run(move |db| remove_missing_sounds(db, &paths)).await
+    }
+
+    /// Removes cached waveforms that no longer belong to any sound.
+    pub async fn prune_waveforms(&self) -> Result<usize> {
+        self.run(prune_waveforms).await
+    }
+
+    pub async fn list_boards(&self) -> Result<Vec<data::Board>> {
+        self.run(get_boards).await
+    }
+
+    pub async fn get_board(&self, name: &str) -> Result<Option<data::Board>> {
+        let name = name.to_string();
+        self.run(move |db| get_board_by_name(db, &name)).await
+    }
+
+    pub async fn insert_board(&self, board: data::Board) -> Result<()> {
+        self.run(move |db| insert_board(db, &board)).await
+    }
+
+    /// Replaces the board called `name`, returning `false` if there is no such board.
+    pub async fn update_board(&self, name: &str, board: data::Board) -> Result<bool> {
+        let name = name.to_string();
+        self.run(move |db| update_board(db, &name, &board)).await
+    }
+
+    /// Deletes the board called `name`, returning `false` if there is no such board.
+    pub async fn delete_board(&self, name: &str) -> Result<bool> {
+        let name = name.to_string();
+        self.run(move |db| delete_board(db, &name)).await
+    }
+}
 
+fn create_tables(db: &Connection) -> Result<()> {
     // db.execute(
     //     "DROP TABLE IF EXISTS sounds",
     //     // "DROP TABLE IF EXISTS sound_events",
@@ -26,53 +176,186 @@ pub fn make_some_db() -> Result<Connection> {
         [],
     )?;
 
-    // db.execute(
-    //     "CREATE TABLE IF NOT EXISTS sound_events (
-    //         id UID PRIMARY KEY,
-    //         sound_id UID NOT NULL,
-    //         timestamp DATETIME NOT NULL,
-    //         FOREIGN KEY (sound_id) REFERENCES sounds(id)
-    //     )",
-    //     [],
-    // )?;
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS waveforms (
+            md5sum BLOB PRIMARY KEY,
+            duration_ms INTEGER NOT NULL,
+            peaks BLOB NOT NULL
+        )",
+        [],
+    )?;
+
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS boards (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE,
+            title TEXT NOT NULL,
+            owner TEXT,
+            layout TEXT NOT NULL DEFAULT 'grid'
+        )",
+        [],
+    )?;
+
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS board_sounds (
+            board_id INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            sound_id INTEGER NOT NULL,
+            PRIMARY KEY (board_id, position),
+            FOREIGN KEY (board_id) REFERENCES boards(id),
+            FOREIGN KEY (sound_id) REFERENCES sounds(id)
+        )",
+        [],
+    )?;
+
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS sound_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            sound_id INTEGER NOT NULL,
+            timestamp DATETIME NOT NULL,
+            source TEXT NOT NULL,
+            FOREIGN KEY (sound_id) REFERENCES sounds(id)
+        )",
+        [],
+    )?;
+
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS sound_tags (
+            sound_id INTEGER NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (sound_id, tag),
+            FOREIGN KEY (sound_id) REFERENCES sounds(id)
+        )",
+        [],
+    )?;
 
-    Ok(db)
+    Ok(())
 }
 
-pub fn increment_play_count(db: &Connection, sound_id: i64) -> Result<()> {
+fn increment_play_count(db: &Connection, sound_id: i64) -> Result<()> {
     db.execute(
         "UPDATE sounds SET play_count = play_count + 1 WHERE id = ?",
-        &[&sound_id],
+        [&sound_id],
     )
     .context("Failed to increment play count")?;
 
     Ok(())
 }
 
-pub fn get_sound_by_name(db: &Connection, name: &str) -> Result<Option<data::Sound>> {
-    let mut stmt = db.prepare("SELECT * FROM sounds WHERE name = ?")?;
-    let mut rows = stmt.query(&[&name])?;
+fn record_play(db: &Connection, sound_id: i64, source: &str) -> Result<()> {
+    increment_play_count(db, sound_id)?;
+
+    db.execute(
+        "INSERT INTO sound_events (sound_id, timestamp, source) VALUES (?, ?, ?)",
+        (&sound_id, &Utc::now(), source),
+    )
+    .context("Failed to insert sound event")?;
+
+    Ok(())
+}
+
+fn get_last_played(db: &Connection) -> Result<HashMap<i64, DateTime<Utc>>> {
+    let mut stmt = db
+        .prepare("SELECT sound_id, MAX(timestamp) FROM sound_events GROUP BY sound_id")
+        .context("Failed to prepare get_last_played")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    let mut last_played = HashMap::new();
+    for row in rows {
+        let (sound_id, timestamp) = row?;
+        last_played.insert(sound_id, timestamp);
+    }
+
+    Ok(last_played)
+}
+
+fn get_durations(db: &Connection) -> Result<HashMap<i64, i64>> {
+    let mut stmt = db
+        .prepare(
+            "SELECT sounds.id, waveforms.duration_ms FROM sounds
+            JOIN waveforms ON waveforms.md5sum = sounds.md5sum",
+        )
+        .context("Failed to prepare get_durations")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    let mut durations = HashMap::new();
+    for row in rows {
+        let (sound_id, duration_ms) = row?;
+        durations.insert(sound_id, duration_ms);
+    }
+
+    Ok(durations)
+}
+
+fn get_tags(db: &Connection) -> Result<HashMap<i64, Vec<String>>> {
+    let mut stmt = db
+        .prepare("SELECT sound_id, tag FROM sound_tags ORDER BY tag")
+        .context("Failed to prepare get_tags")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for row in rows {
+        let (sound_id, tag) = row?;
+        tags.entry(sound_id).or_default().push(tag);
+    }
+
+    Ok(tags)
+}
+
+fn set_sound_tags(db: &Connection, sound_id: i64, tags: &[String]) -> Result<()> {
+    let tx = db.unchecked_transaction()?;
+
+    tx.execute("DELETE FROM sound_tags WHERE sound_id = ?", [&sound_id])
+        .context("Failed to clear sound tags")?;
+    for tag in tags {
+        tx.execute(
+            "INSERT OR IGNORE INTO sound_tags (sound_id, tag) VALUES (?, ?)",
+            (&sound_id, tag),
+        )
+        .context("Failed to insert sound tag")?;
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+fn get_sound_by_path(db: &Connection, path: &str) -> Result<Option<data::Sound>> {
+    let mut stmt = db.prepare("SELECT * FROM sounds WHERE path = ?")?;
+    let mut rows = stmt.query([&path])?;
+
+    if let Some(row) = rows.next()? {
+        Ok(Some(data::Sound {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            path: row.get(2)?,
+            md5sum: row.get(3)?,
+            play_count: row.get(4)?,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn get_sound_by_md5sum(db: &Connection, md5sum: &[u8; 16]) -> Result<Option<data::Sound>> {
+    let mut stmt = db.prepare("SELECT * FROM sounds WHERE md5sum = ?")?;
+    let mut rows = stmt.query([md5sum])?;
 
     if let Some(row) = rows.next()? {
-        // let timestamp: Option<i64> = row.get(5)?;
-        // let a = Utc::now();
-        // let last_played = timestamp.map(|ts| DateTime::from(ts));
         Ok(Some(data::Sound {
             id: row.get(0)?,
             name: row.get(1)?,
             path: row.get(2)?,
             md5sum: row.get(3)?,
             play_count: row.get(4)?,
-            // last_played,
         }))
     } else {
         Ok(None)
     }
 }
 
-pub fn get_sound_by_id(db: &Connection, id: i64) -> Result<Option<data::Sound>> {
+fn get_sound_by_id(db: &Connection, id: i64) -> Result<Option<data::Sound>> {
     let mut stmt = db.prepare("SELECT * FROM sounds WHERE id = ?")?;
-    let mut rows = stmt.query(&[&id])?;
+    let mut rows = stmt.query([&id])?;
 
     if let Some(row) = rows.next()? {
         Ok(Some(data::Sound {
@@ -87,7 +370,7 @@ pub fn get_sound_by_id(db: &Connection, id: i64) -> Result<Option<data::Sound>>
     }
 }
 
-pub fn get_sounds_list(db: &Connection) -> Result<Vec<data::Sound>> {
+fn get_sounds_list(db: &Connection) -> Result<Vec<data::Sound>> {
     let mut stmt = db
         .prepare("SELECT * FROM sounds")
         .context("Failed to prepare get_sounds_list")?;
@@ -111,7 +394,7 @@ pub fn get_sounds_list(db: &Connection) -> Result<Vec<data::Sound>> {
     Ok(sounds)
 }
 
-pub fn insert_sound(db: &Connection, sound: &data::Sound) -> Result<()> {
+fn insert_sound(db: &Connection, sound: &data::Sound) -> Result<()> {
     db.execute(
         "INSERT INTO sounds (name, path, md5sum, play_count) VALUES (?, ?, ?, ?)",
         (&sound.name, &sound.path, &sound.md5sum, &sound.play_count),
@@ -120,3 +403,299 @@ pub fn insert_sound(db: &Connection, sound: &data::Sound) -> Result<()> {
 
     Ok(())
 }
+
+/// The outcome of comparing a sound found on disk to the database.
+pub enum Reconciliation {
+    Inserted,
+    ContentChanged,
+    Unchanged,
+}
+
+fn reconcile_sound(db: &Connection, sound: &data::Sound) -> Result<Reconciliation> {
+    let Some(known) = get_sound_by_path(db, &sound.path)? else {
+        insert_sound(db, sound)?;
+        return Ok(Reconciliation::Inserted);
+    };
+
+    if known.md5sum == sound.md5sum {
+        return Ok(Reconciliation::Unchanged);
+    }
+
+    db.execute(
+        "UPDATE sounds SET md5sum = ? WHERE id = ?",
+        (&sound.md5sum, &known.id),
+    )
+    .context("Failed to update md5sum")?;
+
+    Ok(Reconciliation::ContentChanged)
+}
+
+fn get_waveform(db: &Connection, md5sum: &[u8; 16]) -> Result<Option<data::Waveform>> {
+    let mut stmt = db.prepare("SELECT duration_ms, peaks FROM waveforms WHERE md5sum = ?")?;
+    let mut rows = stmt.query([md5sum])?;
+
+    if let Some(row) = rows.next()? {
+        Ok(Some(data::Waveform {
+            duration_ms: row.get(0)?,
+            peaks: row.get(1)?,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn insert_waveform(db: &Connection, md5sum: &[u8; 16], waveform: &data::Waveform) -> Result<()> {
+    db.execute(
+        "INSERT OR REPLACE INTO waveforms (md5sum, duration_ms, peaks) VALUES (?, ?, ?)",
+        (md5sum, &waveform.duration_ms, &waveform.peaks),
+    )
+    .context("Failed to insert waveform")?;
+
+    Ok(())
+}
+
+fn remove_missing_sounds(db: &Connection, paths: &[String]) -> Result<usize> {
+    let present: HashSet<&str> = paths.iter().map(String::as_str).collect();
+    let tx = db.unchecked_transaction()?;
+
+    let missing = get_sounds_list(&tx)?
+        .into_iter()
+        .filter(|sound| !present.contains(sound.path.as_str()))
+        .collect::<Vec<_>>();
+    for sound in &missing {
+        for table in ["sound_events", "sound_tags", "board_sounds"] {
+            tx.execute(
+                &format!("DELETE FROM {table} WHERE sound_id = ?"),
+                [&sound.id],
+            )
+            .with_context(|| format!("Failed to delete {table} of a missing sound"))?;
+        }
+        tx.execute("DELETE FROM sounds WHERE id = ?", [&sound.id])
+            .context("Failed to delete missing sound")?;
+    }
+
+    tx.commit()?;
+    Ok(missing.len())
+}
+
+fn prune_waveforms(db: &Connection) -> Result<usize> {
+    db.execute(
+        "DELETE FROM waveforms WHERE md5sum NOT IN (SELECT md5sum FROM sounds)",
+        [],
+    )
+    .context("Failed to prune waveforms")
+}
+
+fn get_board_sound_ids(db: &Connection, board_id: i64) -> Result<Vec<i64>> {
+    let mut stmt = db
+        .prepare("SELECT sound_id FROM board_sounds WHERE board_id = ? ORDER BY position")
+        .context("Failed to prepare get_board_sound_ids")?;
+    let rows = stmt.query_map([&board_id], |row| row.get(0))?;
+
+    let mut sound_ids = Vec::new();
+    for sound_id in rows {
+        sound_ids.push(sound_id?);
+    }
+
+    Ok(sound_ids)
+}
+
+fn set_board_sound_ids(db: &Connection, board_id: i64, sound_ids: &[i64]) -> Result<()> {
+    db.execute("DELETE FROM board_sounds WHERE board_id = ?", [&board_id])
+        .context("Failed to clear board sounds")?;
+
+    for (position, sound_id) in sound_ids.iter().enumerate() {
+        db.execute(
+            "INSERT INTO board_sounds (board_id, position, sound_id) VALUES (?, ?, ?)",
+            (&board_id, &(position as i64), sound_id),
+        )
+        .context("Failed to insert board sound")?;
+    }
+
+    Ok(())
+}
+
+fn get_boards(db: &Connection) -> Result<Vec<data::Board>> {
+    let mut stmt = db
+        .prepare("SELECT id, name, title, owner, layout FROM boards ORDER BY name")
+        .context("Failed to prepare get_boards")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                data::Board {
+                    name: row.get(1)?,
+                    title: row.get(2)?,
+                    owner: row.get(3)?,
+                    layout: data::BoardLayout::parse(&row.get::<_, String>(4)?),
+                    sounds: Vec::new(),
+                },
+            ))
+        })
+        .context("Failed to query_map get_boards")?;
+
+    let mut boards = Vec::new();
+    for row in rows {
+        let (id, mut board) = row?;
+        board.sounds = get_board_sound_ids(db, id)?;
+        boards.push(board);
+    }
+
+    Ok(boards)
+}
+
+fn get_board_by_name(db: &Connection, name: &str) -> Result<Option<data::Board>> {
+    let mut stmt =
+        db.prepare("SELECT id, name, title, owner, layout FROM boards WHERE name = ?")?;
+    let mut rows = stmt.query([&name])?;
+
+    if let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        Ok(Some(data::Board {
+            name: row.get(1)?,
+            title: row.get(2)?,
+            owner: row.get(3)?,
+            layout: data::BoardLayout::parse(&row.get::<_, String>(4)?),
+            sounds: get_board_sound_ids(db, id)?,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn insert_board(db: &Connection, board: &data::Board) -> Result<()> {
+    let tx = db.unchecked_transaction()?;
+
+    tx.execute(
+        "INSERT INTO boards (name, title, owner, layout) VALUES (?, ?, ?, ?)",
+        (
+            &board.name,
+            &board.title,
+            &board.owner,
+            board.layout.as_str(),
+        ),
+    )
+    .context("Failed to insert board")?;
+    set_board_sound_ids(&tx, tx.last_insert_rowid(), &board.sounds)?;
+
+    tx.commit()?;
+    Ok(())
+}
+
+fn update_board(db: &Connection, name: &str, board: &data::Board) -> Result<bool> {
+    let tx = db.unchecked_transaction()?;
+
+    let Some(id) = get_board_id(&tx, name)? else {
+        return Ok(false);
+    };
+    tx.execute(
+        "UPDATE boards SET name = ?, title = ?, owner = ?, layout = ? WHERE id = ?",
+        (
+            &board.name,
+            &board.title,
+            &board.owner,
+            board.layout.as_str(),
+            &id,
+        ),
+    )
+    .context("Failed to update board")?;
+    set_board_sound_ids(&tx, id, &board.sounds)?;
+
+    tx.commit()?;
+    Ok(true)
+}
+
+fn delete_board(db: &Connection, name: &str) -> Result<bool> {
+    let tx = db.unchecked_transaction()?;
+
+    let Some(id) = get_board_id(&tx, name)? else {
+        return Ok(false);
+    };
+    tx.execute("DELETE FROM board_sounds WHERE board_id = ?", [&id])
+        .context("Failed to delete board sounds")?;
+    tx.execute("DELETE FROM boards WHERE id = ?", [&id])
+        .context("Failed to delete board")?;
+
+    tx.commit()?;
+    Ok(true)
+}
+
+fn get_board_id(db: &Connection, name: &str) -> Result<Option<i64>> {
+    let mut stmt = db.prepare("SELECT id FROM boards WHERE name = ?")?;
+    let mut rows = stmt.query([&name])?;
+
+    match rows.next()? {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn survives_panicking_queries() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path().join("sounds.db")).unwrap();
+
+        let result: Result<()> = db.run(|_| panic!("query panicked")).await;
+        assert!(result.is_err());
+        assert!(db.list_sounds().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn uses_wal_mode() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path().join("sounds.db")).unwrap();
+
+        let mode: String = db
+            .run(|db| Ok(db.query_row("PRAGMA journal_mode", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(mode, "wal");
+    }
+
+    #[tokio::test]
+    async fn removes_sounds_missing_from_disk() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::open(dir.path().join("sounds.db")).unwrap();
+        for (i, path) in ["kept.mp3", "gone.mp3"].into_iter().enumerate() {
+            let sound = data::Sound {
+                id: 0,
+                name: path.to_string(),
+                path: path.to_string(),
+                md5sum: [i as u8; 16],
+                play_count: 0,
+            };
+            db.reconcile_sound(sound).await.unwrap();
+            let waveform = data::Waveform {
+                duration_ms: 1000,
+                peaks: vec![0; 4],
+            };
+            db.insert_waveform([i as u8; 16], waveform).await.unwrap();
+        }
+        let gone = db.get_sound_by_path("gone.mp3").await.unwrap().unwrap();
+        db.record_play(gone.id, "test").await.unwrap();
+        db.set_sound_tags(gone.id, vec!["tag".into()])
+            .await
+            .unwrap();
+
+        let removed = db
+            .remove_missing_sounds(vec!["kept.mp3".into()])
+            .await
+            .unwrap();
+        assert_eq!(removed, 1);
+        let sounds = db.list_sounds().await.unwrap();
+        assert_eq!(sounds.len(), 1);
+        assert_eq!(sounds[0].path, "kept.mp3");
+        assert!(db.tags().await.unwrap().is_empty());
+        assert!(db.last_played().await.unwrap().is_empty());
+
+        assert_eq!(db.prune_waveforms().await.unwrap(), 1);
+        assert!(db.get_waveform([1; 16]).await.unwrap().is_none());
+        assert!(db.get_waveform([0; 16]).await.unwrap().is_some());
+    }
+}
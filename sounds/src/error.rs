@@ -0,0 +1,217 @@
+use std::{fmt, io};
+
+use axum::{
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use hyper::StatusCode;
+
+use crate::{files::ResolveError, throttle::Throttled};
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// Everything that can go wrong in a request to the sounds API.
+///
+/// Responds with a matching HTTP status and a `{ status, code, message }` JSON body
+/// through [`realraum_backend_common::Error`].
+#[derive(Debug)]
+pub enum ApiError {
+    SoundNotFound,
+    NoMatchingSound,
+    InvalidMd5sum,
+    Resolve(ResolveError),
+    WaveformNotFound,
+    UnsupportedWaveformFormat,
+    BoardNotFound,
+    BoardExists,
+    InvalidBoard(String),
+    /// A search filter is out of range
+    InvalidFilter(String),
+    /// Responds with a `Retry-After` header
+    RateLimited(Throttled),
+    /// Spawning `mplayer` or `killall` failed
+    Playback(io::Error),
+    Database(anyhow::Error),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::SoundNotFound
+            | ApiError::NoMatchingSound
+            | ApiError::WaveformNotFound
+            | ApiError::BoardNotFound => StatusCode::NOT_FOUND,
+            ApiError::InvalidMd5sum
+            | ApiError::UnsupportedWaveformFormat
+            | ApiError::InvalidBoard(_)
+            | ApiError::InvalidFilter(_) => StatusCode::BAD_REQUEST,
+            ApiError::Resolve(e) => match e {
+                ResolveError::InvalidPath => StatusCode::BAD_REQUEST,
+                ResolveError::OutsideLibrary => StatusCode::FORBIDDEN,
+                ResolveError::NotAudio => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                ResolveError::NotFound | ResolveError::NotRegistered => StatusCode::NOT_FOUND,
+            },
+            ApiError::BoardExists => StatusCode::CONFLICT,
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Playback(_) | ApiError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::SoundNotFound => "sound_not_found",
+            ApiError::NoMatchingSound => "no_matching_sound",
+            ApiError::InvalidMd5sum => "invalid_md5sum",
+            ApiError::Resolve(e) => match e {
+                ResolveError::InvalidPath => "invalid_path",
+                ResolveError::OutsideLibrary => "outside_library",
+                ResolveError::NotFound => "file_not_found",
+                ResolveError::NotAudio => "not_audio",
+                ResolveError::NotRegistered => "sound_not_registered",
+            },
+            ApiError::WaveformNotFound => "waveform_not_found",
+            ApiError::UnsupportedWaveformFormat => "unsupported_waveform_format",
+            ApiError::BoardNotFound => "board_not_found",
+            ApiError::BoardExists => "board_exists",
+            ApiError::InvalidBoard(_) => "invalid_board",
+            ApiError::InvalidFilter(_) => "invalid_filter",
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::Playback(_) => "playback_failed",
+            ApiError::Database(_) => "database_error",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::SoundNotFound => f.write_str("No such sound"),
+            ApiError::NoMatchingSound => f.write_str("No sound matches the filters"),
+            ApiError::InvalidMd5sum => f.write_str("Invalid md5sum"),
+            ApiError::Resolve(e) => e.fmt(f),
+            ApiError::WaveformNotFound => f.write_str("No waveform for this sound"),
+            ApiError::UnsupportedWaveformFormat => f.write_str("Unsupported waveform format"),
+            ApiError::BoardNotFound => f.write_str("No such board"),
+            ApiError::BoardExists => f.write_str("A board with this name already exists"),
+            ApiError::InvalidBoard(message) | ApiError::InvalidFilter(message) => {
+                f.write_str(message)
+            }
+            ApiError::RateLimited(throttled) => throttled.fmt(f),
+            ApiError::Playback(_) => f.write_str("Failed to play sound"),
+            // Don't leak SQL into responses, the details end up in the server log
+            ApiError::Database(_) => f.write_str("Database error"),
+        }
+    }
+}
+
+impl From<ResolveError> for ApiError {
+    fn from(e: ResolveError) -> Self {
+        ApiError::Resolve(e)
+    }
+}
+
+impl From<Throttled> for ApiError {
+    fn from(throttled: Throttled) -> Self {
+        ApiError::RateLimited(throttled)
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError::Database(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match &self {
+            ApiError::Playback(e) => tracing::error!("Playback failed: {e}"),
+            ApiError::Database(e) => tracing::error!("Database error: {e:#}"),
+            _ => {}
+        }
+
+        let mut response =
+            realraum_backend_common::Error::new(self.status(), self.code(), self.to_string())
+                .into_response();
+        if let ApiError::RateLimited(throttled) = &self {
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(throttled.retry_after_secs()),
+            );
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::throttle::Limit;
+
+    async fn error_body(response: Response) -> (StatusCode, Value) {
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn responds_with_status_code_and_message() {
+        for (error, status, code) in [
+            (ApiError::SoundNotFound, 404, "sound_not_found"),
+            (ApiError::InvalidMd5sum, 400, "invalid_md5sum"),
+            (
+                ApiError::Resolve(ResolveError::OutsideLibrary),
+                403,
+                "outside_library",
+            ),
+            (ApiError::Resolve(ResolveError::NotAudio), 415, "not_audio"),
+            (ApiError::BoardExists, 409, "board_exists"),
+            (
+                ApiError::RateLimited(Throttled {
+                    limit: Limit::Sound,
+                    retry_after: Duration::from_millis(12_500),
+                }),
+                429,
+                "rate_limited",
+            ),
+            (
+                ApiError::Playback(io::Error::from(io::ErrorKind::NotFound)),
+                500,
+                "playback_failed",
+            ),
+        ] {
+            let message = error.to_string();
+            let (actual_status, body) = error_body(error.into_response()).await;
+
+            assert_eq!(actual_status.as_u16(), status);
+            assert_eq!(body["status"], "error");
+            assert_eq!(body["code"], code);
+            assert_eq!(body["message"], message);
+        }
+    }
+
+    #[tokio::test]
+    async fn hides_database_details() {
+        let error = ApiError::from(anyhow::anyhow!("no such table: sounds"));
+        let (status, body) = error_body(error.into_response()).await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["code"], "database_error");
+        assert_eq!(body["message"], "Database error");
+    }
+
+    #[test]
+    fn tells_when_to_retry() {
+        let response = ApiError::RateLimited(Throttled {
+            limit: Limit::Client,
+            retry_after: Duration::from_millis(12_500),
+        })
+        .into_response();
+
+        assert_eq!(response.headers()[header::RETRY_AFTER], "13");
+    }
+}
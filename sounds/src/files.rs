@@ -1,34 +1,371 @@
-use std::fs;
+use std::{
+    fmt, fs,
+    path::{Component, Path, PathBuf},
+};
 
-use std::path::PathBuf;
+use crate::{
+    data,
+    db::Database,
+    error::{ApiError, ApiResult},
+};
 
-use crate::data;
+/// File extensions of the formats we let `mplayer` play.
+const AUDIO_EXTENSIONS: &[&str] = &["aac", "flac", "m4a", "mp3", "oga", "ogg", "opus", "wav"];
 
-/// Lists all sounds in the [`BASE_PATH`] directory, returning a [`Vec`] of [`Sound`] structs.
-pub(crate) fn index_sounds_from_disk(base_path: &PathBuf) -> Vec<data::Sound> {
+/// Lists all sounds in the `base_path` directory and its subdirectories,
+/// returning a [`Vec`] of [`Sound`] structs.
+///
+/// Subdirectories double as categories, see [`crate::search`].
+/// Only audio files are listed, and like [`resolve_sound`] nothing that leaves the library
+/// through a symlink. This reads every file to hash it, so it blocks.
+pub(crate) fn index_sounds_from_disk(base_path: &Path) -> Vec<data::Sound> {
     let mut sounds = Vec::new();
-    println!("Searching for sounds in {}", base_path.display());
-    for entry in fs::read_dir(&*base_path).unwrap() {
-        if let Ok(entry) = entry {
-            if let Some(filename) = entry.file_name().to_str() {
-                let filepath = entry.path();
-                let fname = filepath.strip_prefix(&*base_path).unwrap_or(&filepath);
-                let fname = fname.to_str().unwrap_or("");
-                let Ok(file_contents_bin) = fs::read(&filepath) else {
-                    // TODO handle subdirectories
-                    println!("Failed to read file {}", filename);
-                    continue;
-                };
-                let md5sum: [u8; 16] = md5::compute(&file_contents_bin).0;
-                sounds.push(data::Sound {
-                    name: filename.to_string(),
-                    path: fname.to_string(),
-                    md5sum,
-                    id: 0,
-                    play_count: 0,
-                });
+    tracing::info!("Searching for sounds in {}", base_path.display());
+    let Ok(library) = base_path.canonicalize() else {
+        tracing::warn!("Failed to open the library {}", base_path.display());
+        return sounds;
+    };
+    index_directory(&library, &library, &mut sounds);
+    sounds
+}
+
+fn index_directory(library: &Path, directory: &Path, sounds: &mut Vec<data::Sound>) {
+    let Ok(entries) = fs::read_dir(directory) else {
+        tracing::warn!("Failed to read directory {}", directory.display());
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let Some(filename) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if filename.starts_with('.') {
+            continue;
+        }
+
+        let filepath = entry.path();
+        // `file_type` doesn't follow symlinks, so symlinked directories can't cause loops
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            index_directory(library, &filepath, sounds);
+            continue;
+        }
+        if !is_audio(&filepath) {
+            continue;
+        }
+        let Ok(canonical) = filepath.canonicalize() else {
+            tracing::warn!("Failed to resolve file {}", filepath.display());
+            continue;
+        };
+        if !canonical.starts_with(library) || !is_audio(&canonical) {
+            tracing::warn!(
+                "Skipping {}, it links out of the library",
+                filepath.display()
+            );
+            continue;
+        }
+
+        let fname = filepath.strip_prefix(library).unwrap_or(&filepath);
+        let fname = fname.to_str().unwrap_or("");
+        let Ok(file_contents_bin) = fs::read(&canonical) else {
+            tracing::warn!("Failed to read file {}", filepath.display());
+            continue;
+        };
+        let md5sum: [u8; 16] = md5::compute(&file_contents_bin).0;
+        sounds.push(data::Sound {
+            name: filename,
+            path: fname.to_string(),
+            md5sum,
+            id: 0,
+            play_count: 0,
+        });
+    }
+}
+
+/// Whether `path` has the extension of one of the [`AUDIO_EXTENSIONS`].
+fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Why a requested sound path was refused by [`resolve_sound`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ResolveError {
+    /// The path is empty, absolute, or contains `..`
+    InvalidPath,
+    /// The path leaves the library, e.g. through a symlink
+    OutsideLibrary,
+    NotFound,
+    NotAudio,
+    NotRegistered,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ResolveError::InvalidPath => "Invalid sound path",
+            ResolveError::OutsideLibrary => "Sound path is outside the library",
+            ResolveError::NotFound => "File not found",
+            ResolveError::NotAudio => "Not an audio file",
+            ResolveError::NotRegistered => "Sound is not registered",
+        })
+    }
+}
+
+/// Resolves a sound path requested by a client to a registered sound and its canonical location.
+///
+/// Every path handed to `mplayer` has to go through here.
+/// Refused paths fail with [`ApiError::Resolve`], database errors with [`ApiError::Database`].
+/// Only regular audio files inside `base_path` which are registered in the database resolve,
+/// so neither `..` nor symlinks pointing out of the library can make us open arbitrary files.
+pub(crate) async fn resolve_sound(
+    db: &Database,
+    base_path: &Path,
+    requested: &str,
+) -> ApiResult<(data::Sound, PathBuf)> {
+    if requested.contains('\0') {
+        return Err(ResolveError::InvalidPath.into());
+    }
+
+    let mut relative = PathBuf::new();
+    for component in Path::new(requested).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(ResolveError::InvalidPath.into())
             }
         }
     }
-    sounds
+    let Some(relative) = relative.to_str().filter(|r| !r.is_empty()) else {
+        return Err(ResolveError::InvalidPath.into());
+    };
+
+    let library = tokio::fs::canonicalize(base_path)
+        .await
+        .map_err(|_| ResolveError::NotFound)?;
+    let canonical = tokio::fs::canonicalize(library.join(relative))
+        .await
+        .map_err(|_| ResolveError::NotFound)?;
+
+    if !canonical.starts_with(&library) {
+        return Err(ResolveError::OutsideLibrary.into());
+    }
+    let is_file = tokio::fs::metadata(&canonical)
+        .await
+        .is_ok_and(|metadata| metadata.is_file());
+    if !is_file {
+        return Err(ResolveError::NotFound.into());
+    }
+
+    if !is_audio(&canonical) {
+        return Err(ResolveError::NotAudio.into());
+    }
+
+    match db.get_sound_by_path(relative).await {
+        Ok(Some(sound)) => Ok((sound, canonical)),
+        Ok(None) => Err(ResolveError::NotRegistered.into()),
+        Err(e) => Err(ApiError::Database(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Builds a library inside a temporary directory, with a secret file next to it.
+    ///
+    /// ```text
+    /// secret.wav
+    /// library/
+    ///     ok.wav, notes.txt, unregistered.wav, loud.WAV
+    ///     nested/deep.wav
+    ///     escape.wav -> ../secret.wav
+    ///     escape_dir -> ..
+    ///     inner_link.wav -> ok.wav
+    ///     disguised.wav -> notes.txt
+    /// ```
+    async fn setup() -> (TempDir, PathBuf, Database) {
+        let dir = TempDir::new().unwrap();
+        let library = dir.path().join("library");
+        fs::create_dir_all(library.join("nested")).unwrap();
+
+        for file in [
+            "secret.wav",
+            "library/ok.wav",
+            "library/notes.txt",
+            "library/unregistered.wav",
+            "library/loud.WAV",
+            "library/nested/deep.wav",
+        ] {
+            fs::write(dir.path().join(file), file).unwrap();
+        }
+        symlink("../secret.wav", library.join("escape.wav")).unwrap();
+        symlink("..", library.join("escape_dir")).unwrap();
+        symlink("ok.wav", library.join("inner_link.wav")).unwrap();
+        symlink("notes.txt", library.join("disguised.wav")).unwrap();
+
+        let db = Database::open(dir.path().join("sounds.db")).unwrap();
+        // Register the hostile files too, the resolver must not trust the database alone
+        for (i, path) in [
+            "ok.wav",
+            "notes.txt",
+            "loud.WAV",
+            "nested/deep.wav",
+            "escape.wav",
+            "escape_dir/secret.wav",
+            "inner_link.wav",
+            "disguised.wav",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let sound = data::Sound {
+                name: path.to_string(),
+                path: path.to_string(),
+                md5sum: [i as u8; 16],
+                id: 0,
+                play_count: 0,
+            };
+            db.reconcile_sound(sound).await.unwrap();
+        }
+
+        (dir, library, db)
+    }
+
+    /// The refusal of a resolved path, database errors fail the test.
+    fn refusal(result: ApiResult<(data::Sound, PathBuf)>) -> Result<PathBuf, ResolveError> {
+        match result {
+            Ok((_, path)) => Ok(path),
+            Err(ApiError::Resolve(e)) => Err(e),
+            Err(e) => panic!("Unexpected error {e:?}"),
+        }
+    }
+
+    async fn resolve(requested: &str) -> Result<PathBuf, ResolveError> {
+        let (_dir, library, db) = setup().await;
+        refusal(resolve_sound(&db, &library, requested).await)
+    }
+
+    #[tokio::test]
+    async fn resolves_registered_sounds() {
+        let (_dir, library, db) = setup().await;
+        let library = library.canonicalize().unwrap();
+
+        for (requested, expected) in [
+            ("ok.wav", "ok.wav"),
+            ("./ok.wav", "ok.wav"),
+            ("nested/deep.wav", "nested/deep.wav"),
+            ("nested//deep.wav", "nested/deep.wav"),
+            ("loud.WAV", "loud.WAV"),
+            ("inner_link.wav", "ok.wav"),
+        ] {
+            let (_, path) = resolve_sound(&db, &library, requested).await.unwrap();
+            assert_eq!(path, library.join(expected), "{requested}");
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_traversal() {
+        for requested in [
+            "../secret.wav",
+            "nested/../../secret.wav",
+            "nested/../ok.wav",
+            "..",
+            "/etc/passwd",
+            "//etc/passwd",
+            "",
+            ".",
+            "ok.wav\0.txt",
+        ] {
+            assert_eq!(
+                resolve(requested).await,
+                Err(ResolveError::InvalidPath),
+                "{requested:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_escaping_symlinks() {
+        for requested in ["escape.wav", "escape_dir/secret.wav"] {
+            assert_eq!(resolve(requested).await, Err(ResolveError::OutsideLibrary));
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_non_audio_files() {
+        for requested in ["notes.txt", "disguised.wav"] {
+            assert_eq!(resolve(requested).await, Err(ResolveError::NotAudio));
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_missing_files_and_directories() {
+        for requested in [
+            "missing.wav",
+            "nested",
+            "%2e%2e/secret.wav",
+            "nested/ok.wav",
+        ] {
+            assert_eq!(resolve(requested).await, Err(ResolveError::NotFound));
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_unregistered_sounds() {
+        assert_eq!(
+            resolve("unregistered.wav").await,
+            Err(ResolveError::NotRegistered)
+        );
+    }
+
+    #[tokio::test]
+    async fn indexes_audio_files_inside_the_library() {
+        let (_dir, library, _db) = setup().await;
+        let mut paths: Vec<_> = index_sounds_from_disk(&library)
+            .into_iter()
+            .map(|sound| sound.path)
+            .collect();
+        paths.sort();
+        // Neither notes.txt, nor the links to it or out of the library
+        assert_eq!(
+            paths,
+            [
+                "inner_link.wav",
+                "loud.WAV",
+                "nested/deep.wav",
+                "ok.wav",
+                "unregistered.wav"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_missing_library() {
+        let (_dir, library, db) = setup().await;
+        fs::remove_dir_all(&library).unwrap();
+        assert_eq!(
+            refusal(resolve_sound(&db, &library, "ok.wav").await),
+            Err(ResolveError::NotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_database_errors() {
+        let (dir, library, db) = setup().await;
+        rusqlite::Connection::open(dir.path().join("sounds.db"))
+            .unwrap()
+            .execute("DROP TABLE sounds", [])
+            .unwrap();
+
+        assert!(matches!(
+            resolve_sound(&db, &library, "ok.wav").await,
+            Err(ApiError::Database(_))
+        ));
+    }
 }
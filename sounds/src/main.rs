@@ -1,93 +1,287 @@
 mod api;
+mod api_v2;
 mod compat;
+mod config;
+mod csrf;
 mod data;
 mod db;
+mod error;
 mod files;
 mod playback;
+mod search;
+mod throttle;
+mod waveform;
 
-use std::{
-    env,
-    net::SocketAddr,
-    path::{Path, PathBuf},
-    sync::{Arc, Mutex},
-};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use axum::{
-    routing::{any, get},
+    extract::FromRef,
+    middleware,
+    routing::{delete, get, post, put},
     Router,
 };
-use lazy_static::lazy_static;
-use tower_http::services::{ServeDir, ServeFile};
-
-const BASE_PATH_FALLBACK: &str = "/home/realraum/welcomesounds";
-
-lazy_static! {
-    static ref AUDIO_LOCK: Mutex<()> = Mutex::new(());
-    pub static ref BASE_PATH: PathBuf = env::var("R3_SOUNDS_BASE_PATH")
-        .map_err(|_| ())
-        .and_then(|s| Path::new(&s).canonicalize().map_err(|_| ()))
-        .unwrap_or_else(|_| Path::new(BASE_PATH_FALLBACK).to_path_buf());
-    // static ref PIPE_OGG
+use clap::Parser;
+use config::Config;
+use db::Database;
+use realraum_backend_common::{
+    auth::{self, Auth, Role, TokensCommand},
+    config::{self as common_config, ConfigArgs},
+    logging, metrics,
+    routes::{self, Check},
+    server,
+};
+use serde::Serialize;
+use throttle::Throttle;
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(flatten)]
+    overrides: CliOverrides,
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum CliCommand {
+    /// Manage API tokens
+    #[command(subcommand)]
+    Tokens(TokensCommand),
+}
+
+/// Flags overriding single values of the [`Config`].
+#[derive(Debug, clap::Args, Serialize)]
+struct CliOverrides {
+    /// Address to listen on
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    addr: Option<SocketAddr>,
+    /// Directory containing the sound library
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base_path: Option<PathBuf>,
+    /// SQLite database file
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    database: Option<PathBuf>,
+    /// Directory of the frontend's static assets
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    static_dir: Option<PathBuf>,
+}
+
+/// State shared by all handlers, which extract the parts they need via [`FromRef`].
+#[derive(Clone)]
+pub struct AppState {
+    db: Database,
+    config: Arc<Config>,
+    throttle: Arc<Throttle>,
+}
+
+impl FromRef<AppState> for Database {
+    fn from_ref(state: &AppState) -> Self {
+        state.db.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Throttle> {
+    fn from_ref(state: &AppState) -> Self {
+        state.throttle.clone()
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let db_con = db::make_some_db()?;
+    let cli = Cli::parse();
+    let mut config: Config = common_config::load(&cli.config, &cli.overrides)?;
+    if cli.config.print_config {
+        return common_config::print(&config);
+    }
+    if let Some(CliCommand::Tokens(command)) = cli.command {
+        return command.run(&config.auth).await;
+    }
+    let log_handle = logging::init(&config.log)?;
+    // Only checked when serving, so `--print-config` and `tokens` work without the library
+    if !config.base_path.is_dir() {
+        bail!(
+            "base_path {} is not a directory",
+            config.base_path.display()
+        );
+    }
+    config.base_path = config.base_path.canonicalize()?;
+    let config = Arc::new(config);
+    let metrics = metrics::install()?;
 
-    for sound in files::index_sounds_from_disk(&BASE_PATH) {
-        let result = db::insert_sound(&db_con, &sound);
-        if result.is_ok() {
-            println!("Inserted sound {}", sound.name);
+    let db = Database::open(&config.database)?;
+
+    let base_path = config.base_path.clone();
+    let sounds =
+        tokio::task::spawn_blocking(move || files::index_sounds_from_disk(&base_path)).await?;
+    // Sounds whose files are gone take their history and waveforms with them
+    let paths = sounds.iter().map(|sound| sound.path.clone()).collect();
+    let removed = db.remove_missing_sounds(paths).await?;
+    if removed > 0 {
+        tracing::info!("Removed {removed} sounds missing from disk");
+    }
+    let mut registered = Vec::with_capacity(sounds.len());
+    for sound in sounds {
+        match db.reconcile_sound(sound.clone()).await {
+            Ok(db::Reconciliation::Inserted) => {
+                tracing::info!(sound = %sound.path, "Inserted sound")
+            }
+            Ok(db::Reconciliation::ContentChanged) => {
+                tracing::info!(sound = %sound.path, "Updated sound")
+            }
+            Ok(db::Reconciliation::Unchanged) => {}
+            Err(e) => {
+                tracing::warn!(sound = %sound.path, "Failed to register sound: {e:#}");
+                continue;
+            }
         }
+        registered.push(sound);
     }
+    db.prune_waveforms().await?;
+    tokio::spawn(waveform::cache_all(
+        db.clone(),
+        config.base_path.clone(),
+        registered,
+    ));
 
-    let db_con = Arc::new(Mutex::new(db_con));
+    let mut compat_api = Router::new()
+        .route("/play/*name", post(compat::handle_play_sound_form))
+        .route_layer(auth::require(Role::Guest));
+    if config.policies.allow_killall {
+        compat_api = compat_api.merge(
+            Router::new()
+                .route(
+                    "/killall_mplayer",
+                    post(compat::handle_killall_mplayer_form),
+                )
+                .route_layer(auth::require(Role::Member)),
+        );
+    }
+    if config.policies.legacy_get_routes {
+        let mut legacy = Router::new()
+            .route("/play/*name", get(compat::handle_play_sound))
+            .route_layer(auth::require(Role::Guest));
+        if config.policies.allow_killall {
+            legacy = legacy.merge(
+                Router::new()
+                    .route("/killall_mplayer", get(compat::handle_killall_mplayer))
+                    .route_layer(auth::require(Role::Member)),
+            );
+        }
+        compat_api = compat_api.merge(legacy.route_layer(middleware::from_fn(routes::deprecated)));
+    }
 
-    let app = Router::new()
+    let mut app = Router::new()
         .nest(
             "/api",
-            Router::new()
-                .route("/", get(api::greeting))
-                .route("/*any", any(api::fallback))
-                .nest(
-                    "/v1",
-                    Router::new()
-                        .route("/killall_mplayer", get(api::handle_killall_mplayer))
-                        .route("/sounds", get(api::sounds_handler))
-                        .route("/play/*name", get(api::handle_play_sound)),
-                ),
+            routes::api(
+                "Welcome to the Realraum Sounds API",
+                env!("CARGO_PKG_VERSION"),
+            )
+            .nest("/v1", api_v1(&config))
+            .nest("/v2", api_v2::routes(&config).into()),
         )
-        .nest(
+        .merge(routes::health({
+            let (db, config) = (db.clone(), config.clone());
+            move || readiness(db.clone(), config.clone())
+        }))
+        .merge(metrics::routes(metrics))
+        .merge(logging::routes(log_handle).route_layer(auth::require(Role::Admin)));
+
+    if config.policies.compat_page {
+        app = app.nest(
             "/compat-sounds",
             Router::new()
                 .route("/", get(compat::html_page_handler))
-                .nest(
-                    "/api-c1",
-                    Router::new()
-                        .route("/killall_mplayer", get(compat::handle_killall_mplayer))
-                        .route("/play/*name", get(compat::handle_play_sound)),
-                ),
-        )
-        .nest_service(
-            "/",
-            ServeDir::new("dist").not_found_service(ServeFile::new("dist/index.html")),
+                .route("/board/:name", get(compat::board_page_handler))
+                .route_layer(auth::require(Role::Guest))
+                .nest("/api-c1", compat_api),
+        );
+    }
+
+    let app = app
+        .route_layer(middleware::from_fn(metrics::track_requests))
+        .nest_service("/", routes::static_files(&config.static_dir))
+        .with_state(AppState {
+            db,
+            config: config.clone(),
+            throttle: Arc::new(Throttle::new(config.policies.rate_limit.clone())),
+        })
+        .layer(middleware::from_fn_with_state(
+            Auth::new(config.auth.clone()),
+            auth::authenticate,
+        ));
+    let app = logging::trace_requests(app);
+
+    server::serve(app, config.addr).await
+}
+
+/// Routes of `/api/v1`, see [`api_v2::routes`] for v2.
+///
+/// The v1 routes playing or stopping sounds on `GET` are deprecated
+/// and only served if `policies.legacy_get_routes` is set.
+fn api_v1(config: &Config) -> Router<AppState> {
+    // Everyone may browse and play sounds
+    let mut guest = Router::new()
+        .route("/sounds", get(api::sounds_handler))
+        .route("/sounds/:id/waveform", get(api::handle_waveform))
+        .route("/sounds/:id/tags", get(api::handle_get_tags))
+        .route("/sounds/:id/play", post(api::handle_play_sound_by_id))
+        .route(
+            "/sounds/by-hash/:md5sum/play",
+            post(api::handle_play_sound_by_hash),
         )
-        .with_state(db_con);
+        .route("/boards", get(api::boards_handler))
+        .route("/boards/:name", get(api::handle_get_board));
 
-    // run it with hyper on localhost:3000
-    // axum::Server::bind(&"192.168.127.246:80".parse().unwrap())
-    let addr: SocketAddr = env::var("R3_SOUNDS_ADDR")
-        .map_err(|_| ())
-        .and_then(|s| s.parse().map_err(|_| ()))
-        .unwrap_or_else(|_| "0.0.0.0:4242".parse().unwrap());
+    let mut member = Router::new()
+        .route("/sounds/:id/tags", put(api::handle_set_tags))
+        .route("/boards", post(api::handle_create_board))
+        .route("/boards/:name", put(api::handle_update_board));
 
-    println!("Starting server on http://{addr}");
+    if config.policies.legacy_get_routes {
+        guest = guest.merge(
+            Router::new()
+                .route("/play_random", get(api::handle_play_random))
+                .route("/play/*name", get(api::handle_play_sound))
+                .route_layer(middleware::from_fn(routes::deprecated)),
+        );
+        if config.policies.allow_killall {
+            member = member.merge(
+                Router::new()
+                    .route("/killall_mplayer", get(api::handle_killall_mplayer))
+                    .route_layer(middleware::from_fn(routes::deprecated)),
+            );
+        }
+    }
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let admin = Router::new()
+        .route("/boards/:name", delete(api::handle_delete_board))
+        .route_layer(auth::require(Role::Admin));
+
+    guest
+        .route_layer(auth::require(Role::Guest))
+        .merge(member.route_layer(auth::require(Role::Member)))
+        .merge(admin)
+}
 
-    Ok(())
+/// The sounds backend is ready if it can query the database and read the sound library.
+async fn readiness(db: Database, config: Arc<Config>) -> Vec<Check> {
+    let library = tokio::fs::read_dir(&config.base_path).await.map(|_| ());
+    vec![
+        Check::new("database", db.ping().await),
+        Check::new("sound_directory", library),
+    ]
 }
@@ -1,26 +1,25 @@
-use std::process::Command;
+use std::{io, path::Path};
 
-use crate::BASE_PATH;
+use tokio::process::Command;
 
-/// Plays a sound from a given path using `mplayer` (not anymore, now using `rodio`).
+use crate::config::PlayerConfig;
+
+/// Plays a sound from a given path by spawning the configured player, `mplayer` by default.
+///
+/// Returns as soon as the process is started, without waiting for the sound to finish.
+/// Overlapping sounds are only limited by the [`crate::throttle::Throttle`] in front of this.
+/// Must be called within the tokio runtime, which reaps the child once it exits.
 ///
-/// Spawns a new child process and returns immediately.  
-/// Multiple sounds are prevented by using a global lock.
-pub fn play_sound_from_path(filepath: &str) -> bool {
-    Command::new("mplayer")
-        .args(&[
-            "-really-quiet",
-            "-nolirc",
-            "-ao",
-            "alsa",
-            &format!("{}/{}", BASE_PATH.display(), filepath),
-        ])
-        .spawn()
-        .expect("Failed to execute mplayer");
+/// The path must have been resolved with [`crate::files::resolve_sound`].
+pub fn play_sound_from_path(player: &PlayerConfig, filepath: &Path) -> io::Result<()> {
+    Command::new(&player.command)
+        .args(&player.args)
+        .arg(filepath)
+        .spawn()?;
 
     // TODO migrate back to rodio once cancelling sounds is implemented,
     //  and once the 5 second sleep hack has been removed.
-    return true;
+    Ok(())
 
     // // Get a output stream handle to the default physical sound device
     // let (_stream, stream_handle) = OutputStream::try_default().unwrap();
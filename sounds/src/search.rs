@@ -0,0 +1,101 @@
+use std::path::Path;
+
+use chrono::{Duration, Utc};
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::{
+    data::Sound,
+    db::Database,
+    error::{ApiError, ApiResult},
+};
+
+/// Longest `not_played_hours`, over a century, which keeps the cutoff within chrono's range.
+const MAX_NOT_PLAYED_HOURS: i64 = 1_000_000;
+
+/// Filters for searching the sound library, usually taken from the query string.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SoundFilter {
+    /// Subdirectory of the library the sound is in, including nested subdirectories
+    category: Option<String>,
+    /// Only sounds with this tag
+    tag: Option<String>,
+    min_duration_ms: Option<i64>,
+    max_duration_ms: Option<i64>,
+    /// Only sounds that weren't played within the last N hours
+    not_played_hours: Option<i64>,
+}
+
+/// The category of a sound is the subdirectory it is in, if any.
+pub(crate) fn category_of(sound: &Sound) -> Option<&str> {
+    Path::new(&sound.path)
+        .parent()
+        .and_then(|parent| parent.to_str())
+        .filter(|parent| !parent.is_empty())
+}
+
+/// Lists all sounds matching `filter`, in the order of [`Database::list_sounds`].
+pub(crate) async fn search_sounds(db: &Database, filter: &SoundFilter) -> ApiResult<Vec<Sound>> {
+    let not_played_since = match filter.not_played_hours {
+        Some(hours @ 0..=MAX_NOT_PLAYED_HOURS) => Some(Utc::now() - Duration::hours(hours)),
+        Some(_) => {
+            return Err(ApiError::InvalidFilter(format!(
+                "not_played_hours must be between 0 and {MAX_NOT_PLAYED_HOURS}"
+            )))
+        }
+        None => None,
+    };
+
+    let mut sounds = db.list_sounds().await?;
+
+    if let Some(category) = &filter.category {
+        let category = category.trim_matches('/');
+        sounds.retain(|sound| {
+            category_of(sound).is_some_and(|c| {
+                c == category || c.strip_prefix(category).is_some_and(|c| c.starts_with('/'))
+            })
+        });
+    }
+
+    if let Some(tag) = &filter.tag {
+        let tags = db.tags().await?;
+        sounds.retain(|sound| tags.get(&sound.id).is_some_and(|t| t.contains(tag)));
+    }
+
+    if filter.min_duration_ms.is_some() || filter.max_duration_ms.is_some() {
+        let durations = db.durations().await?;
+        let min = filter.min_duration_ms.unwrap_or(i64::MIN);
+        let max = filter.max_duration_ms.unwrap_or(i64::MAX);
+        // Sounds without a waveform have an unknown duration and never match
+        sounds.retain(|sound| {
+            durations
+                .get(&sound.id)
+                .is_some_and(|duration| (min..=max).contains(duration))
+        });
+    }
+
+    if let Some(cutoff) = not_played_since {
+        let last_played = db.last_played().await?;
+        sounds.retain(|sound| last_played.get(&sound.id).is_none_or(|t| *t < cutoff));
+    }
+
+    Ok(sounds)
+}
+
+/// Picks a random sound, optionally weighted by inverse play count,
+/// so rarely played sounds get a chance too.
+pub(crate) fn pick_random(sounds: &[Sound], weighted: bool) -> Option<&Sound> {
+    let mut rng = rand::thread_rng();
+
+    if !weighted {
+        return sounds.choose(&mut rng);
+    }
+
+    let weights = sounds
+        .iter()
+        .map(|sound| 1.0 / (sound.play_count.max(0) as f64 + 1.0));
+    let index = WeightedIndex::new(weights).ok()?;
+    sounds.get(index.sample(&mut rng))
+}
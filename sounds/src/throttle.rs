@@ -0,0 +1,386 @@
+//! Rate limiting of playback, so a single client can't keep the speakers busy.
+//!
+//! Every play has to pass three limits, see [`RateLimit`]:
+//! the client's plays within the last minute, the cooldown of the sound and the global cooldown.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    fmt,
+    net::SocketAddr,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+use realraum_backend_common::auth::Identity;
+
+use crate::config::RateLimit;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Who is playing a sound: the token or SSO user name, or the IP of anonymous clients.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Client(String);
+
+impl Client {
+    pub fn new(key: impl Into<String>) -> Self {
+        Self(key.into())
+    }
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Client {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(name) = parts
+            .extensions
+            .get::<Identity>()
+            .and_then(|identity| identity.name.as_ref())
+        {
+            return Ok(Client(format!("user:{name}")));
+        }
+
+        // Behind a reverse proxy all anonymous clients share the proxy's address
+        match parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => Ok(Client(format!("ip:{}", addr.ip()))),
+            None => Ok(Client("unknown".to_string())),
+        }
+    }
+}
+
+/// Which limit refused a play.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Client,
+    Sound,
+    Global,
+}
+
+impl Limit {
+    fn name(self) -> &'static str {
+        match self {
+            Limit::Client => "client",
+            Limit::Sound => "sound",
+            Limit::Global => "global",
+        }
+    }
+}
+
+/// A refused play, which may be retried after `retry_after`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttled {
+    pub limit: Limit,
+    pub retry_after: Duration,
+}
+
+impl Throttled {
+    /// Whole seconds until the play may be retried, as sent in the `Retry-After` header.
+    pub fn retry_after_secs(&self) -> u64 {
+        ceil_secs(self.retry_after)
+    }
+}
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.limit {
+            Limit::Client => "You played too many sounds",
+            Limit::Sound => "This sound was played recently",
+            Limit::Global => "Another sound was played just now",
+        };
+        write!(
+            f,
+            "Rate limited: {reason}, retry after {} s",
+            self.retry_after_secs()
+        )
+    }
+}
+
+/// Rounds up, so clients waiting for the returned seconds won't be refused again.
+pub fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Times of the plays within the last [`WINDOW`] per client
+    clients: HashMap<Client, VecDeque<Instant>>,
+    /// Time of the last play per sound id
+    sounds: HashMap<i64, Instant>,
+    last_play: Option<Instant>,
+}
+
+/// Keeps track of recent plays to enforce a [`RateLimit`].
+#[derive(Debug)]
+pub struct Throttle {
+    limit: RateLimit,
+    state: Mutex<State>,
+}
+
+impl Throttle {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Checks whether `client` may play `sound_id` now.
+    ///
+    /// The play only counts once the returned [`Permit`] is committed, after the player started.
+    /// Until then the permit holds the throttle, so don't keep it across an `.await`.
+    pub fn try_play(&self, client: &Client, sound_id: i64) -> Result<Permit<'_>, Throttled> {
+        self.try_play_at(client, sound_id, Instant::now())
+            .inspect_err(|throttled| {
+                metrics::counter!("sounds_rate_limited_total", "limit" => throttled.limit.name())
+                    .increment(1);
+            })
+    }
+
+    fn try_play_at(
+        &self,
+        client: &Client,
+        sound_id: i64,
+        now: Instant,
+    ) -> Result<Permit<'_>, Throttled> {
+        // A panic while a permit was held can't leave the limits in a state worth refusing plays
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Forget plays that can't limit anything anymore, so the map doesn't grow forever
+        state.clients.retain(|_, plays| {
+            while plays
+                .front()
+                .is_some_and(|&t| now.duration_since(t) >= WINDOW)
+            {
+                plays.pop_front();
+            }
+            !plays.is_empty()
+        });
+        let sound_cooldown = Duration::from_secs(self.limit.sound_cooldown_secs);
+        state
+            .sounds
+            .retain(|_, &mut t| now.duration_since(t) < sound_cooldown);
+
+        let max_plays = self.limit.plays_per_minute as usize;
+        let global = state
+            .last_play
+            .and_then(|t| remaining(t, self.global_cooldown(), now))
+            .map(|retry_after| (Limit::Global, retry_after));
+        let sound = state
+            .sounds
+            .get(&sound_id)
+            .and_then(|&t| remaining(t, sound_cooldown, now))
+            .map(|retry_after| (Limit::Sound, retry_after));
+        let client_plays = state
+            .clients
+            .get(client)
+            .filter(|plays| max_plays > 0 && plays.len() >= max_plays)
+            .and_then(|plays| remaining(plays[0], WINDOW, now))
+            .map(|retry_after| (Limit::Client, retry_after));
+
+        // Tell the client about the longest wait, retrying earlier would be refused again
+        if let Some((limit, retry_after)) = [global, sound, client_plays]
+            .into_iter()
+            .flatten()
+            .max_by_key(|&(_, retry_after)| retry_after)
+        {
+            return Err(Throttled { limit, retry_after });
+        }
+
+        Ok(Permit {
+            throttle: self,
+            state,
+            client: client.clone(),
+            sound_id,
+            now,
+        })
+    }
+
+    /// Time until `sound_id` may be played again by anyone, if it's cooling down.
+    pub fn cooldown(&self, sound_id: i64) -> Option<Duration> {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let sound = state
+            .sounds
+            .get(&sound_id)
+            .and_then(|&t| remaining(t, Duration::from_secs(self.limit.sound_cooldown_secs), now));
+        let global = state
+            .last_play
+            .and_then(|t| remaining(t, self.global_cooldown(), now));
+        sound.max(global)
+    }
+
+    fn global_cooldown(&self) -> Duration {
+        Duration::from_secs(self.limit.global_cooldown_secs)
+    }
+}
+
+/// A play allowed by [`Throttle::try_play`], which only counts once committed.
+///
+/// Dropping it without committing, e.g. because the player failed to start, leaves no trace.
+#[derive(Debug)]
+#[must_use = "the play only counts once committed"]
+pub struct Permit<'a> {
+    throttle: &'a Throttle,
+    state: MutexGuard<'a, State>,
+    client: Client,
+    sound_id: i64,
+    now: Instant,
+}
+
+impl Permit<'_> {
+    /// Records the play, starting its cooldowns.
+    pub fn commit(mut self) {
+        let limit = &self.throttle.limit;
+        if limit.plays_per_minute > 0 {
+            self.state
+                .clients
+                .entry(self.client)
+                .or_default()
+                .push_back(self.now);
+        }
+        if limit.sound_cooldown_secs > 0 {
+            self.state.sounds.insert(self.sound_id, self.now);
+        }
+        self.state.last_play = Some(self.now);
+    }
+}
+
+/// Time left of a `cooldown` started at `start`, `None` once it's over.
+fn remaining(start: Instant, cooldown: Duration, now: Instant) -> Option<Duration> {
+    (start + cooldown)
+        .checked_duration_since(now)
+        .filter(|remaining| !remaining.is_zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(
+        plays_per_minute: u32,
+        sound_cooldown_secs: u64,
+        global_cooldown_secs: u64,
+    ) -> Throttle {
+        Throttle::new(RateLimit {
+            plays_per_minute,
+            sound_cooldown_secs,
+            global_cooldown_secs,
+        })
+    }
+
+    #[test]
+    fn enforces_cooldowns() {
+        let throttle = throttle(0, 30, 2);
+        let client = Client::new("ip:127.0.0.1");
+        let start = Instant::now();
+
+        throttle.try_play_at(&client, 1, start).unwrap().commit();
+        let throttled = throttle
+            .try_play_at(&client, 2, start + Duration::from_millis(500))
+            .unwrap_err();
+        assert_eq!(throttled.limit, Limit::Global);
+        assert_eq!(throttled.retry_after_secs(), 2);
+        let throttled = throttle
+            .try_play_at(&client, 1, start + Duration::from_millis(500))
+            .unwrap_err();
+        assert_eq!(throttled.limit, Limit::Sound);
+
+        let later = start + Duration::from_secs(10);
+        throttle.try_play_at(&client, 2, later).unwrap().commit();
+        let throttled = throttle
+            .try_play_at(&client, 1, later + Duration::from_secs(5))
+            .unwrap_err();
+        assert_eq!(throttled.limit, Limit::Sound);
+        assert_eq!(throttled.retry_after, Duration::from_secs(15));
+
+        throttle
+            .try_play_at(&client, 1, start + Duration::from_secs(30))
+            .unwrap()
+            .commit();
+    }
+
+    #[test]
+    fn survives_panics_while_holding_a_permit() {
+        let throttle = throttle(0, 0, 0);
+        let client = Client::new("ip:127.0.0.1");
+        std::thread::scope(|scope| {
+            let panicked = scope.spawn(|| {
+                let _permit = throttle.try_play(&client, 1).unwrap();
+                panic!("playback panicked");
+            });
+            assert!(panicked.join().is_err());
+        });
+
+        throttle.try_play(&client, 1).unwrap().commit();
+        assert_eq!(throttle.cooldown(1), None);
+    }
+
+    #[test]
+    fn limits_plays_per_client() {
+        let throttle = throttle(2, 0, 0);
+        let (alice, bob) = (Client::new("user:alice"), Client::new("user:bob"));
+        let start = Instant::now();
+
+        throttle.try_play_at(&alice, 1, start).unwrap().commit();
+        throttle
+            .try_play_at(&alice, 1, start + Duration::from_secs(20))
+            .unwrap()
+            .commit();
+        let throttled = throttle
+            .try_play_at(&alice, 1, start + Duration::from_secs(40))
+            .unwrap_err();
+        assert_eq!(throttled.limit, Limit::Client);
+        assert_eq!(throttled.retry_after, Duration::from_secs(20));
+
+        throttle
+            .try_play_at(&bob, 1, start + Duration::from_secs(40))
+            .unwrap()
+            .commit();
+        throttle
+            .try_play_at(&alice, 1, start + Duration::from_secs(60))
+            .unwrap()
+            .commit();
+    }
+
+    #[test]
+    fn refused_plays_dont_count() {
+        let throttle = throttle(1, 30, 0);
+        let client = Client::new("ip:127.0.0.1");
+        let start = Instant::now();
+
+        throttle.try_play_at(&client, 1, start).unwrap().commit();
+        throttle
+            .try_play_at(&client, 1, start + Duration::from_secs(1))
+            .unwrap_err();
+        throttle
+            .try_play_at(&client, 2, start + Duration::from_secs(60))
+            .unwrap()
+            .commit();
+    }
+
+    #[test]
+    fn uncommitted_plays_dont_count() {
+        let throttle = throttle(1, 30, 2);
+        let client = Client::new("ip:127.0.0.1");
+        let start = Instant::now();
+
+        drop(throttle.try_play_at(&client, 1, start).unwrap());
+        throttle
+            .try_play_at(&client, 1, start + Duration::from_millis(500))
+            .unwrap()
+            .commit();
+        assert!(throttle.cooldown(1).is_some());
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    fs::File,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use symphonia::core::{
+    audio::SampleBuffer, codecs::DecoderOptions, errors::Error, formats::FormatOptions,
+    io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+
+use crate::data::{Sound, Waveform};
+use crate::db::Database;
+
+/// Number of buckets a waveform is downsampled to.
+pub(crate) const PEAK_COUNT: usize = 100;
+
+/// Number of frames folded into one intermediate peak while decoding if the length
+/// of the track is unknown, so long files don't have to be kept in memory sample by sample.
+const CHUNK_FRAMES: usize = 256;
+
+const SVG_BAR_WIDTH: usize = 2;
+const SVG_HEIGHT: usize = 32;
+
+/// Caches the waveforms of `sounds` one after another.
+///
+/// Spawned after indexing, so startup doesn't wait for the whole library to be decoded,
+/// until then the waveform routes answer `404 waveform_not_found`.
+pub(crate) async fn cache_all(db: Database, base_path: PathBuf, sounds: Vec<Sound>) {
+    for sound in &sounds {
+        if let Err(e) = ensure_cached(&db, &base_path, sound).await {
+            tracing::warn!("{e:#}");
+        }
+    }
+    tracing::info!(sounds = sounds.len(), "Cached waveforms");
+}
+
+/// Makes sure the waveform of a sound is cached in the database.
+///
+/// Waveforms are keyed by the `md5sum` of the file contents, so a sound whose contents
+/// changed on disk is regenerated automatically, while renamed files reuse their cache entry.
+pub(crate) async fn ensure_cached(db: &Database, base_path: &Path, sound: &Sound) -> Result<()> {
+    if db.get_waveform(sound.md5sum).await?.is_some() {
+        return Ok(());
+    }
+
+    let path = base_path.join(&sound.path);
+    let waveform = tokio::task::spawn_blocking(move || compute_waveform(&path))
+        .await?
+        .with_context(|| format!("Failed to compute waveform of {}", sound.path))?;
+    db.insert_waveform(sound.md5sum, waveform).await?;
+
+    Ok(())
+}
+
+/// Decodes an audio file and downsamples it to [`PEAK_COUNT`] peaks.
+pub(crate) fn compute_waveform(path: &Path) -> Result<Waveform> {
+    let file = File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .context("Unsupported audio format")?
+        .format;
+
+    let track = format.default_track().context("No audio track found")?;
+    let track_id = track.id;
+    let mut sample_rate = track.codec_params.sample_rate;
+    let chunk_frames = match track.codec_params.n_frames {
+        Some(n_frames) => (n_frames as usize / PEAK_COUNT).max(1),
+        None => CHUNK_FRAMES,
+    };
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut frames = 0u64;
+    let mut chunk_peaks = Vec::new();
+    let mut chunk_peak = 0f32;
+    let mut chunk_len = 0;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(Error::ResetRequired) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Skip corrupted packets, like most players do
+            Err(Error::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let spec = *decoded.spec();
+        sample_rate.get_or_insert(spec.rate);
+        let channels = spec.channels.count().max(1);
+
+        let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        samples.copy_interleaved_ref(decoded);
+
+        for frame in samples.samples().chunks(channels) {
+            let peak = frame
+                .iter()
+                .fold(0f32, |peak, sample| peak.max(sample.abs()));
+            chunk_peak = chunk_peak.max(peak);
+            chunk_len += 1;
+            frames += 1;
+
+            if chunk_len == chunk_frames {
+                chunk_peaks.push(chunk_peak);
+                chunk_peak = 0.0;
+                chunk_len = 0;
+            }
+        }
+    }
+    if chunk_len > 0 {
+        chunk_peaks.push(chunk_peak);
+    }
+
+    let duration_ms = match sample_rate {
+        Some(rate) if rate > 0 => (frames * 1000 / rate as u64) as i64,
+        _ => 0,
+    };
+
+    Ok(Waveform {
+        duration_ms,
+        peaks: downsample(&chunk_peaks, PEAK_COUNT),
+    })
+}
+
+/// Folds `peaks` into at most `count` buckets, keeping the maximum of each bucket.
+fn downsample(peaks: &[f32], count: usize) -> Vec<u8> {
+    let buckets = count.min(peaks.len());
+
+    (0..buckets)
+        .map(|i| {
+            let bucket = &peaks[i * peaks.len() / buckets..(i + 1) * peaks.len() / buckets];
+            let peak = bucket.iter().fold(0f32, |a, &b| a.max(b));
+            (peak.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Renders a waveform as a mirrored bar graph, filled with `currentColor`.
+pub(crate) fn render_svg(waveform: &Waveform) -> String {
+    let width = waveform.peaks.len() * SVG_BAR_WIDTH;
+    let center = SVG_HEIGHT as f32 / 2.0;
+
+    let mut path = String::new();
+    for (i, &peak) in waveform.peaks.iter().enumerate() {
+        // Keep silent parts visible as a thin line
+        let half = (peak as f32 / 255.0 * center).max(0.5);
+        path.push_str(&format!(
+            "M{x} {top:.1}h{w}v{h:.1}h-{w}z",
+            x = i * SVG_BAR_WIDTH,
+            top = center - half,
+            w = SVG_BAR_WIDTH - 1,
+            h = half * 2.0,
+        ));
+    }
+
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {width} {SVG_HEIGHT}\" \
+         preserveAspectRatio=\"none\"><path fill=\"currentColor\" d=\"{path}\"/></svg>"
+    )
+}
+
+/// Renders a waveform as the same bar graph as [`render_svg`], black on transparent.
+pub(crate) fn render_png(waveform: &Waveform) -> Vec<u8> {
+    let width = (waveform.peaks.len() * SVG_BAR_WIDTH).max(1);
+    let center = SVG_HEIGHT as f32 / 2.0;
+
+    // Grayscale with alpha, only the alpha channel is drawn
+    let mut pixels = vec![0u8; width * SVG_HEIGHT * 2];
+    for (i, &peak) in waveform.peaks.iter().enumerate() {
+        let half = (peak as f32 / 255.0 * center).max(0.5);
+        let top = (center - half).round() as usize;
+        let bottom = ((center + half).round() as usize).clamp(top + 1, SVG_HEIGHT);
+        for y in top..bottom {
+            for x in i * SVG_BAR_WIDTH..i * SVG_BAR_WIDTH + SVG_BAR_WIDTH - 1 {
+                pixels[(y * width + x) * 2 + 1] = 255;
+            }
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width as u32, SVG_HEIGHT as u32);
+    encoder.set_color(png::ColorType::GrayscaleAlpha);
+    encoder.set_depth(png::BitDepth::Eight);
+    // Writing to a `Vec` with the declared size can't fail
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&pixels).unwrap();
+    writer.finish().unwrap();
+    png
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waveform(peaks: Vec<u8>) -> Waveform {
+        Waveform {
+            duration_ms: 1000,
+            peaks,
+        }
+    }
+
+    #[test]
+    fn downsamples_to_bucket_maxima() {
+        let peaks = [0.1, 0.5, 0.2, 1.0, 0.0, 0.25];
+        assert_eq!(downsample(&peaks, 3), [128, 255, 64]);
+        assert_eq!(downsample(&peaks, 2), [128, 255]);
+        // Short sounds keep one bucket per peak
+        assert_eq!(downsample(&peaks[..2], 100), [26, 128]);
+        assert_eq!(downsample(&[], 100), Vec::<u8>::new());
+        assert_eq!(downsample(&[-1.0, 2.0], 2), [0, 255]);
+    }
+
+    #[test]
+    fn renders_svg_bars() {
+        let svg = render_svg(&waveform(vec![0, 255]));
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 4 32\""));
+        // Silence stays visible as a thin line, full peaks span the height
+        assert!(
+            svg.contains("d=\"M0 15.5h1v1.0h-1zM2 0.0h1v32.0h-1z\""),
+            "{svg}"
+        );
+    }
+
+    #[test]
+    fn renders_png_bars() {
+        let png = render_png(&waveform(vec![0, 255, 128]));
+        let decoder = png::Decoder::new(png.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (6, 32));
+        assert_eq!(info.color_type, png::ColorType::GrayscaleAlpha);
+
+        let alpha = |x: usize, y: usize| pixels[(y * 6 + x) * 2 + 1];
+        let column = |x| (0..32).filter(|&y| alpha(x, y) == 255).count();
+        assert_eq!([column(0), column(2), column(4)], [1, 32, 16]);
+        // The gaps between bars stay transparent
+        assert_eq!([column(1), column(3), column(5)], [0, 0, 0]);
+    }
+}